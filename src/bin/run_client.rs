@@ -1,9 +1,14 @@
+use std::ops::Bound;
 use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use bytes::Bytes;
 use clap::{Parser, Subcommand};
 
 use client::Client;
+use kv_db::cmd::{Scan, Ttl};
+use kv_db::engine::{Expected, SetCondition};
+use kv_db::frame::FrameErrorKind;
 use kv_db::{client, Error, DEFAULT_PORT};
 
 #[derive(Parser, Debug)]
@@ -24,21 +29,300 @@ enum Command {
     Ping {},
     Get {
         key: String,
+        /// Milliseconds since the unix epoch to read the value as of
+        #[clap(long)]
+        at: Option<u64>,
     },
     Set {
         key: String,
         #[clap(parse(from_str = bytes_from_str))]
         value: Bytes,
+        /// Seconds after which the key expires
+        #[clap(long, conflicts_with = "px")]
+        ex: Option<u64>,
+        /// Milliseconds after which the key expires
+        #[clap(long)]
+        px: Option<u64>,
+        /// Only set the key if it doesn't exist yet
+        #[clap(long, conflicts_with = "xx")]
+        nx: bool,
+        /// Only set the key if it already exists
+        #[clap(long)]
+        xx: bool,
     },
     Delete {
         key: String,
     },
+    /// Lists the keys page by page, until the whole keyspace is covered
+    Scan {
+        /// Glob-style pattern the keys have to match
+        #[clap(long = "match")]
+        pattern: Option<String>,
+        /// Amount of keys looked at per page
+        #[clap(long)]
+        count: Option<usize>,
+        /// Milliseconds since the unix epoch to scan the keys as of
+        #[clap(long)]
+        at: Option<u64>,
+        /// Scan all the pages as of the time the first one is scanned
+        #[clap(long, conflicts_with = "at")]
+        snapshot: bool,
+    },
+    /// Lists all the keys matching the glob-style pattern at once
+    Keys {
+        pattern: String,
+    },
+    /// Counts how many of the keys exist
+    Exists {
+        #[clap(required = true)]
+        keys: Vec<String>,
+    },
+    /// Counts all the keys
+    Dbsize {},
+    /// Makes the key expire in the given amount of seconds
+    Expire {
+        key: String,
+        seconds: u64,
+    },
+    /// Shows how many seconds are left until the key expires
+    Ttl {
+        key: String,
+    },
+    /// Makes the key never expire
+    Persist {
+        key: String,
+    },
+    /// Sets the key only if it doesn't exist yet
+    Setnx {
+        key: String,
+        #[clap(parse(from_str = bytes_from_str))]
+        value: Bytes,
+    },
+    /// Sets the key only if it already exists
+    Setxx {
+        key: String,
+        #[clap(parse(from_str = bytes_from_str))]
+        value: Bytes,
+    },
+    /// Sets the key and shows its previous value
+    Getset {
+        key: String,
+        #[clap(parse(from_str = bytes_from_str))]
+        value: Bytes,
+    },
+    /// Sets the key only if it's still at the expected value or version
+    Cas {
+        key: String,
+        #[clap(parse(from_str = bytes_from_str))]
+        value: Bytes,
+        /// Value the key is expected to have
+        #[clap(long = "if-value", parse(from_str = bytes_from_str), required_unless_present = "if-version")]
+        if_value: Option<Bytes>,
+        /// Version the key is expected to be at
+        #[clap(long = "if-version", conflicts_with = "if-value")]
+        if_version: Option<u64>,
+    },
+    /// Shows the version of the key's current value
+    Version {
+        key: String,
+    },
+    /// Increments the integer value of the key by one
+    Incr {
+        key: String,
+    },
+    /// Decrements the integer value of the key by one
+    Decr {
+        key: String,
+    },
+    /// Adds the delta, which may be negative, to the integer value of the key
+    Incrby {
+        key: String,
+        #[clap(allow_hyphen_values = true)]
+        delta: i64,
+    },
+    /// Shows the values of all the keys
+    Mget {
+        #[clap(required = true)]
+        keys: Vec<String>,
+    },
+    /// Sets all the keys at once, given as key value pairs
+    Mset {
+        #[clap(required = true)]
+        pairs: Vec<String>,
+    },
+    /// Sets the fields of the hash, given as field value pairs
+    Hset {
+        key: String,
+        #[clap(required = true)]
+        fields: Vec<String>,
+    },
+    /// Shows the value of the hash field
+    Hget {
+        key: String,
+        field: String,
+    },
+    /// Removes the fields from the hash
+    Hdel {
+        key: String,
+        #[clap(required = true)]
+        fields: Vec<String>,
+    },
+    /// Shows all the fields of the hash
+    Hgetall {
+        key: String,
+    },
+    /// Pushes the values one by one to the left end of the list
+    Lpush {
+        key: String,
+        #[clap(required = true, parse(from_str = bytes_from_str))]
+        values: Vec<Bytes>,
+    },
+    /// Pushes the values one by one to the right end of the list
+    Rpush {
+        key: String,
+        #[clap(required = true, parse(from_str = bytes_from_str))]
+        values: Vec<Bytes>,
+    },
+    /// Pops the item at the left end of the list
+    Lpop {
+        key: String,
+    },
+    /// Pops the item at the right end of the list
+    Rpop {
+        key: String,
+    },
+    /// Shows the items of the list between the two positions, both included
+    Lrange {
+        key: String,
+        #[clap(allow_hyphen_values = true)]
+        start: i64,
+        #[clap(allow_hyphen_values = true)]
+        stop: i64,
+    },
+    /// Shows the length of the list
+    Llen {
+        key: String,
+    },
+    /// Pops the item at the left end of the first non-empty list, waiting for one
+    Blpop {
+        #[clap(required = true)]
+        keys: Vec<String>,
+        /// Seconds to wait for, forever if not given
+        #[clap(long)]
+        timeout: Option<f64>,
+    },
+    /// Adds the members to the set
+    Sadd {
+        key: String,
+        #[clap(required = true, parse(from_str = bytes_from_str))]
+        members: Vec<Bytes>,
+    },
+    /// Removes the members from the set
+    Srem {
+        key: String,
+        #[clap(required = true, parse(from_str = bytes_from_str))]
+        members: Vec<Bytes>,
+    },
+    /// Shows all the members of the set
+    Smembers {
+        key: String,
+    },
+    /// Shows whether the member is in the set
+    Sismember {
+        key: String,
+        #[clap(parse(from_str = bytes_from_str))]
+        member: Bytes,
+    },
+    /// Shows the members found in every one of the sets
+    Sinter {
+        #[clap(required = true)]
+        keys: Vec<String>,
+    },
+    /// Adds the members to the sorted set, given as score member pairs
+    Zadd {
+        key: String,
+        #[clap(required = true, allow_hyphen_values = true)]
+        scores: Vec<String>,
+    },
+    /// Shows the members of the sorted set between the two ranks, both included
+    Zrange {
+        key: String,
+        #[clap(allow_hyphen_values = true)]
+        start: i64,
+        #[clap(allow_hyphen_values = true)]
+        stop: i64,
+    },
+    /// Shows the members of the sorted set with scores between the two bounds,
+    /// which are exclusive if prefixed with `(`
+    Zrangebyscore {
+        key: String,
+        #[clap(allow_hyphen_values = true, parse(try_from_str = score_bound_from_str))]
+        min: Bound<f64>,
+        #[clap(allow_hyphen_values = true, parse(try_from_str = score_bound_from_str))]
+        max: Bound<f64>,
+    },
+    /// Shows the rank of the member in score order
+    Zrank {
+        key: String,
+        #[clap(parse(from_str = bytes_from_str))]
+        member: Bytes,
+    },
+    /// Adds the delta, which may be negative, to the score of the member
+    Zincrby {
+        key: String,
+        #[clap(allow_hyphen_values = true)]
+        delta: f64,
+        #[clap(parse(from_str = bytes_from_str))]
+        member: Bytes,
+    },
+    /// Sets the value, given as JSON, at the path of the JSON document
+    #[clap(name = "json.set")]
+    JsonSet {
+        key: String,
+        path: String,
+        value: String,
+    },
+    /// Shows the value at the path of the JSON document
+    #[clap(name = "json.get")]
+    JsonGet {
+        key: String,
+        #[clap(default_value = "$")]
+        path: String,
+    },
+    /// Removes the value at the path of the JSON document
+    #[clap(name = "json.del")]
+    JsonDel {
+        key: String,
+        #[clap(default_value = "$")]
+        path: String,
+    },
+    /// Adds the delta, which may be negative, to the number at the path of the JSON document
+    #[clap(name = "json.numincrby")]
+    JsonNumincrby {
+        key: String,
+        path: String,
+        #[clap(allow_hyphen_values = true)]
+        delta: String,
+    },
+    /// Lists the keys whose field covered by the secondary index holds the value,
+    /// given as JSON, e.g. `'"ada"'` for a string
+    Find {
+        index: String,
+        value: String,
+    },
 }
 
 fn bytes_from_str(src: &str) -> Bytes {
     Bytes::from(src.to_string())
 }
 
+fn score_bound_from_str(src: &str) -> Result<Bound<f64>, std::num::ParseFloatError> {
+    match src.strip_prefix('(') {
+        Some(score) => Ok(Bound::Excluded(score.parse()?)),
+        None => Ok(Bound::Included(src.parse()?)),
+    }
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Error> {
     let mut client = Client::connect(&format!("127.0.0.1:{}", DEFAULT_PORT)).await?;
@@ -48,18 +332,354 @@ async fn main() -> Result<(), Error> {
             let ping_res = client.ping().await?;
             println!("{}", ping_res);
         }
-        Command::Get { key } => {
-            let get_res = client.get(key.as_str()).await?;
-            println!("GET {}: {}", key, get_res);
+        Command::Get { key, at } => {
+            let value = match at {
+                Some(at) => client.get_at(&key, at).await?,
+                None => client.get(&key).await?,
+            };
+
+            match value {
+                Some(value) => println!("GET {}: {}", key, String::from_utf8_lossy(&value)),
+                None => println!("GET {}: Error: {}", key, FrameErrorKind::NotFound),
+            }
         }
-        Command::Set { key, value } => {
-            let set_res = client.set(key.as_str(), value).await?;
-            println!("SET {}", set_res);
+        Command::Set {
+            key,
+            value,
+            ex,
+            px,
+            nx,
+            xx,
+        } => {
+            let expire = match (ex, px) {
+                (Some(seconds), _) => Some(Duration::from_secs(seconds)),
+                (_, Some(millis)) => Some(Duration::from_millis(millis)),
+                _ => None,
+            };
+
+            let condition = match (nx, xx) {
+                (true, _) => SetCondition::IfAbsent,
+                (_, true) => SetCondition::IfPresent,
+                _ => SetCondition::Always,
+            };
+
+            match client
+                .set_with(key.as_str(), value, expire, condition)
+                .await?
+            {
+                Some(set_res) => println!("SET {}", set_res),
+                None => println!("SET not performed"),
+            }
         }
         Command::Delete { key } => {
             let delete_res = client.delete(key.as_str()).await?;
             println!("DELETE {}", delete_res);
         }
+        Command::Scan {
+            pattern,
+            count,
+            at,
+            snapshot,
+        } => {
+            let mut cursor = Scan::START_CURSOR.to_string();
+
+            let at = match (at, snapshot) {
+                (Some(at), _) => Some(at),
+                (None, true) => {
+                    Some(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64)
+                }
+                (None, false) => None,
+            };
+
+            loop {
+                let (next_cursor, keys) = client
+                    .scan_at(&cursor, pattern.as_deref(), count, at)
+                    .await?;
+
+                for key in keys {
+                    println!("{}", key);
+                }
+
+                if next_cursor == Scan::START_CURSOR {
+                    break;
+                }
+
+                cursor = next_cursor;
+            }
+        }
+        Command::Keys { pattern } => {
+            for key in client.keys(&pattern).await? {
+                println!("{}", key);
+            }
+        }
+        Command::Exists { keys } => {
+            let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+            let exists_res = client.exists(&keys).await?;
+            println!("EXISTS {}", exists_res);
+        }
+        Command::Dbsize {} => {
+            let dbsize_res = client.dbsize().await?;
+            println!("DBSIZE {}", dbsize_res);
+        }
+        Command::Expire { key, seconds } => {
+            let expire_res = client.expire(&key, seconds).await?;
+            println!("EXPIRE {}", expire_res as u8);
+        }
+        Command::Ttl { key } => match client.ttl(&key).await? {
+            Ttl::MISSING => println!("TTL {}: Error: {}", key, FrameErrorKind::NotFound),
+            Ttl::PERSISTENT => println!("TTL {}: no expiry", key),
+            seconds => println!("TTL {}: {}", key, seconds),
+        },
+        Command::Persist { key } => {
+            let persist_res = client.persist(&key).await?;
+            println!("PERSIST {}", persist_res as u8);
+        }
+        Command::Setnx { key, value } => {
+            let setnx_res = client.setnx(&key, value).await?;
+            println!("SETNX {}", setnx_res as u8);
+        }
+        Command::Setxx { key, value } => {
+            let setxx_res = client.setxx(&key, value).await?;
+            println!("SETXX {}", setxx_res as u8);
+        }
+        Command::Getset { key, value } => match client.getset(&key, value).await? {
+            Some(previous) => println!("GETSET {}: {}", key, String::from_utf8_lossy(&previous)),
+            None => println!("GETSET {}: Error: {}", key, FrameErrorKind::NotFound),
+        },
+        Command::Cas {
+            key,
+            value,
+            if_value,
+            if_version,
+        } => {
+            let expected = match (if_value, if_version) {
+                (Some(bytes), _) => Expected::Value(bytes),
+                (_, Some(version)) => Expected::Version(version),
+                (None, None) => unreachable!("clap requires one of them"),
+            };
+
+            let cas_res = client.cas(&key, value, expected).await?;
+            println!("CAS {}", cas_res as u8);
+        }
+        Command::Version { key } => match client.version(&key).await? {
+            Some(version) => println!("VERSION {}: {}", key, version),
+            None => println!("VERSION {}: Error: {}", key, FrameErrorKind::NotFound),
+        },
+        Command::Incr { key } => {
+            let incr_res = client.incr(&key).await?;
+            println!("INCR {}: {}", key, incr_res);
+        }
+        Command::Decr { key } => {
+            let decr_res = client.decr(&key).await?;
+            println!("DECR {}: {}", key, decr_res);
+        }
+        Command::Incrby { key, delta } => {
+            let incrby_res = client.incr_by(&key, delta).await?;
+            println!("INCRBY {}: {}", key, incrby_res);
+        }
+        Command::Mget { keys } => {
+            let key_refs: Vec<&str> = keys.iter().map(String::as_str).collect();
+            let values = client.mget(&key_refs).await?;
+
+            for (key, value) in keys.iter().zip(values) {
+                match value {
+                    Some(value) => println!("GET {}: {}", key, String::from_utf8_lossy(&value)),
+                    None => println!("GET {}: Error: {}", key, FrameErrorKind::NotFound),
+                }
+            }
+        }
+        Command::Mset { pairs } => {
+            if pairs.len() % 2 != 0 {
+                return Err("every key needs a value".into());
+            }
+
+            let pairs: Vec<(&str, Bytes)> = pairs
+                .chunks(2)
+                .map(|pair| (pair[0].as_str(), bytes_from_str(&pair[1])))
+                .collect();
+
+            let mset_res = client.mset(&pairs).await?;
+            println!("MSET {}", mset_res);
+        }
+        Command::Hset { key, fields } => {
+            if fields.len() % 2 != 0 {
+                return Err("every field needs a value".into());
+            }
+
+            let fields: Vec<(&str, Bytes)> = fields
+                .chunks(2)
+                .map(|pair| (pair[0].as_str(), bytes_from_str(&pair[1])))
+                .collect();
+
+            let hset_res = client.hset(&key, &fields).await?;
+            println!("HSET {}: {}", key, hset_res);
+        }
+        Command::Hget { key, field } => match client.hget(&key, &field).await? {
+            Some(value) => println!(
+                "HGET {} {}: {}",
+                key,
+                field,
+                String::from_utf8_lossy(&value)
+            ),
+            None => println!(
+                "HGET {} {}: Error: {}",
+                key,
+                field,
+                FrameErrorKind::NotFound
+            ),
+        },
+        Command::Hdel { key, fields } => {
+            let field_refs: Vec<&str> = fields.iter().map(String::as_str).collect();
+
+            let hdel_res = client.hdel(&key, &field_refs).await?;
+            println!("HDEL {}: {}", key, hdel_res);
+        }
+        Command::Hgetall { key } => {
+            for (field, value) in client.hgetall(&key).await? {
+                println!(
+                    "HGET {} {}: {}",
+                    key,
+                    field,
+                    String::from_utf8_lossy(&value)
+                );
+            }
+        }
+        Command::Lpush { key, values } => {
+            let lpush_res = client.lpush(&key, &values).await?;
+            println!("LPUSH {}: {}", key, lpush_res);
+        }
+        Command::Rpush { key, values } => {
+            let rpush_res = client.rpush(&key, &values).await?;
+            println!("RPUSH {}: {}", key, rpush_res);
+        }
+        Command::Lpop { key } => match client.lpop(&key).await? {
+            Some(item) => println!("LPOP {}: {}", key, String::from_utf8_lossy(&item)),
+            None => println!("LPOP {}: Error: {}", key, FrameErrorKind::NotFound),
+        },
+        Command::Rpop { key } => match client.rpop(&key).await? {
+            Some(item) => println!("RPOP {}: {}", key, String::from_utf8_lossy(&item)),
+            None => println!("RPOP {}: Error: {}", key, FrameErrorKind::NotFound),
+        },
+        Command::Lrange { key, start, stop } => {
+            for item in client.lrange(&key, start, stop).await? {
+                println!("{}", String::from_utf8_lossy(&item));
+            }
+        }
+        Command::Llen { key } => {
+            let llen_res = client.llen(&key).await?;
+            println!("LLEN {}: {}", key, llen_res);
+        }
+        Command::Blpop { keys, timeout } => {
+            let key_refs: Vec<&str> = keys.iter().map(String::as_str).collect();
+            let timeout = timeout
+                .map(Duration::try_from_secs_f64)
+                .transpose()?
+                .filter(|timeout| !timeout.is_zero());
+
+            match client.blpop(&key_refs, timeout).await? {
+                Some((key, item)) => println!("BLPOP {}: {}", key, String::from_utf8_lossy(&item)),
+                None => println!("BLPOP: timed out"),
+            }
+        }
+        Command::Sadd { key, members } => {
+            let sadd_res = client.sadd(&key, &members).await?;
+            println!("SADD {}: {}", key, sadd_res);
+        }
+        Command::Srem { key, members } => {
+            let srem_res = client.srem(&key, &members).await?;
+            println!("SREM {}: {}", key, srem_res);
+        }
+        Command::Smembers { key } => {
+            for member in client.smembers(&key).await? {
+                println!("{}", String::from_utf8_lossy(&member));
+            }
+        }
+        Command::Sismember { key, member } => {
+            let sismember_res = client.sismember(&key, member).await?;
+            println!("SISMEMBER {}: {}", key, sismember_res as u8);
+        }
+        Command::Sinter { keys } => {
+            let key_refs: Vec<&str> = keys.iter().map(String::as_str).collect();
+
+            for member in client.sinter(&key_refs).await? {
+                println!("{}", String::from_utf8_lossy(&member));
+            }
+        }
+        Command::Zadd { key, scores } => {
+            if scores.len() % 2 != 0 {
+                return Err("every score needs a member".into());
+            }
+
+            let scores = scores
+                .chunks(2)
+                .map(|pair| Ok((pair[0].parse()?, bytes_from_str(&pair[1]))))
+                .collect::<Result<Vec<(f64, Bytes)>, Error>>()?;
+
+            let zadd_res = client.zadd(&key, &scores).await?;
+            println!("ZADD {}: {}", key, zadd_res);
+        }
+        Command::Zrange { key, start, stop } => {
+            for (member, score) in client.zrange(&key, start, stop).await? {
+                println!("{}: {}", String::from_utf8_lossy(&member), score);
+            }
+        }
+        Command::Zrangebyscore { key, min, max } => {
+            for (member, score) in client.zrangebyscore(&key, min, max).await? {
+                println!("{}: {}", String::from_utf8_lossy(&member), score);
+            }
+        }
+        Command::Zrank { key, member } => match client.zrank(&key, member).await? {
+            Some(rank) => println!("ZRANK {}: {}", key, rank),
+            None => println!("ZRANK {}: Error: {}", key, FrameErrorKind::NotFound),
+        },
+        Command::Zincrby { key, delta, member } => {
+            let zincrby_res = client.zincrby(&key, delta, member).await?;
+            println!("ZINCRBY {}: {}", key, zincrby_res);
+        }
+        Command::JsonSet { key, path, value } => {
+            let value = serde_json::from_str(&value)?;
+
+            match client.json_set(&key, &path, value).await? {
+                true => println!("JSON.SET {} {}: OK", key, path),
+                false => println!(
+                    "JSON.SET {} {}: Error: {}",
+                    key,
+                    path,
+                    FrameErrorKind::NotFound
+                ),
+            }
+        }
+        Command::JsonGet { key, path } => match client.json_get(&key, &path).await? {
+            Some(value) => println!("JSON.GET {} {}: {}", key, path, value),
+            None => println!(
+                "JSON.GET {} {}: Error: {}",
+                key,
+                path,
+                FrameErrorKind::NotFound
+            ),
+        },
+        Command::JsonDel { key, path } => {
+            let json_del_res = client.json_del(&key, &path).await?;
+            println!("JSON.DEL {} {}: {}", key, path, json_del_res);
+        }
+        Command::JsonNumincrby { key, path, delta } => {
+            match client.json_numincrby(&key, &path, delta.parse()?).await? {
+                Some(number) => println!("JSON.NUMINCRBY {} {}: {}", key, path, number),
+                None => println!(
+                    "JSON.NUMINCRBY {} {}: Error: {}",
+                    key,
+                    path,
+                    FrameErrorKind::NotFound
+                ),
+            }
+        }
+        Command::Find { index, value } => {
+            let value = serde_json::from_str(&value)?;
+
+            for key in client.find(&index, value).await? {
+                println!("{}", key);
+            }
+        }
     }
 
     Ok(())
@@ -1,14 +1,131 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{ArgEnum, Parser};
 use tokio::net::TcpListener;
 use tokio::signal;
 
+use kv_db::db::{Config, DbHolder, Durability, IndexDefinition};
+use kv_db::engine::{JsonPath, MemoryEngine, StorageEngine};
+use kv_db::lsm::{self, LsmEngine};
 use kv_db::DEFAULT_PORT;
 use kv_db::{server, Error};
 
+#[derive(Parser, Debug)]
+#[clap(name = "kv-db-server")]
+struct Cli {
+    /// Storage engine to serve the data from
+    #[clap(long, arg_enum, default_value = "log")]
+    engine: EngineKind,
+
+    /// Directory holding the data files of the engine
+    #[clap(long, default_value = "store")]
+    data_dir: PathBuf,
+
+    /// Size in bytes after which the active segment is closed
+    #[clap(long, default_value_t = Config::default().max_segment_size)]
+    max_segment_size: u64,
+
+    /// Size in bytes after which the memtable of the LSM engine is flushed
+    #[clap(long, default_value_t = lsm::Config::default().memtable_size)]
+    memtable_size: usize,
+
+    /// When writes are synced to disk
+    #[clap(long, arg_enum, default_value = "interval")]
+    fsync: FsyncPolicy,
+
+    /// Period of background syncing for the `interval` policy
+    #[clap(long, default_value_t = 1000)]
+    fsync_interval_ms: u64,
+
+    /// Serve reads from memory-mapped closed segments
+    #[clap(long)]
+    mmap: bool,
+
+    /// Seconds past values can still be read as of, zero turns history off
+    #[clap(long, default_value_t = Config::default().history_retention.as_secs())]
+    history_retention_secs: u64,
+
+    /// Secondary index over a field of the JSON documents held by the keys
+    /// matching the pattern, e.g. `--index by_user 'order:*' user_id`
+    #[clap(
+        long,
+        number_of_values = 3,
+        multiple_occurrences = true,
+        value_names = &["NAME", "PATTERN", "FIELD"]
+    )]
+    index: Vec<String>,
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+enum EngineKind {
+    /// Append-only log segments on disk
+    Log,
+    /// Log-structured merge tree of sorted tables on disk
+    Lsm,
+    /// Nothing is persisted
+    Memory,
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+enum FsyncPolicy {
+    Always,
+    Interval,
+    Never,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
+    let cli = Cli::parse();
+
+    let durability = match cli.fsync {
+        FsyncPolicy::Always => Durability::Always,
+        FsyncPolicy::Interval => Durability::Interval(Duration::from_millis(cli.fsync_interval_ms)),
+        FsyncPolicy::Never => Durability::Never,
+    };
+
+    let engine: Arc<dyn StorageEngine> = match cli.engine {
+        EngineKind::Log => {
+            let indexes = cli
+                .index
+                .chunks(3)
+                .map(|definition| {
+                    Ok(IndexDefinition {
+                        name: definition[0].clone(),
+                        pattern: definition[1].clone(),
+                        field: JsonPath::parse(&definition[2])?,
+                    })
+                })
+                .collect::<Result<_, Error>>()?;
+
+            let config = Config {
+                dir: cli.data_dir,
+                max_segment_size: cli.max_segment_size,
+                durability,
+                mmap_reads: cli.mmap,
+                history_retention: Duration::from_secs(cli.history_retention_secs),
+                indexes,
+            };
+
+            Arc::new(DbHolder::new(config)?.db())
+        }
+        EngineKind::Lsm => {
+            let config = lsm::Config {
+                dir: cli.data_dir,
+                memtable_size: cli.memtable_size,
+                durability,
+                ..lsm::Config::default()
+            };
+
+            Arc::new(LsmEngine::open(config)?)
+        }
+        EngineKind::Memory => Arc::new(MemoryEngine::new()),
+    };
+
     let listener = TcpListener::bind(&format!("127.0.0.1:{}", DEFAULT_PORT)).await?;
 
-    server::run(listener, signal::ctrl_c()).await;
+    server::run(listener, engine, signal::ctrl_c()).await;
 
     Ok(())
 }
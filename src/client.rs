@@ -1,9 +1,19 @@
+use std::ops::Bound;
+use std::time::Duration;
+
 use bytes::Bytes;
+use serde_json::{Number, Value as Json};
 use tokio::net::{TcpStream, ToSocketAddrs};
 
-use crate::cmd::{Delete, Get, Ping, Set};
+use crate::cmd::{
+    BLPop, Cas, DbSize, Delete, Discard, Exec, Exists, Expire, Find, Get, GetSet, HDel, HGet,
+    HGetAll, HSet, IncrBy, JsonDel, JsonGet, JsonNumIncrBy, JsonSet, Keys, LLen, LRange, MGet,
+    MSet, Multi, Persist, Ping, Pop, Push, SAdd, SInter, SIsMember, SMembers, SRem, Scan, Set,
+    SetIf, Ttl, Version, Watch, ZAdd, ZIncrBy, ZRange, ZRangeByScore, ZRank,
+};
 use crate::connection::Connection;
-use crate::frame::Frame;
+use crate::engine::{Expected, JsonPath, ListEnd, SetCondition};
+use crate::frame::{Frame, FrameErrorKind};
 
 pub struct Client {
     connection: Connection,
@@ -28,22 +38,30 @@ impl Client {
         Err("Internal error".into())
     }
 
-    // NOTE: should return bulk as bytes instead of to-string converting?
-    pub async fn get(&mut self, key: &str) -> Result<String, crate::Error> {
+    /// Value is returned as is, `None` stands for a missing key.
+    pub async fn get(&mut self, key: &str) -> Result<Option<Bytes>, crate::Error> {
         let frame = Get::new(key).into_frame();
         self.connection.write_frame(&frame).await?;
 
         match self.read_response().await? {
-            Frame::Bulk(bytes) => {
-                let string = String::from_utf8(bytes.to_vec())?;
-                return Ok(string);
-            }
-            Frame::Simple(string) => {
-                return Ok(string);
-            }
-            Frame::Error(error_kind) => {
-                return Ok(format!("Error: {}", error_kind));
-            }
+            Frame::Bulk(bytes) => Ok(Some(bytes)),
+            Frame::Simple(string) => Ok(Some(Bytes::from(string.into_bytes()))),
+            Frame::Error(FrameErrorKind::NotFound) => Ok(None),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    /// Value the key had at the point in time, in milliseconds since the unix epoch.
+    /// It's an error if the server doesn't keep history that far back.
+    pub async fn get_at(&mut self, key: &str, at: u64) -> Result<Option<Bytes>, crate::Error> {
+        let frame = Get::new(key).at(Some(at)).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(bytes) => Ok(Some(bytes)),
+            Frame::Error(FrameErrorKind::NotFound) => Ok(None),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
             _ => Err("Internal error".into()),
         }
     }
@@ -59,6 +77,29 @@ impl Client {
         Err("Internal error".into())
     }
 
+    /// Sets the value with an optional time to live, `None` is returned
+    /// if the condition kept the value from being set.
+    pub async fn set_with(
+        &mut self,
+        key: &str,
+        value: Bytes,
+        expire: Option<Duration>,
+        condition: SetCondition,
+    ) -> Result<Option<String>, crate::Error> {
+        let frame = Set::new(key, value)
+            .expire(expire)
+            .condition(condition)
+            .into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(string) => Ok(Some(string)),
+            Frame::Null => Ok(None),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
     pub async fn delete(&mut self, key: &str) -> Result<String, crate::Error> {
         let frame = Delete::new(key).into_frame();
         self.connection.write_frame(&frame).await?;
@@ -70,6 +111,643 @@ impl Client {
         }
     }
 
+    /// Fetches the page of keys following the cursor, returning the cursor of the next
+    /// page along with the keys. Iteration starts and ends with `Scan::START_CURSOR`.
+    pub async fn scan(
+        &mut self,
+        cursor: &str,
+        pattern: Option<&str>,
+        count: Option<usize>,
+    ) -> Result<(String, Vec<String>), crate::Error> {
+        self.scan_at(cursor, pattern, count, None).await
+    }
+
+    /// Same as `scan`, but as of the point in time if given. Passing the same point
+    /// in time for all the pages makes them add up to a consistent snapshot.
+    pub async fn scan_at(
+        &mut self,
+        cursor: &str,
+        pattern: Option<&str>,
+        count: Option<usize>,
+        at: Option<u64>,
+    ) -> Result<(String, Vec<String>), crate::Error> {
+        let frame = Scan::new(cursor, pattern.map(str::to_string), count)
+            .at(at)
+            .into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        let mut strings = match self.read_response().await? {
+            Frame::Array(parts) => into_strings(parts)?,
+            Frame::Error(error_kind) => return Err(format!("Error: {}", error_kind).into()),
+            _ => return Err("Internal error".into()),
+        };
+
+        if strings.is_empty() {
+            return Err("Internal error".into());
+        }
+
+        let next_cursor = strings.remove(0);
+
+        Ok((next_cursor, strings))
+    }
+
+    pub async fn keys(&mut self, pattern: &str) -> Result<Vec<String>, crate::Error> {
+        let frame = Keys::new(pattern).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Array(parts) => into_strings(parts),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    /// Amount of the given keys that exist.
+    pub async fn exists(&mut self, keys: &[&str]) -> Result<u64, crate::Error> {
+        let keys = keys.iter().map(|key| key.to_string()).collect();
+
+        let frame = Exists::new(keys).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        Ok(self.read_integer().await? as u64)
+    }
+
+    /// Amount of keys in the database.
+    pub async fn dbsize(&mut self) -> Result<u64, crate::Error> {
+        let frame = DbSize::new().into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        Ok(self.read_integer().await? as u64)
+    }
+
+    /// Makes the key expire in `seconds`, returns `false` if there is no such key.
+    pub async fn expire(&mut self, key: &str, seconds: u64) -> Result<bool, crate::Error> {
+        let frame = Expire::new(key, seconds).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        Ok(self.read_integer().await? == 1)
+    }
+
+    /// Seconds left until the key expires, or either `Ttl::MISSING`
+    /// or `Ttl::PERSISTENT`.
+    pub async fn ttl(&mut self, key: &str) -> Result<i64, crate::Error> {
+        let frame = Ttl::new(key).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        self.read_integer().await
+    }
+
+    /// Makes the key never expire, returns `false` if it had no timeout to remove.
+    pub async fn persist(&mut self, key: &str) -> Result<bool, crate::Error> {
+        let frame = Persist::new(key).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        Ok(self.read_integer().await? == 1)
+    }
+
+    /// Sets the value unless the key exists, returns `false` if it wasn't set.
+    pub async fn setnx(&mut self, key: &str, value: Bytes) -> Result<bool, crate::Error> {
+        let frame = SetIf::new(key, value, SetCondition::IfAbsent).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        Ok(self.read_integer().await? == 1)
+    }
+
+    /// Sets the value only if the key exists, returns `false` if it wasn't set.
+    pub async fn setxx(&mut self, key: &str, value: Bytes) -> Result<bool, crate::Error> {
+        let frame = SetIf::new(key, value, SetCondition::IfPresent).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        Ok(self.read_integer().await? == 1)
+    }
+
+    /// Sets the value, returning the previous one.
+    pub async fn getset(&mut self, key: &str, value: Bytes) -> Result<Option<Bytes>, crate::Error> {
+        let frame = GetSet::new(key, value).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(bytes) => Ok(Some(bytes)),
+            Frame::Null => Ok(None),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    /// Sets the value only if the current one is still the expected one,
+    /// returns `false` if it wasn't set.
+    pub async fn cas(
+        &mut self,
+        key: &str,
+        value: Bytes,
+        expected: Expected,
+    ) -> Result<bool, crate::Error> {
+        let frame = Cas::new(key, value, expected).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        Ok(self.read_integer().await? == 1)
+    }
+
+    /// Version of the current value, `None` stands for a missing key.
+    pub async fn version(&mut self, key: &str) -> Result<Option<u64>, crate::Error> {
+        let frame = Version::new(key).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(version) => Ok(Some(version as u64)),
+            Frame::Null => Ok(None),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    pub async fn incr(&mut self, key: &str) -> Result<i64, crate::Error> {
+        self.incr_by(key, 1).await
+    }
+
+    pub async fn decr(&mut self, key: &str) -> Result<i64, crate::Error> {
+        self.incr_by(key, -1).await
+    }
+
+    /// Adds `delta` to the integer value of the key, returning the result.
+    /// Missing key counts as 0, while a non-integer value is an error.
+    pub async fn incr_by(&mut self, key: &str, delta: i64) -> Result<i64, crate::Error> {
+        let frame = IncrBy::new(key, delta).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        self.read_integer().await
+    }
+
+    /// Values in the order of the keys, `None` standing for a missing key.
+    pub async fn mget(&mut self, keys: &[&str]) -> Result<Vec<Option<Bytes>>, crate::Error> {
+        let keys = keys.iter().map(|key| key.to_string()).collect();
+
+        let frame = MGet::new(keys).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        let parts = match self.read_response().await? {
+            Frame::Array(parts) => parts,
+            Frame::Error(error_kind) => return Err(format!("Error: {}", error_kind).into()),
+            _ => return Err("Internal error".into()),
+        };
+
+        parts
+            .into_iter()
+            .map(|frame| match frame {
+                Frame::Bulk(bytes) => Ok(Some(bytes)),
+                Frame::Null => Ok(None),
+                _ => Err("Internal error".into()),
+            })
+            .collect()
+    }
+
+    /// Sets all the pairs at once, either all of them are written or none.
+    pub async fn mset(&mut self, pairs: &[(&str, Bytes)]) -> Result<String, crate::Error> {
+        let pairs = pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect();
+
+        let frame = MSet::new(pairs).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(string) => Ok(string),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    /// Sets the fields of the hash, returning how many of them are new.
+    pub async fn hset(&mut self, key: &str, fields: &[(&str, Bytes)]) -> Result<u64, crate::Error> {
+        let fields = fields
+            .iter()
+            .map(|(field, value)| (field.to_string(), value.clone()))
+            .collect();
+
+        let frame = HSet::new(key, fields).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        Ok(self.read_integer().await? as u64)
+    }
+
+    /// Value of the hash field, `None` stands for a missing field or key.
+    pub async fn hget(&mut self, key: &str, field: &str) -> Result<Option<Bytes>, crate::Error> {
+        let frame = HGet::new(key, field).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(bytes) => Ok(Some(bytes)),
+            Frame::Null => Ok(None),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    /// Removes the fields from the hash, returning how many of them were there.
+    pub async fn hdel(&mut self, key: &str, fields: &[&str]) -> Result<u64, crate::Error> {
+        let fields = fields.iter().map(|field| field.to_string()).collect();
+
+        let frame = HDel::new(key, fields).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        Ok(self.read_integer().await? as u64)
+    }
+
+    /// All the fields of the hash along with their values, in field order.
+    pub async fn hgetall(&mut self, key: &str) -> Result<Vec<(String, Bytes)>, crate::Error> {
+        let frame = HGetAll::new(key).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        let parts = match self.read_response().await? {
+            Frame::Array(parts) => parts,
+            Frame::Error(error_kind) => return Err(format!("Error: {}", error_kind).into()),
+            _ => return Err("Internal error".into()),
+        };
+
+        let mut parts = parts.into_iter();
+        let mut fields = vec![];
+
+        while let Some(field) = parts.next() {
+            match (field, parts.next()) {
+                (Frame::Bulk(field), Some(Frame::Bulk(value))) => {
+                    fields.push((String::from_utf8(field.to_vec())?, value));
+                }
+                _ => return Err("Internal error".into()),
+            }
+        }
+
+        Ok(fields)
+    }
+
+    /// Pushes the values one by one to the left end of the list, returning its length.
+    pub async fn lpush(&mut self, key: &str, values: &[Bytes]) -> Result<u64, crate::Error> {
+        self.push(key, values, ListEnd::Left).await
+    }
+
+    /// Pushes the values one by one to the right end of the list, returning its length.
+    pub async fn rpush(&mut self, key: &str, values: &[Bytes]) -> Result<u64, crate::Error> {
+        self.push(key, values, ListEnd::Right).await
+    }
+
+    async fn push(
+        &mut self,
+        key: &str,
+        values: &[Bytes],
+        end: ListEnd,
+    ) -> Result<u64, crate::Error> {
+        let frame = Push::new(key, values.to_vec(), end).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        Ok(self.read_integer().await? as u64)
+    }
+
+    /// Item popped from the left end of the list, `None` stands for a missing key.
+    pub async fn lpop(&mut self, key: &str) -> Result<Option<Bytes>, crate::Error> {
+        self.pop(key, ListEnd::Left).await
+    }
+
+    /// Item popped from the right end of the list, `None` stands for a missing key.
+    pub async fn rpop(&mut self, key: &str) -> Result<Option<Bytes>, crate::Error> {
+        self.pop(key, ListEnd::Right).await
+    }
+
+    async fn pop(&mut self, key: &str, end: ListEnd) -> Result<Option<Bytes>, crate::Error> {
+        let frame = Pop::new(key, end).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(bytes) => Ok(Some(bytes)),
+            Frame::Null => Ok(None),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    /// Items between the two positions, both included. Negative positions count
+    /// from the right end.
+    pub async fn lrange(
+        &mut self,
+        key: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<Bytes>, crate::Error> {
+        let frame = LRange::new(key, start, stop).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        self.read_bulk_array().await
+    }
+
+    pub async fn llen(&mut self, key: &str) -> Result<u64, crate::Error> {
+        let frame = LLen::new(key).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        Ok(self.read_integer().await? as u64)
+    }
+
+    /// Pops from the left end of the first non-empty list, waiting for a push if all
+    /// of them are empty. Returns the key along with the item, or `None` if nothing
+    /// was pushed within the timeout. No timeout means waiting forever.
+    pub async fn blpop(
+        &mut self,
+        keys: &[&str],
+        timeout: Option<Duration>,
+    ) -> Result<Option<(String, Bytes)>, crate::Error> {
+        let keys = keys.iter().map(|key| key.to_string()).collect();
+
+        let frame = BLPop::new(keys, timeout).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Array(parts) => match <[Frame; 2]>::try_from(parts) {
+                Ok([Frame::Bulk(key), Frame::Bulk(item)]) => {
+                    Ok(Some((String::from_utf8(key.to_vec())?, item)))
+                }
+                _ => Err("Internal error".into()),
+            },
+            Frame::Null => Ok(None),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    /// Adds the members to the set, returning how many of them weren't there.
+    pub async fn sadd(&mut self, key: &str, members: &[Bytes]) -> Result<u64, crate::Error> {
+        let frame = SAdd::new(key, members.to_vec()).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        Ok(self.read_integer().await? as u64)
+    }
+
+    /// Removes the members from the set, returning how many of them were there.
+    pub async fn srem(&mut self, key: &str, members: &[Bytes]) -> Result<u64, crate::Error> {
+        let frame = SRem::new(key, members.to_vec()).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        Ok(self.read_integer().await? as u64)
+    }
+
+    /// All the members of the set, in member order.
+    pub async fn smembers(&mut self, key: &str) -> Result<Vec<Bytes>, crate::Error> {
+        let frame = SMembers::new(key).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        self.read_bulk_array().await
+    }
+
+    pub async fn sismember(&mut self, key: &str, member: Bytes) -> Result<bool, crate::Error> {
+        let frame = SIsMember::new(key, member).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        Ok(self.read_integer().await? == 1)
+    }
+
+    /// Members found in every one of the sets, in member order.
+    pub async fn sinter(&mut self, keys: &[&str]) -> Result<Vec<Bytes>, crate::Error> {
+        let keys = keys.iter().map(|key| key.to_string()).collect();
+
+        let frame = SInter::new(keys).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        self.read_bulk_array().await
+    }
+
+    /// Adds the members with their scores, or updates the scores of the ones already
+    /// there. Returns how many of the members have been added.
+    pub async fn zadd(&mut self, key: &str, scores: &[(f64, Bytes)]) -> Result<u64, crate::Error> {
+        let frame = ZAdd::new(key, scores.to_vec()).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        Ok(self.read_integer().await? as u64)
+    }
+
+    /// Members between the two ranks, both included, along with their scores.
+    /// Negative ranks count from the highest score.
+    pub async fn zrange(
+        &mut self,
+        key: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<(Bytes, f64)>, crate::Error> {
+        let frame = ZRange::new(key, start, stop, true).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        self.read_scored_members().await
+    }
+
+    /// Members with scores between the bounds, in score order, along with their scores.
+    pub async fn zrangebyscore(
+        &mut self,
+        key: &str,
+        min: Bound<f64>,
+        max: Bound<f64>,
+    ) -> Result<Vec<(Bytes, f64)>, crate::Error> {
+        let frame = ZRangeByScore::new(key, min, max, true).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        self.read_scored_members().await
+    }
+
+    /// Rank of the member in score order, `None` stands for a missing member or key.
+    pub async fn zrank(&mut self, key: &str, member: Bytes) -> Result<Option<u64>, crate::Error> {
+        let frame = ZRank::new(key, member).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Integer(rank) => Ok(Some(rank as u64)),
+            Frame::Null => Ok(None),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    /// Adds the delta to the score of the member, returning the new score.
+    pub async fn zincrby(
+        &mut self,
+        key: &str,
+        delta: f64,
+        member: Bytes,
+    ) -> Result<f64, crate::Error> {
+        let frame = ZIncrBy::new(key, delta, member).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(score) => Ok(std::str::from_utf8(&score)?.parse()?),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    /// Sets the value at the path of the JSON document, e.g. `$.user.name`. Returns
+    /// `false` if nothing was set because the path, other than the root, isn't there.
+    pub async fn json_set(
+        &mut self,
+        key: &str,
+        path: &str,
+        value: Json,
+    ) -> Result<bool, crate::Error> {
+        let frame = JsonSet::new(key, JsonPath::parse(path)?, value).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Simple(_) => Ok(true),
+            Frame::Null => Ok(false),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    /// Value at the path of the JSON document, `None` stands for a missing path or key.
+    pub async fn json_get(&mut self, key: &str, path: &str) -> Result<Option<Json>, crate::Error> {
+        let frame = JsonGet::new(key, JsonPath::parse(path)?).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Frame::Null => Ok(None),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    /// Removes the value at the path of the JSON document, the root removing the key.
+    /// Returns how many values were removed.
+    pub async fn json_del(&mut self, key: &str, path: &str) -> Result<u64, crate::Error> {
+        let frame = JsonDel::new(key, JsonPath::parse(path)?).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        Ok(self.read_integer().await? as u64)
+    }
+
+    /// Adds the delta to the number at the path of the JSON document, returning
+    /// the result. `None` stands for a missing path or key.
+    pub async fn json_numincrby(
+        &mut self,
+        key: &str,
+        path: &str,
+        delta: Number,
+    ) -> Result<Option<Number>, crate::Error> {
+        let frame = JsonNumIncrBy::new(key, JsonPath::parse(path)?, delta).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Bulk(bytes) => Ok(Some(std::str::from_utf8(&bytes)?.parse()?)),
+            Frame::Null => Ok(None),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    /// Keys whose field covered by the secondary index holds the value, in key order.
+    pub async fn find(&mut self, index: &str, value: Json) -> Result<Vec<String>, crate::Error> {
+        let frame = Find::new(index, value).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Array(parts) => into_strings(parts),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    /// Makes the next `exec` fail if any of the keys is written before it.
+    pub async fn watch(&mut self, keys: &[&str]) -> Result<String, crate::Error> {
+        let keys = keys.iter().map(|key| key.to_string()).collect();
+
+        let frame = Watch::new(keys).into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        self.read_simple().await
+    }
+
+    /// Starts a transaction. Commands are then sent with `queue` and only applied
+    /// on `exec`, so their responses come from there.
+    pub async fn multi(&mut self) -> Result<String, crate::Error> {
+        let frame = Multi::new().into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        self.read_simple().await
+    }
+
+    /// Sends a command frame, e.g. `Set::new(key, value).into_frame()`,
+    /// to be applied with the rest of the transaction.
+    pub async fn queue(&mut self, frame: Frame) -> Result<(), crate::Error> {
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_simple().await?.as_str() {
+            "QUEUED" => Ok(()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    /// Applies the queued commands, returning their responses in order. `None`
+    /// is returned if nothing was applied, because a watched key has changed or
+    /// the commands kept conflicting with other writes.
+    pub async fn exec(&mut self) -> Result<Option<Vec<Frame>>, crate::Error> {
+        let frame = Exec::new().into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        match self.read_response().await? {
+            Frame::Array(responses) => Ok(Some(responses)),
+            Frame::Null => Ok(None),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    /// Drops the queued commands, as well as the watched keys.
+    pub async fn discard(&mut self) -> Result<String, crate::Error> {
+        let frame = Discard::new().into_frame();
+        self.connection.write_frame(&frame).await?;
+
+        self.read_simple().await
+    }
+
+    async fn read_bulk_array(&mut self) -> Result<Vec<Bytes>, crate::Error> {
+        let parts = match self.read_response().await? {
+            Frame::Array(parts) => parts,
+            Frame::Error(error_kind) => return Err(format!("Error: {}", error_kind).into()),
+            _ => return Err("Internal error".into()),
+        };
+
+        parts
+            .into_iter()
+            .map(|part| match part {
+                Frame::Bulk(bytes) => Ok(bytes),
+                _ => Err("Internal error".into()),
+            })
+            .collect()
+    }
+
+    /// Members each followed by its score, as replied with `WITHSCORES`.
+    async fn read_scored_members(&mut self) -> Result<Vec<(Bytes, f64)>, crate::Error> {
+        let parts = self.read_bulk_array().await?;
+
+        parts
+            .chunks(2)
+            .map(|pair| match pair {
+                [member, score] => Ok((member.clone(), std::str::from_utf8(score)?.parse()?)),
+                _ => Err("Internal error".into()),
+            })
+            .collect()
+    }
+
+    async fn read_simple(&mut self) -> Result<String, crate::Error> {
+        match self.read_response().await? {
+            Frame::Simple(string) => Ok(string),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
+    async fn read_integer(&mut self) -> Result<i64, crate::Error> {
+        match self.read_response().await? {
+            Frame::Integer(value) => Ok(value),
+            Frame::Error(error_kind) => Err(format!("Error: {}", error_kind).into()),
+            _ => Err("Internal error".into()),
+        }
+    }
+
     async fn read_response(&mut self) -> Result<Frame, crate::Error> {
         let response = self.connection.read_frame().await?;
 
@@ -79,3 +757,14 @@ impl Client {
         }
     }
 }
+
+fn into_strings(frames: Vec<Frame>) -> Result<Vec<String>, crate::Error> {
+    frames
+        .into_iter()
+        .map(|frame| match frame {
+            Frame::Bulk(bytes) => Ok(String::from_utf8(bytes.to_vec())?),
+            Frame::Simple(string) => Ok(string),
+            _ => Err("Internal error".into()),
+        })
+        .collect()
+}
@@ -0,0 +1,118 @@
+/// Matches the key against a glob-style pattern: `*` stands for any sequence of
+/// characters, `?` for any single one, `[abc]`, `[a-z]` and `[^abc]` for character
+/// classes, while `\` makes the next character match literally.
+pub(crate) fn matches(pattern: &str, key: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let key: Vec<char> = key.chars().collect();
+
+    let (mut pi, mut ki) = (0, 0);
+
+    // Position of the last `*` along with the key position it's matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while ki < key.len() {
+        if pi < pattern.len() {
+            if pattern[pi] == '*' {
+                backtrack = Some((pi, ki));
+                pi += 1;
+                continue;
+            }
+
+            if let Some(next_pi) = match_char(&pattern, pi, key[ki]) {
+                pi = next_pi;
+                ki += 1;
+                continue;
+            }
+        }
+
+        // Let the last `*` swallow one more character and try again from there
+        match backtrack {
+            Some((star_pi, star_ki)) => {
+                pi = star_pi + 1;
+                ki = star_ki + 1;
+                backtrack = Some((star_pi, star_ki + 1));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[pi..].iter().all(|&c| c == '*')
+}
+
+/// Matches a single character against the pattern element at `pi`, returning
+/// the position of the next element on success.
+fn match_char(pattern: &[char], pi: usize, c: char) -> Option<usize> {
+    match pattern[pi] {
+        '?' => Some(pi + 1),
+        '\\' if pi + 1 < pattern.len() => (pattern[pi + 1] == c).then_some(pi + 2),
+        '[' => match match_class(pattern, pi + 1, c) {
+            Some((matched, next_pi)) => matched.then_some(next_pi),
+            // Unterminated class is just a literal bracket
+            None => (c == '[').then_some(pi + 1),
+        },
+        literal => (literal == c).then_some(pi + 1),
+    }
+}
+
+/// Matches the character against the class starting right after `[`. Returns
+/// whether it matched and the position past the closing `]`, or `None`
+/// if there is no closing bracket.
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut pi = start;
+
+    let negated = pattern.get(pi) == Some(&'^');
+    if negated {
+        pi += 1;
+    }
+
+    let mut matched = false;
+
+    loop {
+        let mut first = *pattern.get(pi)?;
+
+        if first == ']' && pi > start + negated as usize {
+            return Some((matched != negated, pi + 1));
+        }
+
+        if first == '\\' {
+            pi += 1;
+            first = *pattern.get(pi)?;
+        }
+
+        if pattern.get(pi + 1) == Some(&'-') && pattern.get(pi + 2).is_some_and(|&c| c != ']') {
+            let last = pattern[pi + 2];
+            matched |= first <= c && c <= last;
+            pi += 3;
+        } else {
+            matched |= first == c;
+            pi += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_matching() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("user:*", "user:42"));
+        assert!(!matches("user:*", "session:42"));
+        assert!(matches("*:42", "user:42"));
+        assert!(matches("u*r:*2", "user:42"));
+        assert!(matches("h?llo", "hello"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("key_[0-9]", "key_7"));
+        assert!(!matches("key_[0-9]", "key_x"));
+        assert!(matches("what\\?", "what?"));
+        assert!(!matches("what\\?", "whatx"));
+        assert!(matches("[[]", "["));
+        assert!(matches("a[", "a["));
+    }
+}
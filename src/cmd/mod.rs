@@ -1,40 +1,362 @@
+use std::ops::Bound;
+use std::time::Duration;
+
 use bytes::Bytes;
+use serde_json::{Number, Value as Json};
 
+pub(crate) mod glob;
 mod parse;
 
-use crate::connection::Connection;
-use crate::db::Db;
+use crate::db::now_millis;
+use crate::engine::{
+    Expected, JsonError, JsonPath, Kind, ListEnd, SetCondition, StorageEngine, Value, WrongType,
+};
 use crate::frame::{Frame, FrameErrorKind};
 use parse::{Parse, ParseError};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Command {
     Ping(Ping),
     Get(Get),
-    // TODO: Scan?
+    Scan(Scan),
     Set(Set),
     Delete(Delete),
+    Keys(Keys),
+    Exists(Exists),
+    DbSize(DbSize),
+    Expire(Expire),
+    Ttl(Ttl),
+    Persist(Persist),
+    SetIf(SetIf),
+    GetSet(GetSet),
+    Cas(Cas),
+    Version(Version),
+    IncrBy(IncrBy),
+    MGet(MGet),
+    MSet(MSet),
+    HSet(HSet),
+    HGet(HGet),
+    HDel(HDel),
+    HGetAll(HGetAll),
+    Push(Push),
+    Pop(Pop),
+    LRange(LRange),
+    LLen(LLen),
+    BLPop(BLPop),
+    SAdd(SAdd),
+    SRem(SRem),
+    SMembers(SMembers),
+    SIsMember(SIsMember),
+    SInter(SInter),
+    ZAdd(ZAdd),
+    ZRange(ZRange),
+    ZRangeByScore(ZRangeByScore),
+    ZRank(ZRank),
+    ZIncrBy(ZIncrBy),
+    JsonSet(JsonSet),
+    JsonGet(JsonGet),
+    JsonDel(JsonDel),
+    JsonNumIncrBy(JsonNumIncrBy),
+    Find(Find),
+    Multi(Multi),
+    Exec(Exec),
+    Discard(Discard),
+    Watch(Watch),
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct Ping;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Get {
     pub key: String,
+    /// Value is read as of this point in time, in milliseconds since the unix epoch
+    pub at: Option<u64>,
+}
+
+/// Page of keys following the cursor. Cursor is the last key of the previous page,
+/// so no state is kept on the server between the pages.
+#[derive(Debug, Clone)]
+pub struct Scan {
+    pub cursor: String,
+    pub pattern: Option<String>,
+    pub count: usize,
+    /// Keys are scanned as of this point in time, which gives a consistent view
+    /// across the pages if all of them are scanned as of the same one
+    pub at: Option<u64>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Set {
     pub key: String,
     pub value: Bytes,
+    /// Key is removed once this much time passes, it's kept for good if not given
+    pub expire: Option<Duration>,
+    pub condition: SetCondition,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Delete {
     pub key: String,
 }
 
+#[derive(Debug, Clone)]
+pub struct Keys {
+    pub pattern: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Exists {
+    pub keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DbSize;
+
+#[derive(Debug, Clone)]
+pub struct Expire {
+    pub key: String,
+    pub seconds: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ttl {
+    pub key: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Persist {
+    pub key: String,
+}
+
+/// `SETNX` and `SETXX`, setting the key only if it's missing or only if it exists.
+#[derive(Debug, Clone)]
+pub struct SetIf {
+    pub key: String,
+    pub value: Bytes,
+    pub condition: SetCondition,
+}
+
+#[derive(Debug, Clone)]
+pub struct GetSet {
+    pub key: String,
+    pub value: Bytes,
+}
+
+/// Compare-and-swap, setting the key only if it's still at the expected value or version.
+#[derive(Debug, Clone)]
+pub struct Cas {
+    pub key: String,
+    pub value: Bytes,
+    pub expected: Expected,
+}
+
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub key: String,
+}
+
+/// `INCRBY`, as well as `INCR`, `DECR` and `DECRBY`, which only differ in the delta.
+#[derive(Debug, Clone)]
+pub struct IncrBy {
+    pub key: String,
+    pub delta: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MGet {
+    pub keys: Vec<String>,
+}
+
+/// Sets all the pairs at once, clearing their expiration times like `Set` does.
+#[derive(Debug, Clone)]
+pub struct MSet {
+    pub pairs: Vec<(String, Bytes)>,
+}
+
+/// Sets the fields of a hash, creating it if needed.
+#[derive(Debug, Clone)]
+pub struct HSet {
+    pub key: String,
+    pub fields: Vec<(String, Bytes)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HGet {
+    pub key: String,
+    pub field: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct HDel {
+    pub key: String,
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HGetAll {
+    pub key: String,
+}
+
+/// `LPUSH` and `RPUSH`, which only differ in the end of the list.
+#[derive(Debug, Clone)]
+pub struct Push {
+    pub key: String,
+    pub values: Vec<Bytes>,
+    pub end: ListEnd,
+}
+
+/// `LPOP` and `RPOP`, which only differ in the end of the list.
+#[derive(Debug, Clone)]
+pub struct Pop {
+    pub key: String,
+    pub end: ListEnd,
+}
+
+#[derive(Debug, Clone)]
+pub struct LRange {
+    pub key: String,
+    pub start: i64,
+    pub stop: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct LLen {
+    pub key: String,
+}
+
+/// Pops from the left end of the first non-empty list. Connection handler waits
+/// for one of the lists to be pushed to if all of them are empty.
+#[derive(Debug, Clone)]
+pub struct BLPop {
+    pub keys: Vec<String>,
+    /// How long to wait for, forever if not given
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SAdd {
+    pub key: String,
+    pub members: Vec<Bytes>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SRem {
+    pub key: String,
+    pub members: Vec<Bytes>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SMembers {
+    pub key: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SIsMember {
+    pub key: String,
+    pub member: Bytes,
+}
+
+#[derive(Debug, Clone)]
+pub struct SInter {
+    pub keys: Vec<String>,
+}
+
+/// Adds members to a sorted set, or updates their scores if they are there already.
+#[derive(Debug, Clone)]
+pub struct ZAdd {
+    pub key: String,
+    pub scores: Vec<(f64, Bytes)>,
+}
+
+/// Members of a sorted set between two ranks in score order.
+#[derive(Debug, Clone)]
+pub struct ZRange {
+    pub key: String,
+    pub start: i64,
+    pub stop: i64,
+    /// Every member is followed by its score in the reply
+    pub with_scores: bool,
+}
+
+/// Members of a sorted set with scores between two bounds. Bound is exclusive
+/// if it's prefixed with `(`, and either of them may be an infinity.
+#[derive(Debug, Clone)]
+pub struct ZRangeByScore {
+    pub key: String,
+    pub min: Bound<f64>,
+    pub max: Bound<f64>,
+    /// Every member is followed by its score in the reply
+    pub with_scores: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ZRank {
+    pub key: String,
+    pub member: Bytes,
+}
+
+#[derive(Debug, Clone)]
+pub struct ZIncrBy {
+    pub key: String,
+    pub delta: f64,
+    pub member: Bytes,
+}
+
+/// Sets the value at the path of a JSON document, which is stored as a string.
+#[derive(Debug, Clone)]
+pub struct JsonSet {
+    pub key: String,
+    pub path: JsonPath,
+    pub value: Json,
+}
+
+#[derive(Debug, Clone)]
+pub struct JsonGet {
+    pub key: String,
+    /// Root of the document if not given
+    pub path: JsonPath,
+}
+
+#[derive(Debug, Clone)]
+pub struct JsonDel {
+    pub key: String,
+    /// Root of the document if not given, which deletes the key
+    pub path: JsonPath,
+}
+
+#[derive(Debug, Clone)]
+pub struct JsonNumIncrBy {
+    pub key: String,
+    pub path: JsonPath,
+    pub delta: Number,
+}
+
+/// Keys whose field covered by the secondary index holds the value.
+#[derive(Debug, Clone)]
+pub struct Find {
+    pub index: String,
+    /// Scalar JSON value, so `"7"` and `7` look for a string and a number respectively
+    pub value: Json,
+}
+
+/// Starts a transaction, following commands are queued until `Exec` or `Discard`.
+#[derive(Debug, Clone, Default)]
+pub struct Multi;
+
+/// Applies the queued commands at once, unless any of the watched keys
+/// has been written since it was watched.
+#[derive(Debug, Clone, Default)]
+pub struct Exec;
+
+#[derive(Debug, Clone, Default)]
+pub struct Discard;
+
+#[derive(Debug, Clone)]
+pub struct Watch {
+    pub keys: Vec<String>,
+}
+
 impl Command {
     pub fn from_frame(frame: Frame) -> Result<Command, crate::Error> {
         let mut parse = Parse::new(frame)?;
@@ -44,8 +366,55 @@ impl Command {
         let command = match command_name.as_str() {
             "ping" => Command::Ping(Ping::parse_frames(&mut parse)?),
             "get" => Command::Get(Get::parse_frames(&mut parse)?),
+            "scan" => Command::Scan(Scan::parse_frames(&mut parse)?),
             "set" => Command::Set(Set::parse_frames(&mut parse)?),
             "delete" => Command::Delete(Delete::parse_frames(&mut parse)?),
+            "keys" => Command::Keys(Keys::parse_frames(&mut parse)?),
+            "exists" => Command::Exists(Exists::parse_frames(&mut parse)?),
+            "dbsize" => Command::DbSize(DbSize::parse_frames(&mut parse)?),
+            "expire" => Command::Expire(Expire::parse_frames(&mut parse)?),
+            "ttl" => Command::Ttl(Ttl::parse_frames(&mut parse)?),
+            "persist" => Command::Persist(Persist::parse_frames(&mut parse)?),
+            "setnx" => Command::SetIf(SetIf::parse_frames(&mut parse, SetCondition::IfAbsent)?),
+            "setxx" => Command::SetIf(SetIf::parse_frames(&mut parse, SetCondition::IfPresent)?),
+            "getset" => Command::GetSet(GetSet::parse_frames(&mut parse)?),
+            "cas" => Command::Cas(Cas::parse_frames(&mut parse)?),
+            "version" => Command::Version(Version::parse_frames(&mut parse)?),
+            "incr" | "decr" | "incrby" | "decrby" => {
+                Command::IncrBy(IncrBy::parse_frames(&mut parse, &command_name)?)
+            }
+            "mget" => Command::MGet(MGet::parse_frames(&mut parse)?),
+            "mset" => Command::MSet(MSet::parse_frames(&mut parse)?),
+            "hset" => Command::HSet(HSet::parse_frames(&mut parse)?),
+            "hget" => Command::HGet(HGet::parse_frames(&mut parse)?),
+            "hdel" => Command::HDel(HDel::parse_frames(&mut parse)?),
+            "hgetall" => Command::HGetAll(HGetAll::parse_frames(&mut parse)?),
+            "lpush" => Command::Push(Push::parse_frames(&mut parse, ListEnd::Left)?),
+            "rpush" => Command::Push(Push::parse_frames(&mut parse, ListEnd::Right)?),
+            "lpop" => Command::Pop(Pop::parse_frames(&mut parse, ListEnd::Left)?),
+            "rpop" => Command::Pop(Pop::parse_frames(&mut parse, ListEnd::Right)?),
+            "lrange" => Command::LRange(LRange::parse_frames(&mut parse)?),
+            "llen" => Command::LLen(LLen::parse_frames(&mut parse)?),
+            "blpop" => Command::BLPop(BLPop::parse_frames(&mut parse)?),
+            "sadd" => Command::SAdd(SAdd::parse_frames(&mut parse)?),
+            "srem" => Command::SRem(SRem::parse_frames(&mut parse)?),
+            "smembers" => Command::SMembers(SMembers::parse_frames(&mut parse)?),
+            "sismember" => Command::SIsMember(SIsMember::parse_frames(&mut parse)?),
+            "sinter" => Command::SInter(SInter::parse_frames(&mut parse)?),
+            "zadd" => Command::ZAdd(ZAdd::parse_frames(&mut parse)?),
+            "zrange" => Command::ZRange(ZRange::parse_frames(&mut parse)?),
+            "zrangebyscore" => Command::ZRangeByScore(ZRangeByScore::parse_frames(&mut parse)?),
+            "zrank" => Command::ZRank(ZRank::parse_frames(&mut parse)?),
+            "zincrby" => Command::ZIncrBy(ZIncrBy::parse_frames(&mut parse)?),
+            "json.set" => Command::JsonSet(JsonSet::parse_frames(&mut parse)?),
+            "json.get" => Command::JsonGet(JsonGet::parse_frames(&mut parse)?),
+            "json.del" => Command::JsonDel(JsonDel::parse_frames(&mut parse)?),
+            "json.numincrby" => Command::JsonNumIncrBy(JsonNumIncrBy::parse_frames(&mut parse)?),
+            "find" => Command::Find(Find::parse_frames(&mut parse)?),
+            "multi" => Command::Multi(Multi),
+            "exec" => Command::Exec(Exec),
+            "discard" => Command::Discard(Discard),
+            "watch" => Command::Watch(Watch::parse_frames(&mut parse)?),
             _ => todo!(),
         };
 
@@ -54,14 +423,79 @@ impl Command {
         Ok(command)
     }
 
-    pub(crate) async fn apply(self, conn: &mut Connection, db: &Db) -> Result<(), crate::Error> {
+    /// Transaction commands change the state of the connection rather than
+    /// the engine, so they are taken care of by the connection handler instead.
+    ///
+    /// Command applied to a key of another type, or to a string which isn't
+    /// the JSON it expects, is replied to with an error while the connection
+    /// stays open.
+    pub(crate) fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
         use Command::*;
 
+        let response = match self {
+            Ping(cmd) => cmd.execute(),
+            Get(cmd) => cmd.execute(engine),
+            Scan(cmd) => cmd.execute(engine),
+            Set(cmd) => cmd.execute(engine),
+            Delete(cmd) => cmd.execute(engine),
+            Keys(cmd) => cmd.execute(engine),
+            Exists(cmd) => cmd.execute(engine),
+            DbSize(cmd) => cmd.execute(engine),
+            Expire(cmd) => cmd.execute(engine),
+            Ttl(cmd) => cmd.execute(engine),
+            Persist(cmd) => cmd.execute(engine),
+            SetIf(cmd) => cmd.execute(engine),
+            GetSet(cmd) => cmd.execute(engine),
+            Cas(cmd) => cmd.execute(engine),
+            Version(cmd) => cmd.execute(engine),
+            IncrBy(cmd) => cmd.execute(engine),
+            MGet(cmd) => cmd.execute(engine),
+            MSet(cmd) => cmd.execute(engine),
+            HSet(cmd) => cmd.execute(engine),
+            HGet(cmd) => cmd.execute(engine),
+            HDel(cmd) => cmd.execute(engine),
+            HGetAll(cmd) => cmd.execute(engine),
+            Push(cmd) => cmd.execute(engine),
+            Pop(cmd) => cmd.execute(engine),
+            LRange(cmd) => cmd.execute(engine),
+            LLen(cmd) => cmd.execute(engine),
+            BLPop(cmd) => cmd.execute(engine),
+            SAdd(cmd) => cmd.execute(engine),
+            SRem(cmd) => cmd.execute(engine),
+            SMembers(cmd) => cmd.execute(engine),
+            SIsMember(cmd) => cmd.execute(engine),
+            SInter(cmd) => cmd.execute(engine),
+            ZAdd(cmd) => cmd.execute(engine),
+            ZRange(cmd) => cmd.execute(engine),
+            ZRangeByScore(cmd) => cmd.execute(engine),
+            ZRank(cmd) => cmd.execute(engine),
+            ZIncrBy(cmd) => cmd.execute(engine),
+            JsonSet(cmd) => cmd.execute(engine),
+            JsonGet(cmd) => cmd.execute(engine),
+            JsonDel(cmd) => cmd.execute(engine),
+            JsonNumIncrBy(cmd) => cmd.execute(engine),
+            Find(cmd) => cmd.execute(engine),
+            Multi(_) | Exec(_) | Discard(_) | Watch(_) => {
+                Err("transaction commands are handled by the connection".into())
+            }
+        };
+
+        match response {
+            Err(err) if err.is::<WrongType>() => Ok(Frame::Error(FrameErrorKind::WrongType)),
+            Err(err) => match err.downcast_ref::<JsonError>() {
+                Some(JsonError::NotJson) => Ok(Frame::Error(FrameErrorKind::NotJson)),
+                Some(JsonError::NotANumber) => Ok(Frame::Error(FrameErrorKind::NotANumber)),
+                None => Err(err),
+            },
+            response => response,
+        }
+    }
+
+    /// List the command pushes to, if any, for the connections waiting on it.
+    pub(crate) fn pushed_key(&self) -> Option<&str> {
         match self {
-            Ping(cmd) => cmd.apply(conn).await,
-            Get(cmd) => cmd.apply(conn, db).await,
-            Set(cmd) => cmd.apply(conn, db).await,
-            Delete(cmd) => cmd.apply(conn, db).await,
+            Command::Push(cmd) => Some(&cmd.key),
+            _ => None,
         }
     }
 }
@@ -82,17 +516,13 @@ impl Ping {
     pub fn parse_frames(parse: &mut Parse) -> Result<Ping, crate::Error> {
         match parse.next_bytes() {
             Ok(_) => Ok(Ping::new()),
-            Err(ParseError::EndOfStream) => Ok(Ping::default()),
+            Err(ParseError::EndOfStream) => Ok(Ping),
             Err(e) => Err(e.into()),
         }
     }
 
-    pub async fn apply(self, conn: &mut Connection) -> Result<(), crate::Error> {
-        let response = Frame::Simple("PONG".to_string());
-
-        conn.write_frame(&response).await?;
-
-        Ok(())
+    pub fn execute(self) -> Result<Frame, crate::Error> {
+        Ok(Frame::Simple("PONG".to_string()))
     }
 }
 
@@ -100,39 +530,198 @@ impl Get {
     pub fn new(key: impl ToString) -> Get {
         Get {
             key: key.to_string(),
+            at: None,
         }
     }
 
+    pub fn at(mut self, at: Option<u64>) -> Get {
+        self.at = at;
+        self
+    }
+
     pub fn into_frame(self) -> Frame {
         let mut frame = Frame::array();
 
         frame.push_string("get".to_string());
         frame.push_string(self.key);
 
+        if let Some(at) = self.at {
+            frame.push_string("at".to_string());
+            frame.push_string(at.to_string());
+        }
+
         frame
     }
 
     pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Get, crate::Error> {
         let key = parse.next_string()?;
 
-        Ok(Get { key })
-    }
+        let at = match parse.next_string() {
+            Ok(option) if option.eq_ignore_ascii_case("at") => Some(parse.next_int()?),
+            Ok(option) => return Err(format!("unknown GET option {}", option).into()),
+            Err(ParseError::EndOfStream) => None,
+            Err(err) => return Err(err.into()),
+        };
 
-    pub async fn apply(self, conn: &mut Connection, db: &Db) -> Result<(), crate::Error> {
-        let resp_frame = match db.get(self.key.as_str())? {
-            Some(record) => {
-                let val_bytes = record.get_val_bytes();
-                let err_resp = Frame::Error(FrameErrorKind::InternalError);
+        Ok(Get { key, at })
+    }
 
-                val_bytes.map_or(err_resp, Frame::Bulk)
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let value = match self.at {
+            Some(at) if !is_retained(engine, at) => {
+                return Ok(Frame::Error(FrameErrorKind::HistoryUnavailable))
             }
+            Some(at) => engine
+                .get_value_at(&self.key, at)?
+                .map(Value::into_bytes)
+                .transpose()?,
+            None => engine.get(self.key.as_str())?,
+        };
+
+        let resp_frame = match value {
+            Some(value) => Frame::Bulk(value),
             None => Frame::Error(FrameErrorKind::NotFound),
         };
 
-        conn.write_frame(&resp_frame).await?;
+        Ok(resp_frame)
+    }
+}
+
+impl Scan {
+    /// Cursor which starts the iteration and which is returned once it's over
+    pub const START_CURSOR: &'static str = "0";
+
+    const DEFAULT_COUNT: usize = 10;
+
+    pub fn new(cursor: impl ToString, pattern: Option<String>, count: Option<usize>) -> Scan {
+        Scan {
+            cursor: cursor.to_string(),
+            pattern,
+            count: count.unwrap_or(Scan::DEFAULT_COUNT),
+            at: None,
+        }
+    }
+
+    pub fn at(mut self, at: Option<u64>) -> Scan {
+        self.at = at;
+        self
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("scan".to_string());
+        frame.push_bulk(Bytes::from(self.cursor.into_bytes()));
+
+        if let Some(pattern) = self.pattern {
+            frame.push_string("match".to_string());
+            frame.push_bulk(Bytes::from(pattern.into_bytes()));
+        }
+
+        frame.push_string("count".to_string());
+        frame.push_string(self.count.to_string());
+
+        if let Some(at) = self.at {
+            frame.push_string("at".to_string());
+            frame.push_string(at.to_string());
+        }
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Scan, crate::Error> {
+        let cursor = parse.next_string()?;
+
+        let mut pattern = None;
+        let mut count = None;
+        let mut at = None;
+
+        loop {
+            match parse.next_string() {
+                Ok(option) => match option.to_lowercase().as_str() {
+                    "match" => pattern = Some(parse.next_string()?),
+                    "count" => count = Some(parse.next_int()? as usize),
+                    "at" => at = Some(parse.next_int()?),
+                    _ => return Err(format!("unknown SCAN option {}", option).into()),
+                },
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        if count == Some(0) {
+            return Err("SCAN count has to be positive".into());
+        }
+
+        Ok(Scan::new(cursor, pattern, count).at(at))
+    }
+
+    /// Replies with an array holding the next cursor followed by the matching keys.
+    /// `COUNT` limits the keys looked at rather than the ones returned, so a page
+    /// could turn out to be empty while the iteration is still not over.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let start = match self.cursor.as_str() {
+            Scan::START_CURSOR => Bound::Unbounded,
+            cursor => Bound::Excluded(decode_cursor(cursor)?),
+        };
+
+        let range = (start, Bound::Unbounded);
+
+        let entries = match self.at {
+            Some(at) if !is_retained(engine, at) => {
+                return Ok(Frame::Error(FrameErrorKind::HistoryUnavailable))
+            }
+            Some(at) => engine.scan_at(range, false, self.count, at)?,
+            None => engine.scan(range, false, self.count)?,
+        };
+
+        let next_cursor = match entries.last() {
+            Some((key, _)) if entries.len() == self.count => encode_cursor(key),
+            _ => Scan::START_CURSOR.to_string(),
+        };
+
+        let mut resp_frame = Frame::array();
+        resp_frame.push_bulk(Bytes::from(next_cursor.into_bytes()));
+
+        for (key, _) in entries {
+            let is_match = match &self.pattern {
+                Some(pattern) => glob::matches(pattern, &key),
+                None => true,
+            };
+
+            if is_match {
+                resp_frame.push_bulk(Bytes::from(key.into_bytes()));
+            }
+        }
+
+        Ok(resp_frame)
+    }
+}
+
+/// Whether the engine can still read the keys as of the point in time.
+fn is_retained(engine: &dyn StorageEngine, at: u64) -> bool {
+    engine.history_start().is_some_and(|start| start <= at)
+}
+
+/// Keys are hex-encoded within cursors, so that no key could be mistaken for the start.
+fn encode_cursor(key: &str) -> String {
+    key.bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_cursor(cursor: &str) -> Result<String, crate::Error> {
+    let invalid = || -> crate::Error { format!("invalid SCAN cursor {}", cursor).into() };
 
-        Ok(())
+    if !cursor.len().is_multiple_of(2) {
+        return Err(invalid());
     }
+
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(cursor.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(invalid)?;
+
+    String::from_utf8(bytes).map_err(|_| invalid())
 }
 
 impl Set {
@@ -140,34 +729,97 @@ impl Set {
         Set {
             key: key.to_string(),
             value,
+            expire: None,
+            condition: SetCondition::Always,
         }
     }
 
+    pub fn expire(mut self, expire: Option<Duration>) -> Set {
+        self.expire = expire;
+        self
+    }
+
+    pub fn condition(mut self, condition: SetCondition) -> Set {
+        self.condition = condition;
+        self
+    }
+
     pub fn into_frame(self) -> Frame {
         let mut frame = Frame::array();
 
         frame.push_string("set".to_string());
         frame.push_string(self.key);
 
-        frame.push_bulk(Bytes::from(self.value));
+        frame.push_bulk(self.value);
+
+        if let Some(expire) = self.expire {
+            frame.push_string("px".to_string());
+            frame.push_string(expire.as_millis().to_string());
+        }
+
+        match self.condition {
+            SetCondition::Always => {}
+            SetCondition::IfAbsent => frame.push_string("nx".to_string()),
+            SetCondition::IfPresent => frame.push_string("xx".to_string()),
+        }
 
         frame
     }
 
+    /// Options go after the value: `EX seconds` or `PX milliseconds` to make the key
+    /// expire, `NX` to only set a missing key and `XX` to only set an existing one.
     pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Set, crate::Error> {
         let key = parse.next_string()?;
         let value = parse.next_bytes()?;
 
-        Ok(Set { key, value })
+        let mut set = Set::new(key, value);
+
+        loop {
+            match parse.next_string() {
+                Ok(option) => match option.to_lowercase().as_str() {
+                    "ex" if set.expire.is_none() => {
+                        set.expire = Some(Duration::from_secs(parse.next_int()?));
+                    }
+                    "px" if set.expire.is_none() => {
+                        set.expire = Some(Duration::from_millis(parse.next_int()?));
+                    }
+                    "nx" if set.condition == SetCondition::Always => {
+                        set.condition = SetCondition::IfAbsent;
+                    }
+                    "xx" if set.condition == SetCondition::Always => {
+                        set.condition = SetCondition::IfPresent;
+                    }
+                    _ => return Err(format!("unexpected SET option {}", option).into()),
+                },
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        if set.expire == Some(Duration::ZERO) {
+            return Err("SET expire time has to be positive".into());
+        }
+
+        Ok(set)
     }
 
-    pub async fn apply(self, conn: &mut Connection, db: &Db) -> Result<(), crate::Error> {
-        db.set(self.key, self.value)?;
+    /// Replies with `OK`, or with a null if the condition kept the value from being set.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let expires_at = match self.expire {
+            Some(expire) => match expires_at(expire) {
+                Some(expires_at) => Some(expires_at),
+                None => return Ok(Frame::Error(FrameErrorKind::InvalidExpireTime)),
+            },
+            None => None,
+        };
 
-        let response = Frame::Simple("OK".to_string());
-        conn.write_frame(&response).await?;
+        let response = if engine.set_with(self.key, self.value, expires_at, self.condition)? {
+            Frame::Simple("OK".to_string())
+        } else {
+            Frame::Null
+        };
 
-        Ok(())
+        Ok(response)
     }
 }
 
@@ -193,14 +845,1691 @@ impl Delete {
         Ok(Delete { key })
     }
 
-    pub async fn apply(self, conn: &mut Connection, db: &Db) -> Result<(), crate::Error> {
-        let resp_frame = match db.delete(self.key)? {
-            Some(_) => Frame::Simple("OK".to_string()),
-            None => Frame::Error(FrameErrorKind::NotFound),
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let resp_frame = if engine.delete(&self.key)? {
+            Frame::Simple("OK".to_string())
+        } else {
+            Frame::Error(FrameErrorKind::NotFound)
         };
 
-        conn.write_frame(&resp_frame).await?;
+        Ok(resp_frame)
+    }
+}
+
+impl Keys {
+    pub fn new(pattern: impl ToString) -> Keys {
+        Keys {
+            pattern: pattern.to_string(),
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("keys".to_string());
+        frame.push_bulk(Bytes::from(self.pattern.into_bytes()));
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Keys, crate::Error> {
+        let pattern = parse.next_string()?;
+
+        Ok(Keys { pattern })
+    }
+
+    /// Replies with all the keys matching the pattern at once, `Scan`
+    /// is the way to go through a large keyspace.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let mut resp_frame = Frame::array();
+
+        for key in engine.keys()? {
+            if glob::matches(&self.pattern, &key) {
+                resp_frame.push_bulk(Bytes::from(key.into_bytes()));
+            }
+        }
+
+        Ok(resp_frame)
+    }
+}
+
+impl Exists {
+    pub fn new(keys: Vec<String>) -> Exists {
+        Exists { keys }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("exists".to_string());
+
+        for key in self.keys {
+            frame.push_string(key);
+        }
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Exists, crate::Error> {
+        let mut keys = vec![parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(key) => keys.push(key),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(Exists { keys })
+    }
+
+    /// Replies with the amount of the given keys that exist, repeated keys are
+    /// counted as many times as they are repeated.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let mut count = 0;
+
+        for key in self.keys.iter() {
+            if engine.contains(key)? {
+                count += 1;
+            }
+        }
+
+        Ok(Frame::Integer(count))
+    }
+}
+
+impl DbSize {
+    pub fn new() -> DbSize {
+        DbSize
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("dbsize".to_string());
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(_parse: &mut Parse) -> Result<DbSize, crate::Error> {
+        Ok(DbSize)
+    }
+
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let key_count = engine.key_count()? as i64;
+
+        Ok(Frame::Integer(key_count))
+    }
+}
+
+impl Expire {
+    pub fn new(key: impl ToString, seconds: u64) -> Expire {
+        Expire {
+            key: key.to_string(),
+            seconds,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("expire".to_string());
+        frame.push_string(self.key);
+        frame.push_string(self.seconds.to_string());
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Expire, crate::Error> {
+        let key = parse.next_string()?;
+        let seconds = parse.next_int()?;
+
+        Ok(Expire { key, seconds })
+    }
+
+    /// Replies with 1 if the timeout was set, or with 0 if there is no such key.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let expires_at = match expires_at(Duration::from_secs(self.seconds)) {
+            Some(expires_at) => expires_at,
+            None => return Ok(Frame::Error(FrameErrorKind::InvalidExpireTime)),
+        };
+
+        let previous = engine.set_expiry(&self.key, Some(expires_at))?;
+
+        Ok(Frame::Integer(previous.is_some() as i64))
+    }
+}
+
+/// Point in time the time to live runs out at, `None` if it's too far away.
+fn expires_at(ttl: Duration) -> Option<u64> {
+    u64::try_from(ttl.as_millis())
+        .ok()?
+        .checked_add(now_millis())
+}
+
+impl Ttl {
+    /// Reply for a missing key
+    pub const MISSING: i64 = -2;
+
+    /// Reply for a key which never expires
+    pub const PERSISTENT: i64 = -1;
+
+    pub fn new(key: impl ToString) -> Ttl {
+        Ttl {
+            key: key.to_string(),
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("ttl".to_string());
+        frame.push_string(self.key);
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Ttl, crate::Error> {
+        let key = parse.next_string()?;
+
+        Ok(Ttl { key })
+    }
+
+    /// Replies with the seconds left until the key expires, rounded to the nearest one.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let ttl = match engine.get_value(&self.key)? {
+            None => Ttl::MISSING,
+            Some(value) => match value.expires_at {
+                None => Ttl::PERSISTENT,
+                Some(expires_at) => (expires_at.saturating_sub(now_millis()) + 500) as i64 / 1000,
+            },
+        };
+
+        Ok(Frame::Integer(ttl))
+    }
+}
+
+impl Persist {
+    pub fn new(key: impl ToString) -> Persist {
+        Persist {
+            key: key.to_string(),
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("persist".to_string());
+        frame.push_string(self.key);
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Persist, crate::Error> {
+        let key = parse.next_string()?;
+
+        Ok(Persist { key })
+    }
+
+    /// Replies with 1 if the timeout was removed, or with 0 if the key is missing
+    /// or has no timeout.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let previous = engine.set_expiry(&self.key, None)?;
+        let removed = matches!(previous, Some(Some(_)));
+
+        Ok(Frame::Integer(removed as i64))
+    }
+}
+
+impl SetIf {
+    pub fn new(key: impl ToString, value: Bytes, condition: SetCondition) -> SetIf {
+        SetIf {
+            key: key.to_string(),
+            value,
+            condition,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        let name = match self.condition {
+            SetCondition::IfPresent => "setxx",
+            SetCondition::IfAbsent | SetCondition::Always => "setnx",
+        };
+
+        frame.push_string(name.to_string());
+        frame.push_string(self.key);
+        frame.push_bulk(self.value);
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(
+        parse: &mut Parse,
+        condition: SetCondition,
+    ) -> Result<SetIf, crate::Error> {
+        let key = parse.next_string()?;
+        let value = parse.next_bytes()?;
+
+        Ok(SetIf {
+            key,
+            value,
+            condition,
+        })
+    }
+
+    /// Replies with 1 if the value was set, or with 0 if the condition didn't hold.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let written = engine.set_with(self.key, self.value, None, self.condition)?;
+
+        Ok(Frame::Integer(written as i64))
+    }
+}
+
+impl GetSet {
+    pub fn new(key: impl ToString, value: Bytes) -> GetSet {
+        GetSet {
+            key: key.to_string(),
+            value,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("getset".to_string());
+        frame.push_string(self.key);
+        frame.push_bulk(self.value);
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<GetSet, crate::Error> {
+        let key = parse.next_string()?;
+        let value = parse.next_bytes()?;
+
+        Ok(GetSet { key, value })
+    }
+
+    /// Replies with the previous value, or with a null if the key was missing.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let resp_frame = match engine.get_set(self.key, self.value)? {
+            Some(previous) => Frame::Bulk(previous),
+            None => Frame::Null,
+        };
+
+        Ok(resp_frame)
+    }
+}
+
+impl Cas {
+    pub fn new(key: impl ToString, value: Bytes, expected: Expected) -> Cas {
+        Cas {
+            key: key.to_string(),
+            value,
+            expected,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("cas".to_string());
+        frame.push_string(self.key);
+        frame.push_bulk(self.value);
+
+        match self.expected {
+            Expected::Value(bytes) => {
+                frame.push_string("value".to_string());
+                frame.push_bulk(bytes);
+            }
+            Expected::Version(version) => {
+                frame.push_string("version".to_string());
+                frame.push_string(version.to_string());
+            }
+        }
+
+        frame
+    }
+
+    /// `CAS key value VALUE expected` or `CAS key value VERSION expected`.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Cas, crate::Error> {
+        let key = parse.next_string()?;
+        let value = parse.next_bytes()?;
+
+        let kind = parse.next_string()?;
+
+        let expected = match kind.to_lowercase().as_str() {
+            "value" => Expected::Value(parse.next_bytes()?),
+            "version" => Expected::Version(parse.next_int()?),
+            _ => return Err(format!("unexpected CAS option {}", kind).into()),
+        };
+
+        Ok(Cas {
+            key,
+            value,
+            expected,
+        })
+    }
+
+    /// Replies with 1 if the value was swapped, or with 0 if the key is missing
+    /// or has changed. Expiration time of the key is kept as is.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let swapped = engine.compare_and_swap(self.key, self.expected, self.value)?;
+
+        Ok(Frame::Integer(swapped as i64))
+    }
+}
+
+impl Version {
+    pub fn new(key: impl ToString) -> Version {
+        Version {
+            key: key.to_string(),
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("version".to_string());
+        frame.push_string(self.key);
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Version, crate::Error> {
+        let key = parse.next_string()?;
+
+        Ok(Version { key })
+    }
+
+    /// Replies with the version of the current value, to be passed to `Cas`,
+    /// or with a null if the key is missing.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let resp_frame = match engine.get_value(&self.key)? {
+            Some(value) => Frame::Integer(value.version as i64),
+            None => Frame::Null,
+        };
+
+        Ok(resp_frame)
+    }
+}
+
+impl IncrBy {
+    pub fn new(key: impl ToString, delta: i64) -> IncrBy {
+        IncrBy {
+            key: key.to_string(),
+            delta,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("incrby".to_string());
+        frame.push_string(self.key);
+        frame.push_string(self.delta.to_string());
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(
+        parse: &mut Parse,
+        command_name: &str,
+    ) -> Result<IncrBy, crate::Error> {
+        let key = parse.next_string()?;
+
+        let delta = match command_name {
+            "incr" => 1,
+            "decr" => -1,
+            "incrby" => parse.next_signed_int()?,
+            _ => parse
+                .next_signed_int()?
+                .checked_neg()
+                .ok_or("DECRBY delta is out of range")?,
+        };
+
+        Ok(IncrBy { key, delta })
+    }
+
+    /// Replies with the value after the increment, or with an error
+    /// if the value isn't an integer.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let resp_frame = match engine.increment(&self.key, self.delta)? {
+            Some(count) => Frame::Integer(count),
+            None => Frame::Error(FrameErrorKind::NotAnInteger),
+        };
+
+        Ok(resp_frame)
+    }
+}
+
+impl MGet {
+    pub fn new(keys: Vec<String>) -> MGet {
+        MGet { keys }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("mget".to_string());
+
+        for key in self.keys {
+            frame.push_string(key);
+        }
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<MGet, crate::Error> {
+        let mut keys = vec![parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(key) => keys.push(key),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(MGet { keys })
+    }
+
+    /// Replies with the values in the order of the keys, with a null for every
+    /// missing key and for every key which doesn't hold a string.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let mut values = Vec::with_capacity(self.keys.len());
+
+        for key in self.keys.iter() {
+            values.push(match engine.get_value(key)? {
+                Some(value) if value.kind == Kind::String => Frame::Bulk(value.bytes),
+                _ => Frame::Null,
+            });
+        }
+
+        Ok(Frame::Array(values))
+    }
+}
+
+impl MSet {
+    pub fn new(pairs: Vec<(String, Bytes)>) -> MSet {
+        MSet { pairs }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("mset".to_string());
+
+        for (key, value) in self.pairs {
+            frame.push_string(key);
+            frame.push_bulk(value);
+        }
+
+        frame
+    }
+
+    /// Every key has to be followed by its value.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<MSet, crate::Error> {
+        let mut pairs = vec![(parse.next_string()?, parse.next_bytes()?)];
+
+        loop {
+            match parse.next_string() {
+                Ok(key) => pairs.push((key, parse.next_bytes()?)),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(MSet { pairs })
+    }
+
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let batch = self
+            .pairs
+            .into_iter()
+            .map(|(key, value)| (key, Some(Value::new(value, None))))
+            .collect();
+
+        engine.write_batch(batch)?;
+
+        Ok(Frame::Simple("OK".to_string()))
+    }
+}
+
+impl HSet {
+    pub fn new(key: impl ToString, fields: Vec<(String, Bytes)>) -> HSet {
+        HSet {
+            key: key.to_string(),
+            fields,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("hset".to_string());
+        frame.push_string(self.key);
+
+        for (field, value) in self.fields {
+            frame.push_string(field);
+            frame.push_bulk(value);
+        }
+
+        frame
+    }
+
+    /// Every field has to be followed by its value.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<HSet, crate::Error> {
+        let key = parse.next_string()?;
+        let mut fields = vec![(parse.next_string()?, parse.next_bytes()?)];
+
+        loop {
+            match parse.next_string() {
+                Ok(field) => fields.push((field, parse.next_bytes()?)),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(HSet { key, fields })
+    }
+
+    /// Replies with the amount of fields which have been added rather than updated.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let added = engine.hash_set(&self.key, self.fields)?;
+
+        Ok(Frame::Integer(added as i64))
+    }
+}
+
+impl HGet {
+    pub fn new(key: impl ToString, field: impl ToString) -> HGet {
+        HGet {
+            key: key.to_string(),
+            field: field.to_string(),
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("hget".to_string());
+        frame.push_string(self.key);
+        frame.push_string(self.field);
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<HGet, crate::Error> {
+        let key = parse.next_string()?;
+        let field = parse.next_string()?;
+
+        Ok(HGet { key, field })
+    }
+
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let resp_frame = match engine.hash_get(&self.key, &self.field)? {
+            Some(value) => Frame::Bulk(value),
+            None => Frame::Null,
+        };
+
+        Ok(resp_frame)
+    }
+}
+
+impl HDel {
+    pub fn new(key: impl ToString, fields: Vec<String>) -> HDel {
+        HDel {
+            key: key.to_string(),
+            fields,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("hdel".to_string());
+        frame.push_string(self.key);
+
+        for field in self.fields {
+            frame.push_string(field);
+        }
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<HDel, crate::Error> {
+        let key = parse.next_string()?;
+        let mut fields = vec![parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(field) => fields.push(field),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(HDel { key, fields })
+    }
+
+    /// Replies with the amount of fields which have been removed.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let removed = engine.hash_delete(&self.key, &self.fields)?;
+
+        Ok(Frame::Integer(removed as i64))
+    }
+}
+
+impl HGetAll {
+    pub fn new(key: impl ToString) -> HGetAll {
+        HGetAll {
+            key: key.to_string(),
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("hgetall".to_string());
+        frame.push_string(self.key);
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<HGetAll, crate::Error> {
+        let key = parse.next_string()?;
+
+        Ok(HGetAll { key })
+    }
+
+    /// Replies with the fields in field order, each followed by its value.
+    /// Missing key is an empty hash.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let mut frame = Frame::array();
+
+        for (field, value) in engine.hash_get_all(&self.key)? {
+            frame.push_bulk(Bytes::from(field));
+            frame.push_bulk(value);
+        }
+
+        Ok(frame)
+    }
+}
+
+impl Push {
+    pub fn new(key: impl ToString, values: Vec<Bytes>, end: ListEnd) -> Push {
+        Push {
+            key: key.to_string(),
+            values,
+            end,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        let command_name = match self.end {
+            ListEnd::Left => "lpush",
+            ListEnd::Right => "rpush",
+        };
+
+        frame.push_string(command_name.to_string());
+        frame.push_string(self.key);
+
+        for value in self.values {
+            frame.push_bulk(value);
+        }
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse, end: ListEnd) -> Result<Push, crate::Error> {
+        let key = parse.next_string()?;
+        let mut values = vec![parse.next_bytes()?];
+
+        loop {
+            match parse.next_bytes() {
+                Ok(value) => values.push(value),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(Push { key, values, end })
+    }
+
+    /// Replies with the length of the list after the push.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let len = engine.list_push(&self.key, self.values, self.end)?;
+
+        Ok(Frame::Integer(len as i64))
+    }
+}
+
+impl Pop {
+    pub fn new(key: impl ToString, end: ListEnd) -> Pop {
+        Pop {
+            key: key.to_string(),
+            end,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        let command_name = match self.end {
+            ListEnd::Left => "lpop",
+            ListEnd::Right => "rpop",
+        };
+
+        frame.push_string(command_name.to_string());
+        frame.push_string(self.key);
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse, end: ListEnd) -> Result<Pop, crate::Error> {
+        let key = parse.next_string()?;
+
+        Ok(Pop { key, end })
+    }
+
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let resp_frame = match engine.list_pop(&self.key, self.end)? {
+            Some(value) => Frame::Bulk(value),
+            None => Frame::Null,
+        };
+
+        Ok(resp_frame)
+    }
+}
+
+impl LRange {
+    pub fn new(key: impl ToString, start: i64, stop: i64) -> LRange {
+        LRange {
+            key: key.to_string(),
+            start,
+            stop,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("lrange".to_string());
+        frame.push_string(self.key);
+        frame.push_string(self.start.to_string());
+        frame.push_string(self.stop.to_string());
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<LRange, crate::Error> {
+        let key = parse.next_string()?;
+        let start = parse.next_signed_int()?;
+        let stop = parse.next_signed_int()?;
+
+        Ok(LRange { key, start, stop })
+    }
+
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let mut frame = Frame::array();
+
+        for item in engine.list_range(&self.key, self.start, self.stop)? {
+            frame.push_bulk(item);
+        }
+
+        Ok(frame)
+    }
+}
+
+impl LLen {
+    pub fn new(key: impl ToString) -> LLen {
+        LLen {
+            key: key.to_string(),
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("llen".to_string());
+        frame.push_string(self.key);
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<LLen, crate::Error> {
+        let key = parse.next_string()?;
+
+        Ok(LLen { key })
+    }
+
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let len = engine.list_len(&self.key)?;
+
+        Ok(Frame::Integer(len as i64))
+    }
+}
+
+impl BLPop {
+    pub fn new(keys: Vec<String>, timeout: Option<Duration>) -> BLPop {
+        BLPop { keys, timeout }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("blpop".to_string());
+
+        for key in self.keys {
+            frame.push_string(key);
+        }
+
+        let timeout = self.timeout.map_or(0.0, |timeout| timeout.as_secs_f64());
+        frame.push_string(timeout.to_string());
+
+        frame
+    }
+
+    /// Keys are followed by the timeout in seconds, which may be fractional.
+    /// Timeout of 0 means waiting forever.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<BLPop, crate::Error> {
+        let mut args = vec![parse.next_string()?, parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(arg) => args.push(arg),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        const MSG: &str = "BLPOP timeout is not a non-negative number";
+
+        let timeout = args
+            .pop()
+            .and_then(|timeout| timeout.parse::<f64>().ok())
+            .ok_or(MSG)?;
+
+        let timeout = match Duration::try_from_secs_f64(timeout) {
+            Ok(timeout) if timeout.is_zero() => None,
+            Ok(timeout) => Some(timeout),
+            Err(_) => return Err(MSG.into()),
+        };
+
+        Ok(BLPop {
+            keys: args,
+            timeout,
+        })
+    }
+
+    /// Pops without waiting, replying with the key along with the item
+    /// or with null if all the lists are empty.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        for key in self.keys {
+            if let Some(value) = engine.list_pop(&key, ListEnd::Left)? {
+                let mut frame = Frame::array();
+
+                frame.push_bulk(Bytes::from(key));
+                frame.push_bulk(value);
+
+                return Ok(frame);
+            }
+        }
+
+        Ok(Frame::Null)
+    }
+}
+
+impl SAdd {
+    pub fn new(key: impl ToString, members: Vec<Bytes>) -> SAdd {
+        SAdd {
+            key: key.to_string(),
+            members,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("sadd".to_string());
+        frame.push_string(self.key);
+
+        for member in self.members {
+            frame.push_bulk(member);
+        }
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<SAdd, crate::Error> {
+        let key = parse.next_string()?;
+        let members = parse_members(parse)?;
+
+        Ok(SAdd { key, members })
+    }
+
+    /// Replies with the amount of members which have been added.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let added = engine.set_add(&self.key, self.members)?;
+
+        Ok(Frame::Integer(added as i64))
+    }
+}
+
+impl SRem {
+    pub fn new(key: impl ToString, members: Vec<Bytes>) -> SRem {
+        SRem {
+            key: key.to_string(),
+            members,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("srem".to_string());
+        frame.push_string(self.key);
+
+        for member in self.members {
+            frame.push_bulk(member);
+        }
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<SRem, crate::Error> {
+        let key = parse.next_string()?;
+        let members = parse_members(parse)?;
+
+        Ok(SRem { key, members })
+    }
+
+    /// Replies with the amount of members which have been removed.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let removed = engine.set_remove(&self.key, &self.members)?;
+
+        Ok(Frame::Integer(removed as i64))
+    }
+}
+
+impl SMembers {
+    pub fn new(key: impl ToString) -> SMembers {
+        SMembers {
+            key: key.to_string(),
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("smembers".to_string());
+        frame.push_string(self.key);
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<SMembers, crate::Error> {
+        let key = parse.next_string()?;
+
+        Ok(SMembers { key })
+    }
+
+    /// Replies with the members in member order. Missing key is an empty set.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let mut frame = Frame::array();
+
+        for member in engine.set_members(&self.key)? {
+            frame.push_bulk(member);
+        }
+
+        Ok(frame)
+    }
+}
+
+impl SIsMember {
+    pub fn new(key: impl ToString, member: Bytes) -> SIsMember {
+        SIsMember {
+            key: key.to_string(),
+            member,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("sismember".to_string());
+        frame.push_string(self.key);
+        frame.push_bulk(self.member);
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<SIsMember, crate::Error> {
+        let key = parse.next_string()?;
+        let member = parse.next_bytes()?;
+
+        Ok(SIsMember { key, member })
+    }
+
+    /// Replies with 1 if the member is in the set, 0 otherwise.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let is_member = engine.set_contains(&self.key, &self.member)?;
+
+        Ok(Frame::Integer(is_member as i64))
+    }
+}
+
+impl SInter {
+    pub fn new(keys: Vec<String>) -> SInter {
+        SInter { keys }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("sinter".to_string());
+
+        for key in self.keys {
+            frame.push_string(key);
+        }
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<SInter, crate::Error> {
+        let mut keys = vec![parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(key) => keys.push(key),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(SInter { keys })
+    }
+
+    /// Replies with the members common to all the sets, in member order.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let mut frame = Frame::array();
+
+        for member in engine.set_intersect(&self.keys)? {
+            frame.push_bulk(member);
+        }
+
+        Ok(frame)
+    }
+}
+
+/// At least one member, as for `SADD` and `SREM`.
+fn parse_members(parse: &mut Parse) -> Result<Vec<Bytes>, crate::Error> {
+    let mut members = vec![parse.next_bytes()?];
+
+    loop {
+        match parse.next_bytes() {
+            Ok(member) => members.push(member),
+            Err(ParseError::EndOfStream) => break,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(members)
+}
+
+impl ZAdd {
+    pub fn new(key: impl ToString, scores: Vec<(f64, Bytes)>) -> ZAdd {
+        ZAdd {
+            key: key.to_string(),
+            scores,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("zadd".to_string());
+        frame.push_string(self.key);
+
+        for (score, member) in self.scores {
+            frame.push_string(score.to_string());
+            frame.push_bulk(member);
+        }
+
+        frame
+    }
+
+    /// Every score has to be followed by its member.
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<ZAdd, crate::Error> {
+        let key = parse.next_string()?;
+        let mut scores = vec![(parse.next_float()?, parse.next_bytes()?)];
+
+        loop {
+            match parse.next_float() {
+                Ok(score) => scores.push((score, parse.next_bytes()?)),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(ZAdd { key, scores })
+    }
+
+    /// Replies with the amount of members which have been added rather than updated.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let added = engine.sorted_set_add(&self.key, self.scores)?;
+
+        Ok(Frame::Integer(added as i64))
+    }
+}
+
+impl ZRange {
+    pub fn new(key: impl ToString, start: i64, stop: i64, with_scores: bool) -> ZRange {
+        ZRange {
+            key: key.to_string(),
+            start,
+            stop,
+            with_scores,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("zrange".to_string());
+        frame.push_string(self.key);
+        frame.push_string(self.start.to_string());
+        frame.push_string(self.stop.to_string());
+
+        if self.with_scores {
+            frame.push_string("withscores".to_string());
+        }
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<ZRange, crate::Error> {
+        let key = parse.next_string()?;
+        let start = parse.next_signed_int()?;
+        let stop = parse.next_signed_int()?;
+        let with_scores = parse_with_scores(parse)?;
+
+        Ok(ZRange {
+            key,
+            start,
+            stop,
+            with_scores,
+        })
+    }
+
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let members = engine.sorted_set_range(&self.key, self.start, self.stop)?;
+
+        Ok(scored_members_frame(members, self.with_scores))
+    }
+}
+
+impl ZRangeByScore {
+    pub fn new(
+        key: impl ToString,
+        min: Bound<f64>,
+        max: Bound<f64>,
+        with_scores: bool,
+    ) -> ZRangeByScore {
+        ZRangeByScore {
+            key: key.to_string(),
+            min,
+            max,
+            with_scores,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("zrangebyscore".to_string());
+        frame.push_string(self.key);
+        frame.push_string(score_bound_to_string(self.min, f64::NEG_INFINITY));
+        frame.push_string(score_bound_to_string(self.max, f64::INFINITY));
+
+        if self.with_scores {
+            frame.push_string("withscores".to_string());
+        }
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<ZRangeByScore, crate::Error> {
+        let key = parse.next_string()?;
+        let min = parse_score_bound(&parse.next_string()?)?;
+        let max = parse_score_bound(&parse.next_string()?)?;
+        let with_scores = parse_with_scores(parse)?;
+
+        Ok(ZRangeByScore {
+            key,
+            min,
+            max,
+            with_scores,
+        })
+    }
+
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let members = engine.sorted_set_range_by_score(&self.key, self.min, self.max)?;
+
+        Ok(scored_members_frame(members, self.with_scores))
+    }
+}
+
+/// Optional `WITHSCORES` at the end of `ZRANGE` and `ZRANGEBYSCORE`.
+fn parse_with_scores(parse: &mut Parse) -> Result<bool, crate::Error> {
+    match parse.next_string() {
+        Ok(option) if option.eq_ignore_ascii_case("withscores") => Ok(true),
+        Ok(option) => Err(format!("unknown option {}", option).into()),
+        Err(ParseError::EndOfStream) => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Score, exclusive if prefixed with `(`.
+fn parse_score_bound(bound: &str) -> Result<Bound<f64>, crate::Error> {
+    let (bound, exclusive) = match bound.strip_prefix('(') {
+        Some(bound) => (bound, true),
+        None => (bound, false),
+    };
+
+    let score = bound
+        .parse::<f64>()
+        .ok()
+        .filter(|score| !score.is_nan())
+        .ok_or("protocol error; invalid score bound")?;
+
+    Ok(if exclusive {
+        Bound::Excluded(score)
+    } else {
+        Bound::Included(score)
+    })
+}
+
+/// Open bound is spelled as the infinity on its side.
+fn score_bound_to_string(bound: Bound<f64>, unbounded: f64) -> String {
+    match bound {
+        Bound::Included(score) => score.to_string(),
+        Bound::Excluded(score) => format!("({}", score),
+        Bound::Unbounded => unbounded.to_string(),
+    }
+}
+
+/// Members in the given order, each followed by its score if asked for.
+fn scored_members_frame(members: Vec<(Bytes, f64)>, with_scores: bool) -> Frame {
+    let mut frame = Frame::array();
+
+    for (member, score) in members {
+        frame.push_bulk(member);
+
+        if with_scores {
+            frame.push_bulk(Bytes::from(score.to_string()));
+        }
+    }
+
+    frame
+}
+
+impl ZRank {
+    pub fn new(key: impl ToString, member: Bytes) -> ZRank {
+        ZRank {
+            key: key.to_string(),
+            member,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("zrank".to_string());
+        frame.push_string(self.key);
+        frame.push_bulk(self.member);
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<ZRank, crate::Error> {
+        let key = parse.next_string()?;
+        let member = parse.next_bytes()?;
+
+        Ok(ZRank { key, member })
+    }
+
+    /// Replies with the rank of the member, or with null if it's not in the sorted set.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let resp_frame = match engine.sorted_set_rank(&self.key, &self.member)? {
+            Some(rank) => Frame::Integer(rank as i64),
+            None => Frame::Null,
+        };
+
+        Ok(resp_frame)
+    }
+}
+
+impl ZIncrBy {
+    pub fn new(key: impl ToString, delta: f64, member: Bytes) -> ZIncrBy {
+        ZIncrBy {
+            key: key.to_string(),
+            delta,
+            member,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("zincrby".to_string());
+        frame.push_string(self.key);
+        frame.push_string(self.delta.to_string());
+        frame.push_bulk(self.member);
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<ZIncrBy, crate::Error> {
+        let key = parse.next_string()?;
+        let delta = parse.next_float()?;
+        let member = parse.next_bytes()?;
+
+        Ok(ZIncrBy { key, delta, member })
+    }
+
+    /// Replies with the score after the increment.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let resp_frame = match engine.sorted_set_increment(&self.key, self.member, self.delta)? {
+            Some(score) => Frame::Bulk(Bytes::from(score.to_string())),
+            None => Frame::Error(FrameErrorKind::NotAFloat),
+        };
+
+        Ok(resp_frame)
+    }
+}
+
+impl JsonSet {
+    pub fn new(key: impl ToString, path: JsonPath, value: Json) -> JsonSet {
+        JsonSet {
+            key: key.to_string(),
+            path,
+            value,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("json.set".to_string());
+        frame.push_string(self.key);
+        frame.push_string(self.path.to_string());
+        frame.push_bulk(Bytes::from(self.value.to_string()));
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<JsonSet, crate::Error> {
+        let key = parse.next_string()?;
+        let path = JsonPath::parse(&parse.next_string()?)?;
+        let value = serde_json::from_slice(&parse.next_bytes()?)
+            .map_err(|_| "protocol error; invalid JSON value")?;
+
+        Ok(JsonSet { key, path, value })
+    }
+
+    /// Replies with null if nothing was set because the path isn't there.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let resp_frame = match engine.json_set(&self.key, &self.path, self.value)? {
+            true => Frame::Simple("OK".to_string()),
+            false => Frame::Null,
+        };
+
+        Ok(resp_frame)
+    }
+}
+
+impl JsonGet {
+    pub fn new(key: impl ToString, path: JsonPath) -> JsonGet {
+        JsonGet {
+            key: key.to_string(),
+            path,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("json.get".to_string());
+        frame.push_string(self.key);
+        frame.push_string(self.path.to_string());
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<JsonGet, crate::Error> {
+        let key = parse.next_string()?;
+        let path = parse_json_path(parse)?;
+
+        Ok(JsonGet { key, path })
+    }
+
+    /// Replies with the value serialized as JSON, or with null if either the key
+    /// or the path is missing.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let resp_frame = match engine.json_get(&self.key, &self.path)? {
+            Some(value) => Frame::Bulk(Bytes::from(value.to_string())),
+            None => Frame::Null,
+        };
+
+        Ok(resp_frame)
+    }
+}
+
+impl JsonDel {
+    pub fn new(key: impl ToString, path: JsonPath) -> JsonDel {
+        JsonDel {
+            key: key.to_string(),
+            path,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("json.del".to_string());
+        frame.push_string(self.key);
+        frame.push_string(self.path.to_string());
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<JsonDel, crate::Error> {
+        let key = parse.next_string()?;
+        let path = parse_json_path(parse)?;
+
+        Ok(JsonDel { key, path })
+    }
+
+    /// Replies with the amount of values which have been removed, 0 or 1.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let removed = engine.json_delete(&self.key, &self.path)?;
+
+        Ok(Frame::Integer(removed as i64))
+    }
+}
+
+/// Optional path, the root of the document if not given.
+fn parse_json_path(parse: &mut Parse) -> Result<JsonPath, crate::Error> {
+    match parse.next_string() {
+        Ok(path) => JsonPath::parse(&path),
+        Err(ParseError::EndOfStream) => Ok(JsonPath::root()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+impl JsonNumIncrBy {
+    pub fn new(key: impl ToString, path: JsonPath, delta: Number) -> JsonNumIncrBy {
+        JsonNumIncrBy {
+            key: key.to_string(),
+            path,
+            delta,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("json.numincrby".to_string());
+        frame.push_string(self.key);
+        frame.push_string(self.path.to_string());
+        frame.push_string(self.delta.to_string());
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<JsonNumIncrBy, crate::Error> {
+        let key = parse.next_string()?;
+        let path = JsonPath::parse(&parse.next_string()?)?;
+        let delta = parse
+            .next_string()?
+            .parse()
+            .map_err(|_| "protocol error; invalid number")?;
+
+        Ok(JsonNumIncrBy { key, path, delta })
+    }
+
+    /// Replies with the number after the increment, or with null if either the key
+    /// or the path is missing.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let resp_frame = match engine.json_increment(&self.key, &self.path, &self.delta)? {
+            Some(number) => Frame::Bulk(Bytes::from(number.to_string())),
+            None => Frame::Null,
+        };
+
+        Ok(resp_frame)
+    }
+}
+
+impl Find {
+    pub fn new(index: impl ToString, value: Json) -> Find {
+        Find {
+            index: index.to_string(),
+            value,
+        }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("find".to_string());
+        frame.push_string(self.index);
+        frame.push_bulk(Bytes::from(self.value.to_string()));
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Find, crate::Error> {
+        let index = parse.next_string()?;
+        let value = serde_json::from_slice(&parse.next_bytes()?)
+            .map_err(|_| "protocol error; invalid JSON value")?;
+
+        Ok(Find { index, value })
+    }
+
+    /// Replies with the keys in key order, or with an error if there is no such index.
+    pub fn execute(self, engine: &dyn StorageEngine) -> Result<Frame, crate::Error> {
+        let keys = match engine.find(&self.index, &self.value)? {
+            Some(keys) => keys,
+            None => return Ok(Frame::Error(FrameErrorKind::NoSuchIndex)),
+        };
+
+        let mut frame = Frame::array();
+
+        for key in keys {
+            frame.push_bulk(Bytes::from(key.into_bytes()));
+        }
+
+        Ok(frame)
+    }
+}
+
+impl Multi {
+    pub fn new() -> Multi {
+        Multi
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("multi".to_string());
+
+        frame
+    }
+}
+
+impl Exec {
+    pub fn new() -> Exec {
+        Exec
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("exec".to_string());
+
+        frame
+    }
+}
+
+impl Discard {
+    pub fn new() -> Discard {
+        Discard
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("discard".to_string());
+
+        frame
+    }
+}
+
+impl Watch {
+    pub fn new(keys: Vec<String>) -> Watch {
+        Watch { keys }
+    }
+
+    pub fn into_frame(self) -> Frame {
+        let mut frame = Frame::array();
+
+        frame.push_string("watch".to_string());
+
+        for key in self.keys {
+            frame.push_string(key);
+        }
+
+        frame
+    }
+
+    pub(crate) fn parse_frames(parse: &mut Parse) -> Result<Watch, crate::Error> {
+        let mut keys = vec![parse.next_string()?];
+
+        loop {
+            match parse.next_string() {
+                Ok(key) => keys.push(key),
+                Err(ParseError::EndOfStream) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
 
-        Ok(())
+        Ok(Watch { keys })
     }
 }
@@ -1,4 +1,6 @@
+use std::future::Future;
 use std::io::{self, Cursor};
+use std::pin::Pin;
 
 use bytes::{Buf, BytesMut};
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
@@ -54,7 +56,7 @@ impl Connection {
 
                 self.buffer.advance(len);
 
-                return Ok(Some(frame));
+                Ok(Some(frame))
             }
             Err(Incomplete) => Ok(None),
             _ => todo!(),
@@ -62,53 +64,62 @@ impl Connection {
     }
 
     pub async fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
-        match frame {
-            Frame::Array(val) => {
-                self.stream.write_u8(b'*').await?;
-                self.write_decimal(val.len() as u64).await?;
-
-                for entry in val {
-                    self.write_value(entry).await?;
-                }
-            }
-            _ => {
-                self.write_value(frame).await?;
-            }
-        }
+        self.write_value(frame).await?;
 
         self.stream.flush().await
     }
 
-    async fn write_value(&mut self, frame: &Frame) -> io::Result<()> {
-        match frame {
-            Frame::Bulk(val) => {
-                let len = val.len();
+    /// Boxed, as arrays are written recursively, e.g. the reply of `EXEC`
+    /// holding the arrays some of the queued commands reply with.
+    fn write_value<'a>(
+        &'a mut self,
+        frame: &'a Frame,
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send + 'a>> {
+        Box::pin(async move {
+            match frame {
+                Frame::Array(val) => {
+                    self.stream.write_u8(b'*').await?;
+                    self.write_decimal(val.len() as i64).await?;
+
+                    for entry in val {
+                        self.write_value(entry).await?;
+                    }
+                }
+                Frame::Bulk(val) => {
+                    let len = val.len();
 
-                self.stream.write_u8(b'$').await?;
-                self.write_decimal(len as u64).await?;
-                self.stream.write_all(val).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            Frame::Simple(string) => {
-                self.stream.write_u8(b'+').await?;
-                self.stream.write_all(string.as_bytes()).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            // TODO: error variants as enum?
-            Frame::Error(frame_error) => {
-                self.stream.write_u8(b'-').await?;
-                self.stream
-                    .write_all(frame_error.to_string().as_bytes())
-                    .await?;
-                self.stream.write_all(b"\r\n").await?;
+                    self.stream.write_u8(b'$').await?;
+                    self.write_decimal(len as i64).await?;
+                    self.stream.write_all(val).await?;
+                    self.stream.write_all(b"\r\n").await?;
+                }
+                Frame::Simple(string) => {
+                    self.stream.write_u8(b'+').await?;
+                    self.stream.write_all(string.as_bytes()).await?;
+                    self.stream.write_all(b"\r\n").await?;
+                }
+                Frame::Integer(val) => {
+                    self.stream.write_u8(b':').await?;
+                    self.write_decimal(*val).await?;
+                }
+                Frame::Null => {
+                    self.stream.write_all(b"_\r\n").await?;
+                }
+                // TODO: error variants as enum?
+                Frame::Error(frame_error) => {
+                    self.stream.write_u8(b'-').await?;
+                    self.stream
+                        .write_all(frame_error.to_string().as_bytes())
+                        .await?;
+                    self.stream.write_all(b"\r\n").await?;
+                }
             }
-            _ => todo!(),
-        }
 
-        Ok(())
+            Ok(())
+        })
     }
 
-    async fn write_decimal(&mut self, val: u64) -> io::Result<()> {
+    async fn write_decimal(&mut self, val: i64) -> io::Result<()> {
         use std::io::Write;
 
         // Convert the value to a string
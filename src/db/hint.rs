@@ -0,0 +1,185 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use super::segment::{self, SegmentId};
+
+/*
+    Hint file layout (all integers are big-endian):
+
+    +---------+
+    | version |
+    |   u8    |
+    +---------+
+    +-----------+------------+--------+-----+---------+-----+
+    | timestamp | expires_at | offset | len | key_len | key |  ... one entry per live record
+    |    u64    |    u64     |  u64   | u64 |   u32   |     |
+    +-----------+------------+--------+-----+---------+-----+
+    +-------+
+    | crc32 |  checksum of everything above
+    |  u32  |
+    +-------+
+
+    Hints are only written for merge outputs, which never contain tombstones,
+    so every entry describes a live record within the segment. Zero `expires_at`
+    stands for a record which never expires.
+
+    Hint files of any other version are ignored, and the segment is scanned instead.
+*/
+
+const HINT_VERSION: u8 = 2;
+
+const ENTRY_HEADER_LEN: usize = 36;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct HintEntry {
+    pub(crate) key: String,
+    pub(crate) offset: u64,
+    pub(crate) len: u64,
+    pub(crate) timestamp: u64,
+    pub(crate) expires_at: Option<u64>,
+}
+
+/// Writes hint file for the segment, replacing the previous one atomically.
+pub(crate) fn write(dir: &Path, id: SegmentId, entries: &[HintEntry]) -> Result<(), crate::Error> {
+    let mut buf = BytesMut::new();
+
+    buf.put_u8(HINT_VERSION);
+
+    for entry in entries {
+        buf.put_u64(entry.timestamp);
+        buf.put_u64(entry.expires_at.unwrap_or(0));
+        buf.put_u64(entry.offset);
+        buf.put_u64(entry.len);
+        buf.put_u32(entry.key.len() as u32);
+        buf.put_slice(entry.key.as_bytes());
+    }
+
+    let crc = crc32fast::hash(&buf);
+    buf.put_u32(crc);
+
+    let tmp_path = segment::hint_tmp_path(dir, id);
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(&buf)?;
+    file.sync_all()?;
+
+    fs::rename(&tmp_path, segment::hint_path(dir, id))?;
+    segment::sync_dir(dir)?;
+
+    Ok(())
+}
+
+/// Reads hint file of the segment. `None` means the segment has to be scanned
+/// instead, either because there is no hint file or because it can't be trusted.
+pub(crate) fn read(dir: &Path, id: SegmentId) -> Result<Option<Vec<HintEntry>>, crate::Error> {
+    let path = segment::hint_path(dir, id);
+
+    let buf = match fs::read(&path) {
+        Ok(buf) => buf,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    match decode(&buf) {
+        Some(entries) => Ok(Some(entries)),
+        None => {
+            eprintln!("ignoring corrupted hint file {}", path.display());
+            Ok(None)
+        }
+    }
+}
+
+fn decode(buf: &[u8]) -> Option<Vec<HintEntry>> {
+    if buf.len() < 4 {
+        return None;
+    }
+
+    let (mut src, mut trailer) = buf.split_at(buf.len() - 4);
+
+    if trailer.get_u32() != crc32fast::hash(src) {
+        return None;
+    }
+
+    if !src.has_remaining() || src.get_u8() != HINT_VERSION {
+        return None;
+    }
+
+    let mut entries = vec![];
+
+    while src.has_remaining() {
+        if src.remaining() < ENTRY_HEADER_LEN {
+            return None;
+        }
+
+        let timestamp = src.get_u64();
+        let expires_at = Some(src.get_u64()).filter(|&expires_at| expires_at != 0);
+        let offset = src.get_u64();
+        let len = src.get_u64();
+        let key_len = src.get_u32() as usize;
+
+        if src.remaining() < key_len {
+            return None;
+        }
+
+        let key = String::from_utf8(src[..key_len].to_vec()).ok()?;
+        src.advance(key_len);
+
+        entries.push(HintEntry {
+            key,
+            offset,
+            len,
+            timestamp,
+            expires_at,
+        });
+    }
+
+    Some(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hint_roundtrip() -> Result<(), crate::Error> {
+        let dir = std::env::temp_dir().join("kv_db_hint_roundtrip");
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+
+        let entries = vec![
+            HintEntry {
+                key: "first".to_string(),
+                offset: 0,
+                len: 28,
+                timestamp: 1,
+                expires_at: None,
+            },
+            HintEntry {
+                key: "second".to_string(),
+                offset: 28,
+                len: 29,
+                timestamp: 2,
+                expires_at: Some(3),
+            },
+        ];
+
+        write(&dir, 1, &entries)?;
+
+        assert_eq!(read(&dir, 1)?, Some(entries));
+        assert_eq!(read(&dir, 2)?, None);
+
+        // Flipped bit anywhere makes the whole file untrusted
+        let path = segment::hint_path(&dir, 1);
+        let mut buf = fs::read(&path)?;
+        buf[10] ^= 0x01;
+        fs::write(&path, buf)?;
+
+        assert_eq!(read(&dir, 1)?, None);
+
+        Ok(())
+    }
+}
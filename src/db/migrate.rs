@@ -0,0 +1,159 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use serde::Deserialize;
+
+use super::record::FileRecord;
+use super::segment::{self, SegmentId};
+
+/// Record as it used to be stored before the binary format: a JSON object per line,
+/// with the timestamp in seconds.
+#[derive(Deserialize, Debug)]
+struct JsonRecord {
+    key: String,
+    value: Option<String>,
+    timestamp: u64,
+    is_tombstone: bool,
+}
+
+/// Converts every segment still written as newline-delimited JSON into the binary
+/// record format. Segments already in the binary format are left as is, so running
+/// it on every startup only costs reading a single record per segment.
+///
+/// Each segment is converted into a temporary file which atomically replaces the
+/// original one, so an interrupted migration is simply picked up on the next run.
+pub(crate) fn migrate_json_segments(dir: &Path) -> Result<(), crate::Error> {
+    for id in segment::list_segments(dir)? {
+        let _ = fs::remove_file(migrate_tmp_path(dir, id));
+
+        let path = segment::segment_path(dir, id);
+
+        if !is_json_segment(&path)? {
+            continue;
+        }
+
+        let records = migrate_segment(dir, id, &migrate_tmp_path(dir, id))?;
+
+        fs::rename(migrate_tmp_path(dir, id), &path)?;
+        segment::sync_dir(dir)?;
+
+        eprintln!(
+            "migrated {} records of {} into the binary format",
+            records,
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// A segment is considered JSON if its first line is a JSON record, unless that line
+/// is a binary record whose checksum happens to add up too.
+fn is_json_segment(path: &Path) -> Result<bool, crate::Error> {
+    let mut head = vec![];
+    File::open(path)?.take(64 * 1024).read_to_end(&mut head)?;
+
+    if FileRecord::decode(&Bytes::copy_from_slice(&head)).is_ok() {
+        return Ok(false);
+    }
+
+    let first_line = head.split(|byte| *byte == b'\n').next().unwrap_or_default();
+
+    Ok(serde_json::from_slice::<JsonRecord>(first_line).is_ok())
+}
+
+fn migrate_segment(dir: &Path, id: SegmentId, tmp_path: &Path) -> Result<u64, crate::Error> {
+    let reader = BufReader::new(File::open(segment::segment_path(dir, id))?);
+    let mut file = File::create(tmp_path)?;
+
+    let mut records = 0;
+    let mut lines = reader.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        let line = line?;
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let json_record: JsonRecord = match serde_json::from_str(&line) {
+            Ok(json_record) => json_record,
+            // Last line could be torn by a crash in the middle of writing it
+            Err(_) if lines.peek().is_none() => {
+                let quarantine_path = segment::quarantine(dir, id, line.as_bytes())?;
+
+                eprintln!(
+                    "dropped partial trailing record of segment {}, moved into {}",
+                    id,
+                    quarantine_path.display()
+                );
+
+                break;
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let record = FileRecord::with_timestamp(
+            json_record.key,
+            json_record.value.map(Bytes::from),
+            json_record.is_tombstone,
+            json_record.timestamp * 1000,
+        );
+
+        file.write_all(&record.encode())?;
+
+        records += 1;
+    }
+
+    file.sync_all()?;
+
+    Ok(records)
+}
+
+fn migrate_tmp_path(dir: &Path, id: SegmentId) -> PathBuf {
+    dir.join(format!("{:06}.migrate.tmp", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_segment_migration() -> Result<(), crate::Error> {
+        let dir = std::env::temp_dir().join("kv_db_json_migration");
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+
+        let json_lines = concat!(
+            "{\"key\":\"dogs\",\"value\":\"3\",\"timestamp\":1699084615,\"is_tombstone\":false}\n",
+            "{\"key\":\"cats\",\"value\":null,\"timestamp\":1699084616,\"is_tombstone\":true}\n",
+            "{\"key\":\"birds\",\"value\":\"",
+        );
+        fs::write(segment::segment_path(&dir, 1), json_lines)?;
+
+        migrate_json_segments(&dir)?;
+
+        let mut reader = BufReader::new(File::open(segment::segment_path(&dir, 1))?);
+
+        let (record, _) = FileRecord::read_from(&mut reader)?.unwrap();
+        assert_eq!(record.key, "dogs");
+        assert_eq!(record.value, Some(Bytes::from("3")));
+        assert_eq!(record.timestamp, 1699084615000);
+
+        let (record, _) = FileRecord::read_from(&mut reader)?.unwrap();
+        assert_eq!(record.key, "cats");
+        assert!(record.is_tombstone);
+
+        assert!(FileRecord::read_from(&mut reader)?.is_none());
+
+        // Running it again on binary segments is a no-op
+        let migrated = fs::read(segment::segment_path(&dir, 1))?;
+        migrate_json_segments(&dir)?;
+        assert_eq!(fs::read(segment::segment_path(&dir, 1))?, migrated);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,2761 @@
+use std::collections::{hash_map, BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use serde_json::Value as Json;
+
+use crate::engine::{self, hash, Fields, KeyRange, Kind, StorageEngine, Update, Value};
+
+mod hint;
+mod migrate;
+mod record;
+mod secondary;
+mod segment;
+
+use hint::HintEntry;
+pub(crate) use record::now_millis;
+pub use record::{DecodeError, FileRecord};
+pub use secondary::IndexDefinition;
+use secondary::SecondaryIndexes;
+use segment::{SegmentId, SegmentReader, Segments};
+
+const LEGACY_STORAGE_FILENAME: &str = "store.dat";
+
+// TODO: have index as a singleton?
+pub struct DbHolder {
+    pub db: Db,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Directory holding segment files
+    pub dir: PathBuf,
+    /// Active segment is closed and a new one is started once appending
+    /// a record would make it exceed this size
+    pub max_segment_size: u64,
+    pub durability: Durability,
+    /// Closed segments are memory-mapped and values are served straight from the mapping
+    pub mmap_reads: bool,
+    /// Overwritten, deleted and expired values can still be read as of a point in time
+    /// within this window, zero turns history off
+    pub history_retention: Duration,
+    /// Secondary indexes kept in memory and rebuilt when the store is opened
+    pub indexes: Vec<IndexDefinition>,
+}
+
+/// When appended records are flushed from the OS page cache to the disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Durability {
+    /// Write is only acknowledged after it has been synced to disk. Concurrent
+    /// writes waiting for a sync are committed together by a single fsync.
+    Always,
+    /// Writes are synced in the background once per interval, so up to
+    /// an interval worth of acknowledged writes could be lost on power failure.
+    Interval(Duration),
+    /// Syncing is left entirely up to the OS.
+    Never,
+}
+
+#[derive(Clone)]
+pub struct Db {
+    index: Arc<Mutex<Index>>,
+    config: Arc<Config>,
+    /// Ensures only a single merge of closed segments is running at a time
+    compaction_lock: Arc<Mutex<()>>,
+    /// Sequence number of the last record known to be on disk. Lock is held for
+    /// the duration of fsync, so that writers queued behind it could find their
+    /// records already synced by the time they get it.
+    synced_seq: Arc<Mutex<u64>>,
+}
+
+#[derive(Debug)]
+struct Index {
+    /// Ordered by key, so that key ranges could be iterated over
+    records: BTreeMap<String, ValueMetadata>,
+    /// Fields written on top of the full records of hashes, which are only
+    /// folded into new full records by compaction
+    field_updates: BTreeMap<String, FieldUpdates>,
+    /// Keys with an expiration time, ordered by it, so that the expired ones
+    /// could be purged without going through all the records
+    expiries: BTreeSet<(u64, String)>,
+    segments: Segments,
+    /// Sequence number of the last appended record
+    written_seq: u64,
+    /// Timestamp of the last appended record, which is also its version
+    last_version: u64,
+    /// Values the keys had before the current ones, oldest first. Entries pointing
+    /// to the segments are carried over by compaction until they are old enough.
+    history: BTreeMap<String, Vec<PastVersion>>,
+    /// Keys along with the time their history has grown, which is roughly the order
+    /// the entries become old enough to be dropped in
+    superseded: VecDeque<(u64, String)>,
+    /// In milliseconds, zero when no history is kept
+    history_retention: u64,
+    /// History only covers the writes made since the store was opened
+    opened_at: u64,
+    secondary: SecondaryIndexes,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueMetadata {
+    segment_id: SegmentId,
+    offset: u64,
+    len: u64,
+    expires_at: Option<u64>,
+    /// Timestamp of the record
+    version: u64,
+}
+
+/// Location of the last update of a hash field.
+#[derive(Debug, Clone, PartialEq)]
+struct FieldUpdate {
+    meta: ValueMetadata,
+    is_deletion: bool,
+}
+
+/// Last updates of the fields of a hash, by field.
+type FieldUpdates = BTreeMap<String, FieldUpdate>;
+
+/// State of the key from the version on, until the next one.
+#[derive(Debug, Clone, PartialEq)]
+enum PastVersion {
+    Value(ValueMetadata),
+    Deleted(u64),
+}
+
+/// Live records within a key range, read lazily from the locations captured
+/// when the range was created. Goes backwards when reversed with `rev`.
+pub struct Range {
+    entries: std::vec::IntoIter<(String, ValueMetadata)>,
+    /// Updates to be folded into the hashes among the entries
+    field_updates: HashMap<String, FieldUpdates>,
+    readers: HashMap<SegmentId, SegmentReader>,
+}
+
+/// Key along with its location before and after the merge
+type Relocation = (String, ValueMetadata, ValueMetadata);
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            dir: PathBuf::from("store"),
+            max_segment_size: 4 * 1024 * 1024,
+            durability: Durability::Interval(Duration::from_secs(1)),
+            mmap_reads: false,
+            history_retention: Duration::from_secs(5 * 60),
+            indexes: vec![],
+        }
+    }
+}
+
+impl DbHolder {
+    pub fn new(config: Config) -> Result<DbHolder, crate::Error> {
+        fs::create_dir_all(&config.dir)?;
+        segment::adopt_legacy_file(Path::new(LEGACY_STORAGE_FILENAME), &config.dir)?;
+
+        Ok(DbHolder {
+            db: Db::new(config)?,
+        })
+    }
+
+    pub fn db(&self) -> Db {
+        self.db.clone()
+    }
+}
+
+impl Db {
+    pub fn new(config: Config) -> Result<Db, crate::Error> {
+        fs::create_dir_all(&config.dir)?;
+
+        segment::recover(&config.dir)?;
+        migrate::migrate_json_segments(&config.dir)?;
+
+        let index = Db::rehydrate_index_from_disk(&config)?;
+
+        Ok(Db {
+            index: Arc::new(Mutex::new(index)),
+            config: Arc::new(config),
+            compaction_lock: Arc::new(Mutex::new(())),
+            synced_seq: Arc::new(Mutex::new(0)),
+        })
+    }
+
+    pub fn durability(&self) -> Durability {
+        self.config.durability
+    }
+
+    /// Flushes everything appended so far to disk.
+    pub fn sync(&self) -> Result<(), crate::Error> {
+        let written_seq = self.index.lock().unwrap().written_seq;
+
+        self.sync_up_to(written_seq)
+    }
+
+    /// Makes sure the record with the given sequence number and all the preceding ones
+    /// are on disk. A single fsync covers all the records appended before it started,
+    /// so writers that were waiting on the lock meanwhile usually have nothing left to do.
+    fn sync_up_to(&self, seq: u64) -> Result<(), crate::Error> {
+        let mut synced_seq = self.synced_seq.lock().unwrap();
+
+        if *synced_seq >= seq {
+            return Ok(());
+        }
+
+        // Records in the segments closed before are synced on rollover. Index lock
+        // is released before fsync, so that appending isn't blocked meanwhile.
+        let (written_seq, active_file) = {
+            let mut index_state = self.index.lock().unwrap();
+            let active_segment = index_state.segments.active;
+
+            (
+                index_state.written_seq,
+                index_state.segments.file(active_segment)?,
+            )
+        };
+
+        active_file.sync_data()?;
+
+        *synced_seq = written_seq;
+
+        Ok(())
+    }
+
+    /// Holds the writer back until its record satisfies the durability policy.
+    fn wait_durable(&self, seq: u64) -> Result<(), crate::Error> {
+        match self.config.durability {
+            Durability::Always => self.sync_up_to(seq),
+            Durability::Interval(_) | Durability::Never => Ok(()),
+        }
+    }
+
+    /// Merges all closed segments into a single one, leaving only the most recent
+    /// version of every live key and dropping tombstones and expired records along the way.
+    /// Past versions still within the history retention window are carried over as well.
+    /// Field updates of a hash are folded into its full record, as long as both are there.
+    ///
+    /// Closed segments are immutable, so the index lock is only taken to snapshot the
+    /// live records at the beginning and to swap their locations at the end. Writes to
+    /// the active segment proceed as usual while the merge output is being written.
+    pub fn run_compaction(&self) -> Result<(), crate::Error> {
+        let _compaction_guard = self.compaction_lock.lock().unwrap();
+
+        let (closed_segments, closed_readers, live_records, past_records, field_updates) = {
+            let mut index_state = self.index.lock().unwrap();
+            let now = now_millis();
+
+            // Expired records are only carried over as history, if at all
+            let expired_keys: Vec<String> = index_state
+                .records
+                .iter()
+                .filter(|(_, meta)| meta.is_expired(now))
+                .map(|(key, _)| key.clone())
+                .collect();
+
+            for key in expired_keys {
+                index_state.remove(&key);
+            }
+
+            index_state.prune_history(now);
+
+            let closed = &index_state.segments.closed;
+
+            let live_records: Vec<(String, ValueMetadata)> = index_state
+                .records
+                .iter()
+                .filter(|(_, meta)| closed.contains(&meta.segment_id))
+                .map(|(key, meta)| (key.clone(), meta.clone()))
+                .collect();
+
+            let past_records: Vec<(String, ValueMetadata)> = index_state
+                .history
+                .iter()
+                .flat_map(|(key, past)| past.iter().map(move |past| (key, past)))
+                .filter_map(|(key, past)| match past {
+                    PastVersion::Value(meta) if closed.contains(&meta.segment_id) => {
+                        Some((key.clone(), meta.clone()))
+                    }
+                    _ => None,
+                })
+                .collect();
+
+            // Field updates come after the full record of their hash, so the ones
+            // within closed segments always have it there to be folded into
+            let field_updates: BTreeMap<String, FieldUpdates> = index_state
+                .field_updates
+                .iter()
+                .map(|(key, updates)| {
+                    let updates: FieldUpdates = updates
+                        .iter()
+                        .filter(|(_, update)| closed.contains(&update.meta.segment_id))
+                        .map(|(field, update)| (field.clone(), update.clone()))
+                        .collect();
+
+                    (key.clone(), updates)
+                })
+                .filter(|(_, updates)| !updates.is_empty())
+                .collect();
+
+            let closed_segments: Vec<SegmentId> = closed.iter().copied().collect();
+
+            let mut closed_readers = HashMap::new();
+
+            for &segment_id in closed_segments.iter() {
+                closed_readers.insert(segment_id, index_state.segments.reader(segment_id)?);
+            }
+
+            (
+                closed_segments,
+                closed_readers,
+                live_records,
+                past_records,
+                field_updates,
+            )
+        };
+
+        if field_updates.is_empty()
+            && !self.is_worth_merging(&closed_segments, &live_records, &past_records)?
+        {
+            return Ok(());
+        }
+
+        // Merge output takes the place of the most recent closed segment, which keeps
+        // it ordered before the active segment
+        let target = *closed_segments.last().unwrap();
+        let dir = self.config.dir.as_path();
+
+        let merged = self.write_merge_file(
+            target,
+            &closed_readers,
+            live_records,
+            past_records,
+            &field_updates,
+        );
+
+        let (merged_records, hint_entries) = match merged {
+            Ok(merged) => merged,
+            Err(err) => {
+                let _ = fs::remove_file(segment::merge_tmp_path(dir, target));
+                return Err(err);
+            }
+        };
+
+        // Commit point: from now on the merge is rolled forward even after a crash
+        fs::rename(
+            segment::merge_tmp_path(dir, target),
+            segment::merge_path(dir, target),
+        )?;
+        segment::sync_dir(dir)?;
+
+        // Files are swapped outside of the lock, so that appends aren't held up by it.
+        // Handles of all the merged segments have been opened before the merge, and
+        // keep reading the replaced files until the index points to the merged one.
+        segment::finish_merge(dir, target)?;
+
+        let mut index_state = self.index.lock().unwrap();
+
+        for (key, old_meta, new_meta) in merged_records {
+            index_state.relocate(&key, &old_meta, new_meta);
+        }
+
+        for (key, folded) in field_updates {
+            index_state.drop_field_updates(&key, &folded);
+        }
+
+        index_state.segments.replace_merged(target);
+
+        drop(index_state);
+
+        // Merge is complete without the hint file, startup would just be slower
+        if let Err(err) = hint::write(dir, target, &hint_entries) {
+            eprintln!("failed to write hint file for segment {}", target);
+            dbg!(err);
+        }
+
+        Ok(())
+    }
+
+    /// Single closed segment is only rewritten if it has some garbage in it.
+    fn is_worth_merging(
+        &self,
+        closed_segments: &[SegmentId],
+        live_records: &[(String, ValueMetadata)],
+        past_records: &[(String, ValueMetadata)],
+    ) -> Result<bool, crate::Error> {
+        match closed_segments {
+            [] => Ok(false),
+            [segment_id] => {
+                let path = segment::segment_path(&self.config.dir, *segment_id);
+                let segment_size = fs::metadata(path)?.len();
+                let kept_size: u64 = live_records
+                    .iter()
+                    .chain(past_records)
+                    .map(|(_, meta)| meta.len)
+                    .sum();
+
+                Ok(kept_size < segment_size)
+            }
+            _ => Ok(true),
+        }
+    }
+
+    /// Past records are marked as such, so that they are never indexed as the current
+    /// ones after a restart, and are left out of the hint file.
+    fn write_merge_file(
+        &self,
+        target: SegmentId,
+        closed_readers: &HashMap<SegmentId, SegmentReader>,
+        live_records: Vec<(String, ValueMetadata)>,
+        past_records: Vec<(String, ValueMetadata)>,
+        field_updates: &BTreeMap<String, FieldUpdates>,
+    ) -> Result<(Vec<Relocation>, Vec<HintEntry>), crate::Error> {
+        let mut file = File::create(segment::merge_tmp_path(&self.config.dir, target))?;
+
+        let mut merged_records = Vec::with_capacity(live_records.len() + past_records.len());
+        let mut hint_entries = Vec::with_capacity(live_records.len());
+        let mut offset = 0;
+
+        let records = live_records
+            .into_iter()
+            .map(|(key, meta)| (key, meta, false))
+            .chain(
+                past_records
+                    .into_iter()
+                    .map(|(key, meta)| (key, meta, true)),
+            );
+
+        for (key, old_meta, is_historical) in records {
+            let mut record = Db::retrieve(&closed_readers[&old_meta.segment_id], &old_meta)?;
+
+            match field_updates.get(&key) {
+                Some(updates) if !is_historical => Db::fold(&mut record, updates, closed_readers)?,
+                _ => {}
+            }
+
+            // Only the live records of a batch are carried over, each on its own
+            record.batch_continues = false;
+            record.is_historical = is_historical;
+            let encoded_rec = record.encode();
+            let len = encoded_rec.len() as u64;
+
+            file.write_all(&encoded_rec)?;
+
+            let new_meta = ValueMetadata {
+                segment_id: target,
+                offset,
+                len,
+                expires_at: record.expires_at,
+                version: record.timestamp,
+            };
+
+            if !is_historical {
+                hint_entries.push(HintEntry {
+                    key: key.clone(),
+                    offset,
+                    len,
+                    timestamp: record.timestamp,
+                    expires_at: record.expires_at,
+                });
+            }
+
+            merged_records.push((key, old_meta, new_meta));
+
+            offset += len;
+        }
+
+        file.sync_all()?;
+
+        Ok((merged_records, hint_entries))
+    }
+
+    fn rehydrate_index_from_disk(config: &Config) -> Result<Index, crate::Error> {
+        let dir = config.dir.as_path();
+        let segments = segment::list_segments(dir)?;
+
+        let mut hydrated_index = BTreeMap::new();
+        let mut field_updates = BTreeMap::new();
+        let mut last_version = 0;
+        let mut active_segment_size = 0;
+        let mut last_has_hint = false;
+
+        for &segment_id in segments.iter() {
+            let is_last = Some(&segment_id) == segments.last();
+
+            last_has_hint = match hint::read(dir, segment_id)? {
+                Some(hint_entries) => {
+                    last_version = last_version.max(Db::load_hint_entries(
+                        &mut hydrated_index,
+                        &mut field_updates,
+                        segment_id,
+                        hint_entries,
+                    ));
+                    true
+                }
+                None => {
+                    last_version = last_version.max(Db::scan_segment(
+                        dir,
+                        &mut hydrated_index,
+                        &mut field_updates,
+                        segment_id,
+                        is_last,
+                    )?);
+                    false
+                }
+            };
+
+            active_segment_size = fs::metadata(segment::segment_path(dir, segment_id))?.len();
+        }
+
+        // Records which expired while the store was closed are as good as deleted
+        let now = now_millis();
+        hydrated_index.retain(|_, meta| !meta.is_expired(now));
+        field_updates.retain(|key, _| hydrated_index.contains_key(key));
+
+        let expiries = hydrated_index
+            .iter()
+            .filter_map(|(key, meta)| Some((meta.expires_at?, key.clone())))
+            .collect();
+
+        // Writing continues into the most recent segment, unless it's a merge output,
+        // which has to stay in line with its hint file
+        let (active_segment, closed_segments) = match segments.split_last() {
+            Some((&last, _)) if last_has_hint => {
+                active_segment_size = 0;
+                (last + 1, segments.iter().copied().collect())
+            }
+            Some((&last, closed)) => (last, closed.iter().copied().collect()),
+            None => (segment::FIRST_SEGMENT_ID, BTreeSet::new()),
+        };
+
+        let mut index = Index {
+            records: hydrated_index,
+            field_updates,
+            expiries,
+            segments: Segments::new(
+                dir,
+                active_segment,
+                active_segment_size,
+                closed_segments,
+                config.mmap_reads,
+            ),
+            written_seq: 0,
+            last_version,
+            history: BTreeMap::new(),
+            superseded: VecDeque::new(),
+            history_retention: config.history_retention.as_millis() as u64,
+            opened_at: engine::next_version(last_version),
+            secondary: SecondaryIndexes::new(config.indexes.clone())?,
+        };
+
+        Db::rebuild_secondary_indexes(&mut index)?;
+
+        Ok(index)
+    }
+
+    /// Reads the current values of the keys covered by the secondary indexes.
+    fn rebuild_secondary_indexes(index: &mut Index) -> Result<(), crate::Error> {
+        let covered: Vec<(String, ValueMetadata)> = index
+            .records
+            .iter()
+            .filter(|(key, _)| index.secondary.covers(key))
+            .map(|(key, meta)| (key.clone(), meta.clone()))
+            .collect();
+
+        for (key, meta) in covered {
+            let reader = index.segments.reader(meta.segment_id)?;
+            let record = Db::retrieve(&reader, &meta)?;
+
+            if let (Kind::String, Some(value)) = (record.kind, &record.value) {
+                index.secondary.add(&key, value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the greatest version among the loaded records.
+    fn load_hint_entries(
+        hydrated_index: &mut BTreeMap<String, ValueMetadata>,
+        field_updates: &mut BTreeMap<String, FieldUpdates>,
+        segment_id: SegmentId,
+        hint_entries: Vec<HintEntry>,
+    ) -> u64 {
+        let mut last_version = 0;
+
+        for entry in hint_entries {
+            last_version = last_version.max(entry.timestamp);
+
+            let index_record = ValueMetadata {
+                segment_id,
+                offset: entry.offset,
+                len: entry.len,
+                expires_at: entry.expires_at,
+                version: entry.timestamp,
+            };
+
+            field_updates.remove(&entry.key);
+            hydrated_index.insert(entry.key, index_record);
+        }
+
+        last_version
+    }
+
+    /// Returns the greatest version among the scanned records. Only the last segment
+    /// could have been cut off by a crash, so an unreadable record anywhere else
+    /// fails the scan rather than dropping the records following it.
+    fn scan_segment(
+        dir: &Path,
+        hydrated_index: &mut BTreeMap<String, ValueMetadata>,
+        field_updates: &mut BTreeMap<String, FieldUpdates>,
+        segment_id: SegmentId,
+        is_last: bool,
+    ) -> Result<u64, crate::Error> {
+        let file = File::open(segment::segment_path(dir, segment_id))?;
+        let mut reader = BufReader::new(file);
+
+        let mut offset = 0;
+        let mut last_version = 0;
+
+        loop {
+            let batch = match FileRecord::read_batch_from(&mut reader) {
+                Ok(Some(batch)) => batch,
+                Ok(None) => break,
+                Err(DecodeError::Io(err)) => return Err(err.into()),
+                Err(err) if is_last => {
+                    Db::repair_segment_tail(dir, segment_id, offset, err)?;
+                    break;
+                }
+                Err(err) => {
+                    return Err(format!(
+                        "segment {} has an unreadable record at offset {} ({})",
+                        segment_id, offset, err
+                    )
+                    .into())
+                }
+            };
+
+            for (record, len) in batch {
+                last_version = last_version.max(record.timestamp);
+
+                let index_record = ValueMetadata {
+                    segment_id,
+                    offset,
+                    len,
+                    expires_at: record.expires_at,
+                    version: record.timestamp,
+                };
+
+                if record.is_historical {
+                    // History isn't restored, only the writes made since opening are covered
+                } else if let Some(field) = record.field {
+                    let update = FieldUpdate {
+                        meta: index_record,
+                        is_deletion: record.is_tombstone,
+                    };
+
+                    field_updates
+                        .entry(record.key)
+                        .or_default()
+                        .insert(field, update);
+                } else if record.is_tombstone {
+                    field_updates.remove(&record.key);
+                    hydrated_index.remove(&record.key);
+                } else {
+                    field_updates.remove(&record.key);
+                    hydrated_index.insert(record.key, index_record);
+                }
+
+                offset += len;
+            }
+        }
+
+        Ok(last_version)
+    }
+
+    /// Record that can't be decoded is most likely a result of a write torn by a crash,
+    /// which could only happen at the very end of a segment. Everything from that record,
+    /// or from the start of its batch, onwards is moved aside, so that the segment could
+    /// be appended to again.
+    fn repair_segment_tail(
+        dir: &Path,
+        segment_id: SegmentId,
+        offset: u64,
+        err: DecodeError,
+    ) -> Result<(), crate::Error> {
+        let segment_size = fs::metadata(segment::segment_path(dir, segment_id))?.len();
+        let quarantine_path = segment::truncate_tail(dir, segment_id, offset)?;
+
+        eprintln!(
+            "segment {} has an unreadable record at offset {} ({}); truncated {} bytes, \
+             moved into {}",
+            segment_id,
+            offset,
+            err,
+            segment_size - offset,
+            quarantine_path.display()
+        );
+
+        Ok(())
+    }
+
+    fn retrieve(
+        reader: &SegmentReader,
+        value_metadata: &ValueMetadata,
+    ) -> Result<FileRecord, crate::Error> {
+        let buffer = reader.read(value_metadata.offset, value_metadata.len)?;
+
+        let record = FileRecord::decode(&buffer)?;
+
+        Ok(record)
+    }
+
+    /// Appends record to the active segment and reflects it in the index.
+    /// Caller is expected to hold the index lock for the whole operation, so that
+    /// appending and indexing are never interleaved with segment swapping.
+    ///
+    /// Record is stamped with the next version. Sequence number of the appended
+    /// record is returned, which is what the caller should wait on for the record
+    /// to become durable.
+    fn insert(
+        &self,
+        index_state: &mut Index,
+        file_record: FileRecord,
+    ) -> Result<u64, crate::Error> {
+        self.insert_batch(index_state, vec![file_record])
+    }
+
+    /// Appends records as a single batch, which after a crash is either found
+    /// in the segment as a whole or not at all. Batch always goes into a single
+    /// segment, even if that makes the segment exceed its maximum size.
+    fn insert_batch(
+        &self,
+        index_state: &mut Index,
+        mut file_records: Vec<FileRecord>,
+    ) -> Result<u64, crate::Error> {
+        let batch_len = file_records.len();
+        let mut encoded_batch = BytesMut::new();
+        let mut lens = Vec::with_capacity(batch_len);
+
+        for (idx, file_record) in file_records.iter_mut().enumerate() {
+            file_record.timestamp = engine::next_version(index_state.last_version);
+            file_record.batch_continues = idx + 1 < batch_len;
+            index_state.last_version = file_record.timestamp;
+
+            let encoded_rec = file_record.encode();
+            lens.push(encoded_rec.len() as u64);
+            encoded_batch.extend_from_slice(&encoded_rec);
+        }
+
+        let len = encoded_batch.len() as u64;
+
+        let segments = &mut index_state.segments;
+
+        if segments.active_size > 0 && segments.active_size + len > self.config.max_segment_size {
+            segments.roll(self.config.durability != Durability::Never)?;
+        }
+
+        let mut offset = segments.append(&encoded_batch)?;
+        let segment_id = segments.active;
+
+        index_state.written_seq += 1;
+
+        for (file_record, len) in file_records.into_iter().zip(lens) {
+            let value_metadata = ValueMetadata {
+                segment_id,
+                offset,
+                len,
+                expires_at: file_record.expires_at,
+                version: file_record.timestamp,
+            };
+
+            if let Some(field) = file_record.field {
+                let update = FieldUpdate {
+                    meta: value_metadata,
+                    is_deletion: file_record.is_tombstone,
+                };
+
+                index_state.update_field(file_record.key, field, update);
+            } else if file_record.is_tombstone {
+                index_state.delete(&file_record.key, file_record.timestamp);
+            } else {
+                index_state.insert(file_record.key.clone(), value_metadata);
+
+                if let (Kind::String, Some(value)) = (file_record.kind, &file_record.value) {
+                    index_state.secondary.add(&file_record.key, value);
+                }
+            }
+
+            offset += len;
+        }
+
+        Ok(index_state.written_seq)
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<FileRecord>, crate::Error> {
+        // Reading itself happens outside of the lock, the reader stays valid
+        // even if compaction replaces the segment in the meantime
+        let (range, index_record) = {
+            let mut index_state = self.index.lock().unwrap();
+
+            let index_record = match index_state.live(key, now_millis()) {
+                Some(index_record) => index_record,
+                None => return Ok(None),
+            };
+
+            let entries = vec![(key.to_string(), index_record.clone())];
+
+            (
+                Db::range_over(&mut index_state, entries, u64::MAX)?,
+                index_record,
+            )
+        };
+
+        let file_record = range.read_record(key, &index_record)?;
+
+        if file_record.is_tombstone {
+            return Ok(None);
+        }
+
+        Ok(Some(file_record))
+    }
+
+    pub fn set(&self, key: String, value: Bytes) -> Result<(), crate::Error> {
+        let record = FileRecord::new(key, Some(value), false);
+
+        let seq = {
+            let mut index_state = self.index.lock().unwrap();
+
+            self.insert(&mut index_state, record)?
+        };
+
+        self.wait_durable(seq)?;
+
+        Ok(())
+    }
+
+    pub fn delete(&self, key: String) -> Result<Option<()>, crate::Error> {
+        let seq = {
+            let mut index_state = self.index.lock().unwrap();
+
+            if index_state.live(&key, now_millis()).is_none() {
+                return Ok(None);
+            }
+
+            let tombstone_record = FileRecord::new(key, None, true);
+
+            self.insert(&mut index_state, tombstone_record)?
+        };
+
+        self.wait_durable(seq)?;
+
+        Ok(Some(()))
+    }
+
+    /// Iterates over live records with keys within the range, e.g. `db.range("a".."c")`.
+    /// Index lock is only held while the range is created, values written
+    /// after that aren't visible to the iteration.
+    pub fn range<'a>(&self, range: impl RangeBounds<&'a str>) -> Result<Range, crate::Error> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+
+        if engine::is_empty_range::<&str>(&range) {
+            return Ok(Range {
+                entries: vec![].into_iter(),
+                field_updates: HashMap::new(),
+                readers: HashMap::new(),
+            });
+        }
+
+        let mut index_state = self.index.lock().unwrap();
+        let now = now_millis();
+
+        let entries: Vec<(String, ValueMetadata)> = index_state
+            .records
+            .range::<str, _>(range)
+            .filter(|(_, meta)| !meta.is_expired(now))
+            .map(|(key, meta)| (key.clone(), meta.clone()))
+            .collect();
+
+        Db::range_over(&mut index_state, entries, u64::MAX)
+    }
+
+    /// Same as `range`, but as of the point in time, which has to be within history.
+    /// Keys are taken in reverse order if `reverse` is set, up to `limit` of them.
+    ///
+    /// Only the last update of every hash field is kept, so a field updated since
+    /// the point in time is read as it was in the last full record of its hash.
+    pub fn range_at<'a>(
+        &self,
+        range: impl RangeBounds<&'a str>,
+        reverse: bool,
+        limit: usize,
+        at: u64,
+    ) -> Result<Range, crate::Error> {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+
+        if engine::is_empty_range::<&str>(&range) {
+            return Ok(Range {
+                entries: vec![].into_iter(),
+                field_updates: HashMap::new(),
+                readers: HashMap::new(),
+            });
+        }
+
+        let mut index_state = self.index.lock().unwrap();
+
+        // Keys deleted since then are only found in history
+        let keys: BTreeSet<&String> = index_state
+            .records
+            .range::<str, _>(range)
+            .map(|(key, _)| key)
+            .chain(
+                index_state
+                    .history
+                    .range::<str, _>(range)
+                    .map(|(key, _)| key),
+            )
+            .collect();
+
+        let keys: Box<dyn Iterator<Item = &&String>> = if reverse {
+            Box::new(keys.iter().rev())
+        } else {
+            Box::new(keys.iter())
+        };
+
+        let entries: Vec<(String, ValueMetadata)> = keys
+            .filter_map(|key| Some((key.to_string(), index_state.at(key, at)?)))
+            .take(limit)
+            .collect();
+
+        Db::range_over(&mut index_state, entries, at)
+    }
+
+    /// Entries which are the current records of hashes get the field updates
+    /// written on top of them up to the given version.
+    fn range_over(
+        index_state: &mut Index,
+        entries: Vec<(String, ValueMetadata)>,
+        as_of: u64,
+    ) -> Result<Range, crate::Error> {
+        let mut field_updates = HashMap::new();
+
+        for (key, meta) in entries.iter() {
+            let updates = match index_state.field_updates.get(key) {
+                Some(updates) if index_state.records.get(key) == Some(meta) => updates,
+                _ => continue,
+            };
+
+            let updates: FieldUpdates = updates
+                .iter()
+                .filter(|(_, update)| update.meta.version <= as_of)
+                .map(|(field, update)| (field.clone(), update.clone()))
+                .collect();
+
+            field_updates.insert(key.clone(), updates);
+        }
+
+        let mut readers = HashMap::new();
+
+        let metas = entries.iter().map(|(_, meta)| meta).chain(
+            field_updates
+                .values()
+                .flat_map(|updates| updates.values())
+                .map(|update| &update.meta),
+        );
+
+        for meta in metas {
+            if let hash_map::Entry::Vacant(slot) = readers.entry(meta.segment_id) {
+                slot.insert(index_state.segments.reader(meta.segment_id)?);
+            }
+        }
+
+        Ok(Range {
+            entries: entries.into_iter(),
+            field_updates,
+            readers,
+        })
+    }
+
+    /// Current value of the key, read while holding the index lock.
+    fn read_current(
+        index_state: &mut Index,
+        key: &str,
+        now: u64,
+    ) -> Result<Option<Value>, crate::Error> {
+        let meta = match index_state.live(key, now) {
+            Some(meta) => meta,
+            None => return Ok(None),
+        };
+
+        let entries = vec![(key.to_string(), meta.clone())];
+        let range = Db::range_over(index_state, entries, u64::MAX)?;
+
+        Ok(range.read_record(key, &meta)?.into_value())
+    }
+
+    /// Applies the field updates to the full record of a hash, which takes
+    /// the version of the last of them.
+    fn fold(
+        record: &mut FileRecord,
+        updates: &FieldUpdates,
+        readers: &HashMap<SegmentId, SegmentReader>,
+    ) -> Result<(), crate::Error> {
+        let mut fields = hash::decode(&record.value.clone().unwrap_or_default())?;
+
+        for (field, update) in updates {
+            if update.is_deletion {
+                fields.remove(field);
+            } else {
+                let field_record = Db::retrieve(&readers[&update.meta.segment_id], &update.meta)?;
+                fields.insert(field.clone(), field_record.value.unwrap_or_default());
+            }
+
+            record.timestamp = record.timestamp.max(update.meta.version);
+        }
+
+        record.value = Some(hash::encode(&fields));
+
+        Ok(())
+    }
+}
+
+impl Index {
+    /// Makes the location current for the key, the previous one goes into history.
+    fn insert(&mut self, key: String, meta: ValueMetadata) {
+        if !self.retire(&key, meta.version) && self.history.contains_key(&key) {
+            // Deletion at the end of the history only lasts until now
+            self.superseded.push_back((meta.version, key.clone()));
+        }
+
+        if let Some(expires_at) = meta.expires_at {
+            self.expiries.insert((expires_at, key.clone()));
+        }
+
+        self.records.insert(key, meta);
+    }
+
+    fn delete(&mut self, key: &str, version: u64) {
+        if self.retire(key, version) {
+            self.push_history(key, PastVersion::Deleted(version), version);
+        }
+    }
+
+    /// Drops the key once it has expired.
+    fn remove(&mut self, key: &str) {
+        self.retire(key, now_millis());
+    }
+
+    /// Moves the current location of the key into history, `at` being the time it
+    /// stopped being current. Returns `false` if there was no such key.
+    fn retire(&mut self, key: &str, at: u64) -> bool {
+        let previous = match self.records.remove(key) {
+            Some(previous) => previous,
+            None => return false,
+        };
+
+        if let Some(expires_at) = previous.expires_at {
+            self.expiries.remove(&(expires_at, key.to_string()));
+        }
+
+        self.field_updates.remove(key);
+        self.secondary.remove(key);
+        self.push_history(key, PastVersion::Value(previous), at);
+
+        true
+    }
+
+    /// Field updates only count as long as the full record of the hash stays current.
+    fn update_field(&mut self, key: String, field: String, update: FieldUpdate) {
+        if self.records.contains_key(&key) {
+            self.field_updates
+                .entry(key)
+                .or_default()
+                .insert(field, update);
+        }
+    }
+
+    /// Drops the field updates folded by compaction, unless the fields have been
+    /// updated again meanwhile.
+    fn drop_field_updates(&mut self, key: &str, folded: &FieldUpdates) {
+        let updates = match self.field_updates.get_mut(key) {
+            Some(updates) => updates,
+            None => return,
+        };
+
+        updates.retain(|field, update| folded.get(field) != Some(update));
+
+        if updates.is_empty() {
+            self.field_updates.remove(key);
+        }
+    }
+
+    fn push_history(&mut self, key: &str, past: PastVersion, at: u64) {
+        if self.history_retention == 0 {
+            return;
+        }
+
+        self.history.entry(key.to_string()).or_default().push(past);
+        self.superseded.push_back((at, key.to_string()));
+    }
+
+    /// Drops the history which ended before the retention window.
+    fn prune_history(&mut self, now: u64) {
+        let cutoff = now.saturating_sub(self.history_retention);
+
+        while let Some((at, _)) = self.superseded.front() {
+            if *at > cutoff {
+                break;
+            }
+
+            let (_, key) = self.superseded.pop_front().unwrap();
+
+            let past = match self.history.get_mut(&key) {
+                Some(past) => past,
+                None => continue,
+            };
+
+            let current_version = self.records.get(&key).map(|meta| meta.version);
+
+            // Every version lasts until the next one, the last one in history lasts
+            // until the current one or, if there is none, until it expired or was deleted
+            let ended = (0..past.len())
+                .take_while(|&idx| {
+                    let end = match past.get(idx + 1) {
+                        Some(next) => Some(next.version()),
+                        None => current_version.or(match &past[idx] {
+                            PastVersion::Value(meta) => meta.expires_at,
+                            PastVersion::Deleted(version) => Some(*version),
+                        }),
+                    };
+
+                    end.is_some_and(|end| end <= cutoff)
+                })
+                .count();
+
+            past.drain(..ended);
+
+            if past.is_empty() {
+                self.history.remove(&key);
+            }
+        }
+    }
+
+    /// Points the key, or a past version of it, to where compaction has moved it.
+    /// Key could have been overwritten, deleted or dropped from history meanwhile.
+    fn relocate(&mut self, key: &str, old_meta: &ValueMetadata, new_meta: ValueMetadata) {
+        if let Some(meta) = self.records.get_mut(key) {
+            if meta == old_meta {
+                *meta = new_meta;
+                return;
+            }
+        }
+
+        let past = self.history.get_mut(key).into_iter().flatten();
+
+        for past in past {
+            if let PastVersion::Value(meta) = past {
+                if meta == old_meta {
+                    *meta = new_meta;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Location of the value the key had as of the version, `None` if it was missing.
+    fn at(&self, key: &str, version: u64) -> Option<ValueMetadata> {
+        let meta = match self.records.get(key) {
+            Some(meta) if meta.version <= version => Some(meta),
+            _ => {
+                let past = self.history.get(key)?;
+
+                match past.iter().rev().find(|past| past.version() <= version)? {
+                    PastVersion::Value(meta) => Some(meta),
+                    PastVersion::Deleted(_) => None,
+                }
+            }
+        };
+
+        meta.filter(|meta| !meta.is_expired(version)).cloned()
+    }
+
+    /// Earliest version the keys can be read as of.
+    fn history_start(&self, now: u64) -> Option<u64> {
+        if self.history_retention == 0 {
+            return None;
+        }
+
+        Some(
+            self.opened_at
+                .max(now.saturating_sub(self.history_retention)),
+        )
+    }
+
+    /// Location of the key, unless it has expired, in which case it's dropped right away.
+    fn live(&mut self, key: &str, now: u64) -> Option<ValueMetadata> {
+        let meta = self.records.get(key)?;
+
+        if meta.is_expired(now) {
+            self.remove(key);
+            return None;
+        }
+
+        Some(meta.clone())
+    }
+
+    /// Version of the key, which for a hash is that of its last field update, if any.
+    fn live_version(&mut self, key: &str, now: u64) -> Option<u64> {
+        let meta = self.live(key, now)?;
+
+        let updates = self.field_updates.get(key).into_iter().flatten();
+
+        Some(updates.fold(meta.version, |version, (_, update)| {
+            version.max(update.meta.version)
+        }))
+    }
+}
+
+impl ValueMetadata {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+impl PastVersion {
+    fn version(&self) -> u64 {
+        match self {
+            PastVersion::Value(meta) => meta.version,
+            PastVersion::Deleted(version) => *version,
+        }
+    }
+}
+
+impl Range {
+    fn read_record(&self, key: &str, meta: &ValueMetadata) -> Result<FileRecord, crate::Error> {
+        let mut record = Db::retrieve(&self.readers[&meta.segment_id], meta)?;
+
+        if let Some(updates) = self.field_updates.get(key) {
+            Db::fold(&mut record, updates, &self.readers)?;
+        }
+
+        Ok(record)
+    }
+
+    fn read(&self, key: String, meta: ValueMetadata) -> Result<(String, Bytes), crate::Error> {
+        let record = self.read_record(&key, &meta)?;
+
+        Ok((key, record.value.unwrap_or_default()))
+    }
+}
+
+impl Iterator for Range {
+    type Item = Result<(String, Bytes), crate::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, meta) = self.entries.next()?;
+
+        Some(self.read(key, meta))
+    }
+}
+
+impl DoubleEndedIterator for Range {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let (key, meta) = self.entries.next_back()?;
+
+        Some(self.read(key, meta))
+    }
+}
+
+impl StorageEngine for Db {
+    fn get_value(&self, key: &str) -> Result<Option<Value>, crate::Error> {
+        Ok(Db::get(self, key)?.and_then(FileRecord::into_value))
+    }
+
+    /// Current value is read while holding the index lock, which is what keeps
+    /// other writes to the key out until the outcome is appended.
+    fn update(
+        &self,
+        key: &str,
+        f: &mut dyn FnMut(Option<&Value>) -> Result<Update, crate::Error>,
+    ) -> Result<(), crate::Error> {
+        let seq = {
+            let mut index_state = self.index.lock().unwrap();
+
+            let current = Db::read_current(&mut index_state, key, now_millis())?;
+
+            let record = match f(current.as_ref())? {
+                Update::Keep => return Ok(()),
+                Update::Set(value) => FileRecord::from_value(key.to_string(), Some(value)),
+                Update::Delete if current.is_none() => return Ok(()),
+                Update::Delete => FileRecord::new(key.to_string(), None, true),
+            };
+
+            self.insert(&mut index_state, record)?
+        };
+
+        self.wait_durable(seq)
+    }
+
+    /// Whole batch goes to disk with a single append, so that it's never
+    /// partially recovered after a crash. Versions are checked under the same
+    /// index lock the batch is appended with.
+    fn write_batch_if(
+        &self,
+        versions: Vec<(String, Option<u64>)>,
+        batch: Vec<(String, Option<Value>)>,
+    ) -> Result<bool, crate::Error> {
+        let records: Vec<FileRecord> = batch
+            .into_iter()
+            .map(|(key, value)| FileRecord::from_value(key, value))
+            .collect();
+
+        let seq = {
+            let mut index_state = self.index.lock().unwrap();
+            let now = now_millis();
+
+            for (key, version) in versions {
+                if index_state.live_version(&key, now) != version {
+                    return Ok(false);
+                }
+            }
+
+            if records.is_empty() {
+                return Ok(true);
+            }
+
+            self.insert_batch(&mut index_state, records)?
+        };
+
+        self.wait_durable(seq)?;
+
+        Ok(true)
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, crate::Error> {
+        let mut index_state = self.index.lock().unwrap();
+
+        Ok(index_state.live(key, now_millis()).is_some())
+    }
+
+    fn set(&self, key: String, value: Bytes) -> Result<(), crate::Error> {
+        Db::set(self, key, value)
+    }
+
+    fn delete(&self, key: &str) -> Result<bool, crate::Error> {
+        Ok(Db::delete(self, key.to_string())?.is_some())
+    }
+
+    fn find(&self, index: &str, value: &Json) -> Result<Option<Vec<String>>, crate::Error> {
+        let mut index_state = self.index.lock().unwrap();
+        let now = now_millis();
+
+        let keys = match index_state.secondary.find(index, value) {
+            Some(keys) => keys,
+            None => return Ok(None),
+        };
+
+        Ok(Some(
+            keys.into_iter()
+                .filter(|key| index_state.live(key, now).is_some())
+                .collect(),
+        ))
+    }
+
+    fn history_start(&self) -> Option<u64> {
+        self.index.lock().unwrap().history_start(now_millis())
+    }
+
+    fn get_value_at(&self, key: &str, at: u64) -> Result<Option<Value>, crate::Error> {
+        let (range, meta) = {
+            let mut index_state = self.index.lock().unwrap();
+
+            let meta = match index_state.at(key, at) {
+                Some(meta) => meta,
+                None => return Ok(None),
+            };
+
+            let entries = vec![(key.to_string(), meta.clone())];
+
+            (Db::range_over(&mut index_state, entries, at)?, meta)
+        };
+
+        Ok(range.read_record(key, &meta)?.into_value())
+    }
+
+    /// Fields are written as records of their own on top of the full record of
+    /// the hash, which isn't rewritten until compaction.
+    fn hash_set(&self, key: &str, fields: Vec<(String, Bytes)>) -> Result<usize, crate::Error> {
+        if fields.is_empty() {
+            return Ok(0);
+        }
+
+        let (seq, added) = {
+            let mut index_state = self.index.lock().unwrap();
+
+            let current = Db::read_current(&mut index_state, key, now_millis())?;
+
+            let mut hash = match &current {
+                Some(current) => current.fields()?,
+                None => Fields::new(),
+            };
+
+            let added = fields
+                .iter()
+                .filter(|(field, value)| hash.insert(field.clone(), value.clone()).is_none())
+                .count();
+
+            let records = match current {
+                Some(_) => fields
+                    .into_iter()
+                    .map(|(field, value)| {
+                        FileRecord::field_update(key.to_string(), field, Some(value))
+                    })
+                    .collect(),
+                None => vec![FileRecord::from_value(
+                    key.to_string(),
+                    Some(Value::hash(&hash, None)),
+                )],
+            };
+
+            (self.insert_batch(&mut index_state, records)?, added)
+        };
+
+        self.wait_durable(seq)?;
+
+        Ok(added)
+    }
+
+    /// Only the update of the field is read, if there is one, rather than the whole hash.
+    fn hash_get(&self, key: &str, field: &str) -> Result<Option<Bytes>, crate::Error> {
+        let (reader, meta, is_update) = {
+            let mut index_state = self.index.lock().unwrap();
+
+            let meta = match index_state.live(key, now_millis()) {
+                Some(meta) => meta,
+                None => return Ok(None),
+            };
+
+            let update = index_state
+                .field_updates
+                .get(key)
+                .and_then(|updates| updates.get(field))
+                .cloned();
+
+            let is_update = update.is_some();
+
+            let meta = match update {
+                Some(update) if update.is_deletion => return Ok(None),
+                Some(update) => update.meta,
+                None => meta,
+            };
+
+            (
+                index_state.segments.reader(meta.segment_id)?,
+                meta,
+                is_update,
+            )
+        };
+
+        let record = Db::retrieve(&reader, &meta)?;
+
+        if is_update {
+            return Ok(record.value);
+        }
+
+        match record.into_value() {
+            Some(value) => Ok(value.fields()?.remove(field)),
+            None => Ok(None),
+        }
+    }
+
+    fn hash_delete(&self, key: &str, fields: &[String]) -> Result<usize, crate::Error> {
+        let (seq, removed) = {
+            let mut index_state = self.index.lock().unwrap();
+
+            let mut hash = match Db::read_current(&mut index_state, key, now_millis())? {
+                Some(current) => current.fields()?,
+                None => return Ok(0),
+            };
+
+            let removed: Vec<&String> = fields
+                .iter()
+                .filter(|field| hash.remove(*field).is_some())
+                .collect();
+
+            if removed.is_empty() {
+                return Ok(0);
+            }
+
+            let records = if hash.is_empty() {
+                vec![FileRecord::new(key.to_string(), None, true)]
+            } else {
+                removed
+                    .iter()
+                    .map(|field| FileRecord::field_update(key.to_string(), field.to_string(), None))
+                    .collect()
+            };
+
+            (self.insert_batch(&mut index_state, records)?, removed.len())
+        };
+
+        self.wait_durable(seq)?;
+
+        Ok(removed)
+    }
+
+    fn scan_at(
+        &self,
+        range: KeyRange,
+        reverse: bool,
+        limit: usize,
+        at: u64,
+    ) -> Result<Vec<(String, Bytes)>, crate::Error> {
+        let range = (
+            range.0.as_ref().map(String::as_str),
+            range.1.as_ref().map(String::as_str),
+        );
+
+        self.range_at(range, reverse, limit, at)?.collect()
+    }
+
+    fn scan(
+        &self,
+        range: KeyRange,
+        reverse: bool,
+        limit: usize,
+    ) -> Result<Vec<(String, Bytes)>, crate::Error> {
+        let range = self.range((
+            range.0.as_ref().map(String::as_str),
+            range.1.as_ref().map(String::as_str),
+        ))?;
+
+        if reverse {
+            range.rev().take(limit).collect()
+        } else {
+            range.take(limit).collect()
+        }
+    }
+
+    /// Keys come straight from the index, without reading any values.
+    fn keys(&self) -> Result<Vec<String>, crate::Error> {
+        let index_state = self.index.lock().unwrap();
+        let now = now_millis();
+
+        Ok(index_state
+            .records
+            .iter()
+            .filter(|(_, meta)| !meta.is_expired(now))
+            .map(|(key, _)| key.clone())
+            .collect())
+    }
+
+    fn key_count(&self) -> Result<usize, crate::Error> {
+        let index_state = self.index.lock().unwrap();
+        let expired = index_state
+            .expiries
+            .range(..(now_millis() + 1, String::new()))
+            .count();
+
+        Ok(index_state.records.len() - expired)
+    }
+
+    fn compact(&self) -> Result<(), crate::Error> {
+        self.run_compaction()
+    }
+
+    /// Expired records are only dropped from the index, they are left on disk until
+    /// compaction and are skipped when the index is rebuilt in the meantime.
+    fn purge_expired(&self) -> Result<usize, crate::Error> {
+        let mut index_state = self.index.lock().unwrap();
+        let now = now_millis();
+        let mut purged = 0;
+
+        while let Some((expires_at, _)) = index_state.expiries.first() {
+            if *expires_at > now {
+                break;
+            }
+
+            let (expires_at, key) = index_state.expiries.pop_first().unwrap();
+            index_state.retire(&key, expires_at);
+            purged += 1;
+        }
+
+        index_state.prune_history(now);
+
+        Ok(purged)
+    }
+
+    fn sync(&self) -> Result<(), crate::Error> {
+        Db::sync(self)
+    }
+
+    fn sync_interval(&self) -> Option<Duration> {
+        match self.config.durability {
+            Durability::Interval(period) => Some(period),
+            Durability::Always | Durability::Never => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::ops::Bound;
+
+    use crate::engine::{Expected, JsonPath, Kind, SetCondition, WrongType};
+    use serde_json::json;
+
+    fn setup_config(name: &str, max_segment_size: u64) -> Config {
+        let dir = std::env::temp_dir().join(format!("kv_db_{}", name));
+
+        let _ = fs::remove_dir_all(&dir);
+
+        Config {
+            dir,
+            max_segment_size,
+            durability: Durability::Never,
+            mmap_reads: false,
+            history_retention: Config::default().history_retention,
+            indexes: vec![],
+        }
+    }
+
+    fn setup_db(name: &str) -> Result<Db, crate::Error> {
+        Db::new(setup_config(name, Config::default().max_segment_size))
+    }
+
+    fn reopen(db: Db) -> Result<Db, crate::Error> {
+        let config = db.config.as_ref().clone();
+
+        drop(db);
+
+        Db::new(config)
+    }
+
+    #[test]
+    fn test_insertion() -> Result<(), crate::Error> {
+        let db = setup_db("insertion")?;
+
+        let key = "test_key".to_string();
+        let value = Bytes::from("test_value".to_string());
+
+        db.set(key.clone(), value)?;
+
+        let retrieved_record = db.get(&key)?.unwrap();
+        assert_eq!(retrieved_record.key, key);
+        assert_eq!(retrieved_record.value, Some(Bytes::from("test_value")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_values() -> Result<(), crate::Error> {
+        let db = setup_db("binary_values")?;
+
+        let value = Bytes::from_static(&[0x00, 0xff, 0xfe, b'\r', b'\n', 0x80]);
+
+        db.set("binary".to_string(), value.clone())?;
+        db.set("empty".to_string(), Bytes::new())?;
+
+        assert_eq!(db.get("binary")?.unwrap().value, Some(value.clone()));
+        assert_eq!(db.get("empty")?.unwrap().value, Some(Bytes::new()));
+
+        let db = reopen(db)?;
+
+        assert_eq!(db.get("binary")?.unwrap().value, Some(value));
+        assert_eq!(db.get("empty")?.unwrap().value, Some(Bytes::new()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_retrieval() -> Result<(), crate::Error> {
+        let db = setup_db("retrieval")?;
+
+        db.set("first".to_string(), Bytes::from("1"))?;
+        db.set("second".to_string(), Bytes::from("2"))?;
+        db.set("first".to_string(), Bytes::from("3"))?;
+
+        assert_eq!(db.get("first")?.unwrap().value, Some(Bytes::from("3")));
+        assert_eq!(db.get("second")?.unwrap().value, Some(Bytes::from("2")));
+        assert!(db.get("missing")?.is_none());
+
+        // Index rebuilt from disk sees the same state
+        let db = reopen(db)?;
+
+        assert_eq!(db.get("first")?.unwrap().value, Some(Bytes::from("3")));
+        assert_eq!(db.get("second")?.unwrap().value, Some(Bytes::from("2")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deletion() -> Result<(), crate::Error> {
+        let db = setup_db("deletion")?;
+
+        db.set("key".to_string(), Bytes::from("value"))?;
+
+        assert!(db.delete("key".to_string())?.is_some());
+        assert!(db.get("key")?.is_none());
+        assert!(db.delete("key".to_string())?.is_none());
+
+        let db = reopen(db)?;
+
+        assert!(db.get("key")?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_storage_engine() -> Result<(), crate::Error> {
+        let db = setup_db("storage_engine")?;
+        let engine: &dyn StorageEngine = &db;
+
+        engine.set("b".to_string(), Bytes::from("2"))?;
+        engine.set("a".to_string(), Bytes::from("1"))?;
+        engine.set("c".to_string(), Bytes::from("3"))?;
+
+        assert!(engine.delete("c")?);
+        assert!(!engine.delete("c")?);
+
+        assert_eq!(engine.get("a")?, Some(Bytes::from("1")));
+        assert!(engine.contains("b")?);
+        assert!(!engine.contains("c")?);
+        assert_eq!(engine.keys()?, vec!["a", "b"]);
+        assert_eq!(engine.key_count()?, 2);
+        assert_eq!(
+            engine.scan((Bound::Unbounded, Bound::Unbounded), false, usize::MAX)?,
+            vec![
+                ("a".to_string(), Bytes::from("1")),
+                ("b".to_string(), Bytes::from("2")),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_iteration() -> Result<(), crate::Error> {
+        let db = setup_db("range_iteration")?;
+
+        for key in ["d", "a", "c", "e", "b"] {
+            db.set(key.to_string(), Bytes::from(key.to_uppercase()))?;
+        }
+
+        db.delete("c".to_string())?;
+
+        let forward = db.range("b".."e")?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            forward,
+            vec![
+                ("b".to_string(), Bytes::from("B")),
+                ("d".to_string(), Bytes::from("D")),
+            ]
+        );
+
+        let reverse: Vec<String> = db
+            .range("b"..="e")?
+            .rev()
+            .map(|entry| entry.map(|(key, _)| key))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(reverse, vec!["e", "d", "b"]);
+
+        assert_eq!(db.range(..)?.count(), 4);
+        assert_eq!(db.range("e".."b")?.count(), 0);
+
+        // Values written after the range is created aren't visible to it
+        let range = db.range(..)?;
+        db.set("f".to_string(), Bytes::from("F"))?;
+        assert_eq!(range.count(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expiration() -> Result<(), crate::Error> {
+        let db = Db::new(setup_config("expiration", 200))?;
+        let engine: &dyn StorageEngine = &db;
+        let now = now_millis();
+
+        let later = Some(now + 60_000);
+
+        engine.set_with(
+            "gone".to_string(),
+            Bytes::from("1"),
+            Some(now - 1),
+            SetCondition::Always,
+        )?;
+        engine.set_with(
+            "later".to_string(),
+            Bytes::from("2"),
+            later,
+            SetCondition::Always,
+        )?;
+        engine.set("kept".to_string(), Bytes::from("3"))?;
+
+        assert!(db.get("gone")?.is_none());
+        assert!(db.delete("gone".to_string())?.is_none());
+        assert_eq!(engine.get_value("later")?.unwrap().expires_at, later);
+        assert_eq!(engine.keys()?, vec!["kept", "later"]);
+        assert_eq!(engine.key_count()?, 2);
+
+        // Expiration time survives a restart, while expired keys stay gone
+        engine.set_with(
+            "soon".to_string(),
+            Bytes::from("4"),
+            Some(now + 100),
+            SetCondition::Always,
+        )?;
+        let db = reopen(db)?;
+        let engine: &dyn StorageEngine = &db;
+
+        assert_eq!(engine.get_value("later")?.unwrap().expires_at, later);
+        assert!(engine.contains("soon")?);
+
+        std::thread::sleep(Duration::from_millis(150));
+
+        assert_eq!(engine.purge_expired()?, 1);
+        assert_eq!(engine.keys()?, vec!["kept", "later"]);
+        assert_eq!(db.index.lock().unwrap().expiries.len(), 1);
+
+        // Expired records aren't carried over by the merge
+        engine.set_expiry("kept", Some(now_millis() + 50))?;
+        engine.set("filler".to_string(), Bytes::from("x".repeat(200)))?;
+        std::thread::sleep(Duration::from_millis(100));
+
+        db.run_compaction()?;
+
+        assert!(engine.get("kept")?.is_none());
+        assert!(!db.index.lock().unwrap().records.contains_key("kept"));
+
+        let db = reopen(db)?;
+
+        assert!(db.get("kept")?.is_none());
+        assert_eq!(db.get("later")?.unwrap().expires_at, later);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_and_swap() -> Result<(), crate::Error> {
+        let db = setup_db("compare_and_swap")?;
+
+        db.set("counter".to_string(), Bytes::from("0"))?;
+
+        // Every increment is a read followed by a swap, which only goes through
+        // if nobody else has written in between
+        let writers: Vec<_> = (0..8)
+            .map(|_| {
+                let db = db.clone();
+
+                std::thread::spawn(move || {
+                    for _ in 0..25 {
+                        loop {
+                            let current = db.get_value("counter").unwrap().unwrap();
+                            let count: u64 = std::str::from_utf8(&current.bytes)
+                                .unwrap()
+                                .parse()
+                                .unwrap();
+                            let next = Bytes::from((count + 1).to_string());
+                            let expected = Expected::Version(current.version);
+
+                            if db
+                                .compare_and_swap("counter".to_string(), expected, next)
+                                .unwrap()
+                            {
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(db.get("counter")?.unwrap().value, Some(Bytes::from("200")));
+
+        let version = db.get_value("counter")?.unwrap().version;
+        let stale = Expected::Version(version - 1);
+        assert!(!db.compare_and_swap("counter".to_string(), stale, Bytes::from("x"))?);
+
+        let expected = Expected::Value(Bytes::from("200"));
+        assert!(db.compare_and_swap("counter".to_string(), expected, Bytes::from("201"))?);
+        assert!(!db.compare_and_swap("missing".to_string(), Expected::Version(0), Bytes::new())?);
+
+        assert_eq!(
+            db.get_set("counter".to_string(), Bytes::from("0"))?,
+            Some(Bytes::from("201"))
+        );
+        assert_eq!(db.get_set("fresh".to_string(), Bytes::from("1"))?, None);
+
+        assert!(!db.set_with(
+            "fresh".to_string(),
+            Bytes::new(),
+            None,
+            SetCondition::IfAbsent
+        )?);
+        assert!(!db.set_with(
+            "missing".to_string(),
+            Bytes::new(),
+            None,
+            SetCondition::IfPresent
+        )?);
+
+        // Versions survive a restart and keep growing after it
+        let version = db.get_value("counter")?.unwrap().version;
+        let db = reopen(db)?;
+
+        assert_eq!(db.get_value("counter")?.unwrap().version, version);
+
+        db.set("counter".to_string(), Bytes::from("1"))?;
+        assert!(db.get_value("counter")?.unwrap().version > version);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_increment() -> Result<(), crate::Error> {
+        let db = setup_db("increment")?;
+
+        let writers: Vec<_> = (0..8)
+            .map(|_| {
+                let db = db.clone();
+
+                std::thread::spawn(move || {
+                    for _ in 0..25 {
+                        db.increment("counter", 2).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(db.increment("counter", -1)?, Some(399));
+
+        db.set("text".to_string(), Bytes::from("12a"))?;
+        assert_eq!(db.increment("text", 1)?, None);
+        assert_eq!(db.get("text")?.unwrap().value, Some(Bytes::from("12a")));
+
+        let db = reopen(db)?;
+
+        assert_eq!(db.get("counter")?.unwrap().value, Some(Bytes::from("399")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_synced_writes() -> Result<(), crate::Error> {
+        let config = Config {
+            durability: Durability::Always,
+            ..setup_config("synced_writes", 200)
+        };
+
+        let db = Db::new(config)?;
+
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let db = db.clone();
+
+                std::thread::spawn(move || {
+                    for i in 0..10 {
+                        let key = format!("key_{}_{}", writer, i);
+                        db.set(key, Bytes::from(i.to_string())).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        // Every acknowledged write is covered by some fsync
+        let written_seq = db.index.lock().unwrap().written_seq;
+        assert_eq!(written_seq, 80);
+        assert_eq!(*db.synced_seq.lock().unwrap(), written_seq);
+
+        let db = reopen(db)?;
+
+        for writer in 0..8 {
+            for i in 0..10 {
+                let record = db.get(&format!("key_{}_{}", writer, i))?.unwrap();
+                assert_eq!(record.value, Some(Bytes::from(i.to_string())));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_segment_rollover() -> Result<(), crate::Error> {
+        let db = Db::new(setup_config("segment_rollover", 200))?;
+
+        for i in 0..10 {
+            db.set(format!("key_{}", i), Bytes::from(i.to_string()))?;
+        }
+
+        let segments = segment::list_segments(&db.config.dir)?;
+        assert!(segments.len() > 1);
+
+        for segment_id in segments {
+            let path = segment::segment_path(&db.config.dir, segment_id);
+            assert!(fs::metadata(path)?.len() <= 200);
+        }
+
+        let db = reopen(db)?;
+
+        for i in 0..10 {
+            let record = db.get(&format!("key_{}", i))?.unwrap();
+            assert_eq!(record.value, Some(Bytes::from(i.to_string())));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction() -> Result<(), crate::Error> {
+        let db = Db::new(setup_config("compaction", 200))?;
+
+        for i in 0..10 {
+            db.set("overwritten".to_string(), Bytes::from(i.to_string()))?;
+        }
+        db.set("kept".to_string(), Bytes::from("kept"))?;
+        db.set("deleted".to_string(), Bytes::from("deleted"))?;
+        db.delete("deleted".to_string())?;
+
+        let active_segment = db.index.lock().unwrap().segments.active;
+        let active_path = segment::segment_path(&db.config.dir, active_segment);
+        let active_before = fs::read(&active_path)?;
+
+        db.run_compaction()?;
+
+        // All closed segments are merged into one, active segment is left untouched
+        let segments = segment::list_segments(&db.config.dir)?;
+        assert_eq!(segments, vec![active_segment - 1, active_segment]);
+        assert_eq!(fs::read(&active_path)?, active_before);
+
+        assert_eq!(
+            db.get("overwritten")?.unwrap().value,
+            Some(Bytes::from("9"))
+        );
+        assert_eq!(db.get("kept")?.unwrap().value, Some(Bytes::from("kept")));
+        assert!(db.get("deleted")?.is_none());
+
+        // Writes after compaction go on top of the merged segments
+        db.set("kept".to_string(), Bytes::from("updated"))?;
+
+        let db = reopen(db)?;
+
+        assert_eq!(
+            db.get("overwritten")?.unwrap().value,
+            Some(Bytes::from("9"))
+        );
+        assert_eq!(db.get("kept")?.unwrap().value, Some(Bytes::from("updated")));
+        assert!(db.get("deleted")?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_writes_during_compaction() -> Result<(), crate::Error> {
+        let db = Db::new(setup_config("writes_during_compaction", 200))?;
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        // Segments rolled while a merge is being written have to survive it
+        let compactor = {
+            let db = db.clone();
+            let done = done.clone();
+
+            std::thread::spawn(move || {
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    db.run_compaction().unwrap();
+                }
+            })
+        };
+
+        for i in 0..1000 {
+            db.set(format!("key_{}", i), Bytes::from(i.to_string()))?;
+        }
+
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+        compactor.join().unwrap();
+
+        db.run_compaction()?;
+        let db = reopen(db)?;
+
+        for i in 0..1000 {
+            let record = db.get(&format!("key_{}", i))?.unwrap();
+            assert_eq!(record.value, Some(Bytes::from(i.to_string())));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_field_updates() -> Result<(), crate::Error> {
+        let db = Db::new(setup_config("hash_field_updates", 300))?;
+
+        let pairs = |pairs: &[(&str, &str)]| -> Vec<(String, Bytes)> {
+            pairs
+                .iter()
+                .map(|(field, value)| (field.to_string(), Bytes::from(value.to_string())))
+                .collect()
+        };
+
+        assert_eq!(
+            db.hash_set("user", pairs(&[("name", "Ada"), ("lang", "en")]))?,
+            2
+        );
+        assert_eq!(
+            db.hash_set("user", pairs(&[("lang", "fr"), ("age", "36")]))?,
+            1
+        );
+        assert_eq!(
+            db.hash_delete("user", &["name".to_string(), "missing".to_string()])?,
+            1
+        );
+
+        // Hash is only written in full once, the rest are updates of single fields
+        assert_eq!(db.index.lock().unwrap().field_updates["user"].len(), 3);
+
+        let expected: Fields = pairs(&[("age", "36"), ("lang", "fr")])
+            .into_iter()
+            .collect();
+
+        assert_eq!(db.hash_get_all("user")?, expected);
+        assert_eq!(db.hash_get("user", "lang")?, Some(Bytes::from("fr")));
+        assert_eq!(db.hash_get("user", "name")?, None);
+        assert!(StorageEngine::get(&db, "user").is_err());
+
+        db.set("plain".to_string(), Bytes::from("1"))?;
+        assert!(db.hash_set("plain", pairs(&[("a", "1")])).is_err());
+
+        // Version of the hash is that of its last field update
+        let version = db.get_value("user")?.unwrap().version;
+        assert_eq!(version, db.index.lock().unwrap().last_version - 1);
+
+        let db = reopen(db)?;
+
+        assert_eq!(db.hash_get_all("user")?, expected);
+        assert_eq!(db.get_value("user")?.unwrap().version, version);
+
+        for i in 0..5 {
+            db.set(format!("filler_{}", i), Bytes::from(vec![0; 100]))?;
+        }
+
+        db.run_compaction()?;
+
+        // Compaction folds the updates into a single full record of the hash
+        assert!(db.index.lock().unwrap().field_updates.is_empty());
+        assert_eq!(db.hash_get_all("user")?, expected);
+        assert_eq!(db.get_value("user")?.unwrap().version, version);
+
+        db.hash_set("user", pairs(&[("name", "Grace")]))?;
+
+        let db = reopen(db)?;
+
+        let expected: Fields = pairs(&[("age", "36"), ("lang", "fr"), ("name", "Grace")])
+            .into_iter()
+            .collect();
+
+        assert_eq!(db.hash_get_all("user")?, expected);
+
+        // Hash written in full, as a transaction does, replaces the field updates
+        let transaction = engine::Transaction::new(&db, vec![]);
+        transaction.hash_delete("user", &["age".to_string()])?;
+        assert!(transaction.commit()?);
+
+        assert!(!db.index.lock().unwrap().field_updates.contains_key("user"));
+        assert_eq!(db.hash_get("user", "age")?, None);
+        assert_eq!(db.hash_get("user", "name")?, Some(Bytes::from("Grace")));
+
+        // Hash goes away along with its last field
+        assert_eq!(
+            db.hash_delete("user", &["lang".to_string(), "name".to_string()])?,
+            2
+        );
+        assert!(!db.contains("user")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sets_and_sorted_sets() -> Result<(), crate::Error> {
+        let db = Db::new(setup_config("sets_and_sorted_sets", 200))?;
+
+        let members = |members: &[&'static str]| -> Vec<Bytes> {
+            members.iter().map(|member| Bytes::from(*member)).collect()
+        };
+
+        assert_eq!(db.set_add("tags", members(&["rust", "db", "rust"]))?, 2);
+        assert_eq!(db.set_add("other", members(&["db", "go"]))?, 2);
+        assert_eq!(db.set_remove("other", &members(&["go", "c"]))?, 1);
+
+        let scores = vec![(3.0, Bytes::from("ada")), (1.0, Bytes::from("bob"))];
+        assert_eq!(db.sorted_set_add("board", scores)?, 2);
+        assert_eq!(
+            db.sorted_set_increment("board", Bytes::from("bob"), 5.0)?,
+            Some(6.0)
+        );
+
+        // Filler closes the segments, so that compaction merges the collections
+        for i in 0..10 {
+            db.set("filler".to_string(), Bytes::from(i.to_string()))?;
+        }
+
+        db.run_compaction()?;
+        let db = reopen(db)?;
+
+        let engine: &dyn StorageEngine = &db;
+
+        assert_eq!(
+            engine.set_members("tags")?.into_iter().collect::<Vec<_>>(),
+            members(&["db", "rust"])
+        );
+        assert!(engine.set_contains("other", b"db")?);
+        assert!(!engine.set_contains("other", b"go")?);
+        assert_eq!(
+            engine
+                .set_intersect(&["tags".to_string(), "other".to_string()])?
+                .into_iter()
+                .collect::<Vec<_>>(),
+            members(&["db"])
+        );
+        assert_eq!(
+            engine.sorted_set_range("board", 0, -1)?,
+            vec![(Bytes::from("ada"), 3.0), (Bytes::from("bob"), 6.0)]
+        );
+        assert_eq!(engine.sorted_set_rank("board", b"bob")?, Some(1));
+
+        // Type of the value is kept in the record, so it's known after a reopen too
+        let err = engine.get("tags").unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<WrongType>(),
+            Some(&WrongType {
+                expected: Kind::String,
+                found: Kind::Set,
+            })
+        );
+        assert!(engine.set_add("board", members(&["a"])).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_secondary_indexes() -> Result<(), crate::Error> {
+        let mut config = setup_config("secondary_indexes", 200);
+        config.indexes = vec![IndexDefinition {
+            name: "by_user".to_string(),
+            pattern: "order:*".to_string(),
+            field: JsonPath::parse("user_id")?,
+        }];
+
+        let db = Db::new(config)?;
+        let engine: &dyn StorageEngine = &db;
+
+        let order = |user_id: &str| Bytes::from(format!(r#"{{"user_id": "{}"}}"#, user_id));
+        let keys = |keys: &[&str]| Some(keys.iter().map(|key| key.to_string()).collect());
+
+        db.set("order:1".to_string(), order("ada"))?;
+        db.set("order:2".to_string(), order("ada"))?;
+        db.set("order:3".to_string(), order("bob"))?;
+        db.set("user:1".to_string(), order("ada"))?;
+
+        assert_eq!(
+            engine.find("by_user", &json!("ada"))?,
+            keys(&["order:1", "order:2"])
+        );
+        assert_eq!(engine.find("by_name", &json!("ada"))?, None);
+
+        db.delete("order:1".to_string())?;
+        engine.json_set("order:3", &JsonPath::parse("user_id")?, json!("ada"))?;
+        db.set("order:2".to_string(), Bytes::from("not json"))?;
+
+        assert_eq!(engine.find("by_user", &json!("ada"))?, keys(&["order:3"]));
+        assert_eq!(engine.find("by_user", &json!("bob"))?, keys(&[]));
+
+        // Filler closes the segments, so that the index is rebuilt from a merge output too
+        for i in 0..10 {
+            db.set("filler".to_string(), Bytes::from(i.to_string()))?;
+        }
+
+        db.run_compaction()?;
+        db.set("order:4".to_string(), order("ada"))?;
+
+        let db = reopen(db)?;
+        let engine: &dyn StorageEngine = &db;
+
+        assert_eq!(
+            engine.find("by_user", &json!("ada"))?,
+            keys(&["order:3", "order:4"])
+        );
+
+        engine.set_expiry("order:4", Some(now_millis() - 1))?;
+
+        assert_eq!(engine.find("by_user", &json!("ada"))?, keys(&["order:3"]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_point_in_time_reads() -> Result<(), crate::Error> {
+        let db = Db::new(setup_config("point_in_time", 200))?;
+        let version = |db: &Db| db.index.lock().unwrap().last_version;
+
+        db.set("key".to_string(), Bytes::from("1"))?;
+        let first = version(&db);
+        db.set("key".to_string(), Bytes::from("2"))?;
+        let second = version(&db);
+        db.delete("key".to_string())?;
+        let deleted = version(&db);
+        db.set("key".to_string(), Bytes::from("3"))?;
+        db.set("other".to_string(), Bytes::from("1"))?;
+
+        assert!(db.history_start().unwrap() <= first);
+
+        let value_at = |db: &Db, at| -> Result<Option<Bytes>, crate::Error> {
+            Ok(db.get_value_at("key", at)?.map(|value| value.bytes))
+        };
+
+        assert_eq!(value_at(&db, first)?, Some(Bytes::from("1")));
+        assert_eq!(value_at(&db, second)?, Some(Bytes::from("2")));
+        assert_eq!(value_at(&db, deleted)?, None);
+        assert_eq!(value_at(&db, version(&db))?, Some(Bytes::from("3")));
+        assert_eq!(db.get_value_at("other", second)?, None);
+
+        // Snapshot sees the keys deleted since, and not the ones written since
+        assert_eq!(
+            db.scan_at((Bound::Unbounded, Bound::Unbounded), false, 10, second)?,
+            vec![("key".to_string(), Bytes::from("2"))]
+        );
+        assert!(db
+            .scan_at((Bound::Unbounded, Bound::Unbounded), false, 10, deleted)?
+            .is_empty());
+
+        // Past versions survive compaction, without being taken for current ones later on
+        db.set("gone".to_string(), Bytes::from("gone"))?;
+        let gone = version(&db);
+        db.delete("gone".to_string())?;
+        db.set("filler".to_string(), Bytes::from("x".repeat(200)))?;
+
+        db.run_compaction()?;
+
+        assert_eq!(value_at(&db, second)?, Some(Bytes::from("2")));
+        assert_eq!(
+            db.get_value_at("gone", gone)?.map(|value| value.bytes),
+            Some(Bytes::from("gone"))
+        );
+
+        for entry in fs::read_dir(&db.config.dir)? {
+            let path = entry?.path();
+
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "hint")
+            {
+                fs::remove_file(path)?;
+            }
+        }
+
+        let db = reopen(db)?;
+
+        assert_eq!(db.get("key")?.unwrap().value, Some(Bytes::from("3")));
+        assert!(db.get("gone")?.is_none());
+
+        // History only covers the writes made since opening
+        assert!(db.history_start().unwrap() > version(&db));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_history_retention() -> Result<(), crate::Error> {
+        let db = Db::new(Config {
+            history_retention: Duration::from_millis(50),
+            ..setup_config("history_retention", 200)
+        })?;
+
+        db.set("key".to_string(), Bytes::from("1"))?;
+        db.set("key".to_string(), Bytes::from("2"))?;
+        db.delete("key".to_string())?;
+
+        assert!(db.index.lock().unwrap().history.contains_key("key"));
+
+        std::thread::sleep(Duration::from_millis(100));
+        db.purge_expired()?;
+
+        let index_state = db.index.lock().unwrap();
+        assert!(index_state.history.is_empty());
+        assert!(index_state.superseded.is_empty());
+        drop(index_state);
+
+        let db = Db::new(Config {
+            history_retention: Duration::ZERO,
+            ..setup_config("no_history", 200)
+        })?;
+
+        db.set("key".to_string(), Bytes::from("1"))?;
+        db.set("key".to_string(), Bytes::from("2"))?;
+
+        assert!(db.history_start().is_none());
+        assert!(db.index.lock().unwrap().history.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_handles_reuse() -> Result<(), crate::Error> {
+        let db = Db::new(setup_config("file_handles", 100))?;
+
+        for i in 0..10 {
+            db.set("key".to_string(), Bytes::from(i.to_string()))?;
+        }
+
+        let first = db.index.lock().unwrap().segments.file(1)?;
+        let second = db.index.lock().unwrap().segments.file(1)?;
+        assert!(Arc::ptr_eq(&first, &second));
+
+        db.run_compaction()?;
+
+        // Handle taken before the merge is still readable, while the new one
+        // points to the merge output
+        let merged_segment = *db.index.lock().unwrap().segments.closed.last().unwrap();
+        let merged = db.index.lock().unwrap().segments.file(merged_segment)?;
+        assert!(!Arc::ptr_eq(&first, &merged));
+        assert!(!SegmentReader::File(first).read(0, 1)?.is_empty());
+
+        assert_eq!(db.get("key")?.unwrap().value, Some(Bytes::from("9")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mmap_reads() -> Result<(), crate::Error> {
+        let config = Config {
+            mmap_reads: true,
+            ..setup_config("mmap_reads", 100)
+        };
+
+        let db = Db::new(config)?;
+
+        for i in 0..10 {
+            db.set(format!("key_{}", i), Bytes::from(i.to_string()))?;
+        }
+
+        let (closed_reader, active_reader) = {
+            let mut index_state = db.index.lock().unwrap();
+            let closed_segment = *index_state.segments.closed.first().unwrap();
+            let active_segment = index_state.segments.active;
+
+            (
+                index_state.segments.reader(closed_segment)?,
+                index_state.segments.reader(active_segment)?,
+            )
+        };
+
+        let map = match closed_reader {
+            SegmentReader::Mapped(map) => map,
+            SegmentReader::File(_) => panic!("closed segment is expected to be mapped"),
+        };
+        assert!(matches!(active_reader, SegmentReader::File(_)));
+
+        // Value from the closed segment points right into the mapping
+        let value = db.get("key_0")?.unwrap().value.unwrap();
+        assert!(map.as_ptr_range().contains(&value.as_ptr()));
+        assert_eq!(value, Bytes::from("0"));
+
+        db.run_compaction()?;
+
+        for i in 0..10 {
+            let record = db.get(&format!("key_{}", i))?.unwrap();
+            assert_eq!(record.value, Some(Bytes::from(i.to_string())));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hint_files() -> Result<(), crate::Error> {
+        let db = Db::new(setup_config("hint_files", 100))?;
+
+        for i in 0..10 {
+            db.set(format!("key_{}", i), Bytes::from(i.to_string()))?;
+        }
+        db.delete("key_0".to_string())?;
+
+        db.run_compaction()?;
+
+        let merged_segment = *db.index.lock().unwrap().segments.closed.last().unwrap();
+        let hint_path = segment::hint_path(&db.config.dir, merged_segment);
+        assert!(hint_path.exists());
+
+        let db = reopen(db)?;
+
+        assert!(db.get("key_0")?.is_none());
+        for i in 1..10 {
+            let record = db.get(&format!("key_{}", i))?.unwrap();
+            assert_eq!(record.value, Some(Bytes::from(i.to_string())));
+        }
+
+        // Merged segment is never appended to, otherwise its hint would go stale
+        db.set("key_0".to_string(), Bytes::from("restored"))?;
+        assert!(db.index.lock().unwrap().segments.active > merged_segment);
+
+        // Corrupted hint is ignored in favour of scanning the segment
+        let mut hint = fs::read(&hint_path)?;
+        hint[0] ^= 0xff;
+        fs::write(&hint_path, hint)?;
+
+        let db = reopen(db)?;
+
+        assert_eq!(
+            db.get("key_0")?.unwrap().value,
+            Some(Bytes::from("restored"))
+        );
+        for i in 1..10 {
+            let record = db.get(&format!("key_{}", i))?.unwrap();
+            assert_eq!(record.value, Some(Bytes::from(i.to_string())));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_torn_write_repair() -> Result<(), crate::Error> {
+        let db = setup_db("torn_write")?;
+
+        db.set("first".to_string(), Bytes::from("1"))?;
+        db.set("second".to_string(), Bytes::from("2"))?;
+
+        let dir = db.config.dir.clone();
+        let path = segment::segment_path(&dir, segment::FIRST_SEGMENT_ID);
+        let intact_len = fs::metadata(&path)?.len();
+
+        // Only part of the record made it to disk before the crash
+        let torn = FileRecord::new("third".to_string(), Some(Bytes::from("3")), false).encode();
+        let mut file = OpenOptions::new().append(true).open(&path)?;
+        file.write_all(&torn[..torn.len() - 2])?;
+
+        let db = reopen(db)?;
+
+        assert_eq!(fs::metadata(&path)?.len(), intact_len);
+        assert_eq!(db.get("first")?.unwrap().value, Some(Bytes::from("1")));
+        assert_eq!(db.get("second")?.unwrap().value, Some(Bytes::from("2")));
+        assert!(db.get("third")?.is_none());
+
+        let quarantined: Vec<_> = fs::read_dir(&dir)?
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_str().unwrap().contains(".quarantine."))
+            .collect();
+
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(fs::read(&quarantined[0])?, &torn[..torn.len() - 2]);
+
+        // Segment is appendable again
+        db.set("third".to_string(), Bytes::from("3"))?;
+
+        let db = reopen(db)?;
+
+        assert_eq!(db.get("third")?.unwrap().value, Some(Bytes::from("3")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_torn_batch_repair() -> Result<(), crate::Error> {
+        let db = setup_db("torn_batch")?;
+
+        db.write_batch(vec![
+            (
+                "first".to_string(),
+                Some(Value::new(Bytes::from("1"), None)),
+            ),
+            (
+                "second".to_string(),
+                Some(Value::new(Bytes::from("2"), None)),
+            ),
+        ])?;
+        db.write_batch(vec![
+            ("first".to_string(), None),
+            (
+                "third".to_string(),
+                Some(Value::new(Bytes::from("3"), None)),
+            ),
+        ])?;
+
+        assert!(db.get("first")?.is_none());
+        assert_eq!(db.get("third")?.unwrap().value, Some(Bytes::from("3")));
+
+        let path = segment::segment_path(&db.config.dir, segment::FIRST_SEGMENT_ID);
+        let intact_len = fs::metadata(&path)?.len();
+
+        // Batch is cut off right after its first record, which is complete on its own
+        let mut record = FileRecord::new("fourth".to_string(), Some(Bytes::from("4")), false);
+        record.batch_continues = true;
+        let mut file = OpenOptions::new().append(true).open(&path)?;
+        file.write_all(&record.encode())?;
+
+        let db = reopen(db)?;
+
+        assert_eq!(fs::metadata(&path)?.len(), intact_len);
+        assert!(db.get("first")?.is_none());
+        assert_eq!(db.get("second")?.unwrap().value, Some(Bytes::from("2")));
+        assert_eq!(db.get("third")?.unwrap().value, Some(Bytes::from("3")));
+        assert!(db.get("fourth")?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_conditional_batch() -> Result<(), crate::Error> {
+        let db = setup_db("conditional_batch")?;
+
+        db.set("watched".to_string(), Bytes::from("1"))?;
+        let version = db.get_value("watched")?.map(|value| value.version);
+
+        let batch = vec![
+            (
+                "first".to_string(),
+                Some(Value::new(Bytes::from("1"), None)),
+            ),
+            (
+                "second".to_string(),
+                Some(Value::new(Bytes::from("2"), None)),
+            ),
+        ];
+
+        assert!(!db.write_batch_if(vec![("watched".to_string(), None)], batch.clone())?);
+        assert!(db.get("first")?.is_none());
+
+        assert!(db.write_batch_if(
+            vec![
+                ("watched".to_string(), version),
+                ("missing".to_string(), None)
+            ],
+            batch
+        )?);
+
+        let db = reopen(db)?;
+
+        assert_eq!(db.get("first")?.unwrap().value, Some(Bytes::from("1")));
+        assert_eq!(db.get("second")?.unwrap().value, Some(Bytes::from("2")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_record_repair() -> Result<(), crate::Error> {
+        let db = setup_db("corrupted_record")?;
+
+        db.set("first".to_string(), Bytes::from("1"))?;
+        db.set("second".to_string(), Bytes::from("2"))?;
+
+        let path = segment::segment_path(&db.config.dir, segment::FIRST_SEGMENT_ID);
+
+        let mut content = fs::read(&path)?;
+        let last = content.len() - 1;
+        content[last] ^= 0xff;
+        fs::write(&path, content)?;
+
+        let db = reopen(db)?;
+
+        assert_eq!(db.get("first")?.unwrap().value, Some(Bytes::from("1")));
+        assert!(db.get("second")?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_closed_segment() -> Result<(), crate::Error> {
+        let db = Db::new(setup_config("corrupted_closed_segment", 30))?;
+
+        db.set("first".to_string(), Bytes::from("1"))?;
+        db.set("second".to_string(), Bytes::from("2"))?;
+        db.set("third".to_string(), Bytes::from("3"))?;
+
+        let dir = db.config.dir.clone();
+        let path = segment::segment_path(&dir, segment::FIRST_SEGMENT_ID);
+        let intact = fs::read(&path)?;
+
+        let mut content = intact.clone();
+        let last = content.len() - 1;
+        content[last] ^= 0xff;
+        fs::write(&path, content)?;
+
+        // Records past the corruption can't be told apart from garbage, so nothing
+        // is truncated and opening fails instead
+        let config = db.config.as_ref().clone();
+        drop(db);
+
+        let err = Db::new(config.clone()).err().unwrap();
+        assert!(err
+            .to_string()
+            .contains("segment 1 has an unreadable record"));
+        assert_eq!(fs::metadata(&path)?.len(), intact.len() as u64);
+
+        fs::write(&path, intact)?;
+        let db = Db::new(config)?;
+
+        assert_eq!(db.get("first")?.unwrap().value, Some(Bytes::from("1")));
+        assert_eq!(db.get("third")?.unwrap().value, Some(Bytes::from("3")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unfinished_merge_is_discarded() -> Result<(), crate::Error> {
+        let db = Db::new(setup_config("unfinished_merge", 100))?;
+
+        db.set("first".to_string(), Bytes::from("1"))?;
+        db.set("second".to_string(), Bytes::from("2"))?;
+
+        let merge_tmp_path = segment::merge_tmp_path(&db.config.dir, 1);
+        fs::write(
+            &merge_tmp_path,
+            &FileRecord::new("first".to_string(), None, true).encode()[..10],
+        )?;
+
+        let db = reopen(db)?;
+
+        assert!(!merge_tmp_path.exists());
+        assert_eq!(db.get("first")?.unwrap().value, Some(Bytes::from("1")));
+        assert_eq!(db.get("second")?.unwrap().value, Some(Bytes::from("2")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_committed_merge_is_rolled_forward() -> Result<(), crate::Error> {
+        let config = setup_config("committed_merge", 30);
+        let dir = config.dir.clone();
+
+        let db = Db::new(config)?;
+
+        db.set("first".to_string(), Bytes::from("1"))?;
+        db.set("second".to_string(), Bytes::from("2"))?;
+        db.set("first".to_string(), Bytes::from("3"))?;
+
+        // Pretend the merge of first two segments has crashed right after the commit point
+        let merged = FileRecord::new("second".to_string(), Some(Bytes::from("2")), false).encode();
+        fs::write(segment::merge_path(&dir, 2), merged)?;
+
+        let db = reopen(db)?;
+
+        assert_eq!(segment::list_segments(&dir)?, vec![2, 3]);
+        assert!(!segment::merge_path(&dir, 2).exists());
+        assert_eq!(db.get("first")?.unwrap().value, Some(Bytes::from("3")));
+        assert_eq!(db.get("second")?.unwrap().value, Some(Bytes::from("2")));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,580 @@
+use std::fmt;
+use std::io::{self, Read};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::engine::{Kind, Value};
+
+/*
+    Binary record layout (all integers are big-endian):
+
+    +-------+---------+-------+-----------+---------+-----------+-----+-------+
+    | crc32 | version | flags | timestamp | key_len | value_len | key | value |
+    |  u32  |   u8    |  u8   |    u64    |   u32   |    u32    |     |       |
+    +-------+---------+-------+-----------+---------+-----------+-----+-------+
+
+    - crc32 covers everything that follows it, up to the end of the value
+    - timestamp is in milliseconds since the unix epoch
+    - flags bit 0 marks a tombstone, tombstones are written with an empty value
+    - flags bit 1 marks a record which expires, in which case the header is followed
+      by the expiration time as u64 milliseconds since the unix epoch, before the key
+    - flags bit 2 marks a record of a batch which is followed by more records of the
+      same batch; batch is only applied once its last record, without the bit, is read
+    - flags bit 3 marks a superseded record carried over by compaction, so that the
+      value could still be read as of the past; it's never taken for the current one
+    - flags bits 4 to 6 hold the type of the value: 0 for a string, 1 for a hash,
+      2 for a list, 3 for a set and 4 for a sorted set
+    - flags bit 7 marks an update of a single hash field, written on top of the
+      last full record of the hash; value starts with the field as u32 length and
+      bytes, followed by the value of the field, and a tombstone deletes the field
+*/
+
+pub(crate) const HEADER_LEN: usize = 22;
+
+const FORMAT_VERSION: u8 = 1;
+
+const TOMBSTONE_FLAG: u8 = 0b0000_0001;
+const EXPIRES_FLAG: u8 = 0b0000_0010;
+const BATCH_CONTINUES_FLAG: u8 = 0b0000_0100;
+const HISTORICAL_FLAG: u8 = 0b0000_1000;
+const KIND_MASK: u8 = 0b0111_0000;
+const FIELD_FLAG: u8 = 0b1000_0000;
+
+const KIND_SHIFT: u32 = 4;
+
+const EXPIRES_AT_LEN: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileRecord {
+    pub(crate) key: String,
+    pub(crate) value: Option<Bytes>,
+    pub(crate) timestamp: u64,
+    pub(crate) is_tombstone: bool,
+    /// Milliseconds since the unix epoch after which the record is treated as deleted
+    pub(crate) expires_at: Option<u64>,
+    /// More records of the same batch follow this one
+    pub(crate) batch_continues: bool,
+    /// Record only holds a past version of the key
+    pub(crate) is_historical: bool,
+    pub(crate) kind: Kind,
+    /// Hash field the record updates, rather than the whole value of the key
+    pub(crate) field: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    /// Buffer ends before the record does
+    Incomplete,
+    /// Record is complete but its content doesn't add up
+    Corrupted(String),
+    Io(io::Error),
+}
+
+impl FileRecord {
+    pub fn new(key: String, value: Option<Bytes>, is_tombstone: bool) -> FileRecord {
+        FileRecord::with_timestamp(key, value, is_tombstone, now_millis())
+    }
+
+    pub(crate) fn with_timestamp(
+        key: String,
+        value: Option<Bytes>,
+        is_tombstone: bool,
+        timestamp: u64,
+    ) -> FileRecord {
+        FileRecord {
+            key,
+            value,
+            timestamp,
+            is_tombstone,
+            expires_at: None,
+            batch_continues: false,
+            is_historical: false,
+            kind: Kind::String,
+            field: None,
+        }
+    }
+
+    /// Record setting the field of the hash, or deleting it for `None`.
+    pub(crate) fn field_update(key: String, field: String, value: Option<Bytes>) -> FileRecord {
+        let is_tombstone = value.is_none();
+
+        FileRecord {
+            kind: Kind::Hash,
+            field: Some(field),
+            ..FileRecord::new(key, value, is_tombstone)
+        }
+    }
+
+    pub(crate) fn of_kind(mut self, kind: Kind) -> FileRecord {
+        self.kind = kind;
+        self
+    }
+
+    pub(crate) fn expiring_at(mut self, expires_at: Option<u64>) -> FileRecord {
+        self.expires_at = expires_at;
+        self
+    }
+
+    /// Record writing the value, or a tombstone for `None`.
+    pub(crate) fn from_value(key: String, value: Option<Value>) -> FileRecord {
+        match value {
+            Some(value) => FileRecord::new(key, Some(value.bytes), false)
+                .expiring_at(value.expires_at)
+                .of_kind(value.kind),
+            None => FileRecord::new(key, None, true),
+        }
+    }
+
+    /// Value the record holds, `None` for a tombstone.
+    pub(crate) fn into_value(self) -> Option<Value> {
+        if self.is_tombstone {
+            return None;
+        }
+
+        Some(Value {
+            bytes: self.value.unwrap_or_default(),
+            expires_at: self.expires_at,
+            version: self.timestamp,
+            kind: self.kind,
+        })
+    }
+
+    pub fn get_val_bytes(&self) -> Option<Bytes> {
+        self.value.clone()
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let value = match (&self.value, self.is_tombstone) {
+            (Some(value), false) => &value[..],
+            _ => &[],
+        };
+
+        let field = self.field.as_deref().unwrap_or_default();
+        let field_len = self.field.as_ref().map_or(0, |field| 4 + field.len());
+
+        let mut flags = kind_tag(self.kind) << KIND_SHIFT;
+
+        if self.is_tombstone {
+            flags |= TOMBSTONE_FLAG;
+        }
+
+        if self.expires_at.is_some() {
+            flags |= EXPIRES_FLAG;
+        }
+
+        if self.batch_continues {
+            flags |= BATCH_CONTINUES_FLAG;
+        }
+
+        if self.is_historical {
+            flags |= HISTORICAL_FLAG;
+        }
+
+        if self.field.is_some() {
+            flags |= FIELD_FLAG;
+        }
+
+        let capacity = HEADER_LEN + EXPIRES_AT_LEN + self.key.len() + field_len + value.len();
+        let mut buf = BytesMut::with_capacity(capacity);
+
+        // crc placeholder, filled in once the rest is written
+        buf.put_u32(0);
+        buf.put_u8(FORMAT_VERSION);
+        buf.put_u8(flags);
+        buf.put_u64(self.timestamp);
+        buf.put_u32(self.key.len() as u32);
+        buf.put_u32((field_len + value.len()) as u32);
+
+        if let Some(expires_at) = self.expires_at {
+            buf.put_u64(expires_at);
+        }
+
+        buf.put_slice(self.key.as_bytes());
+
+        if self.field.is_some() {
+            buf.put_u32(field.len() as u32);
+            buf.put_slice(field.as_bytes());
+        }
+
+        buf.put_slice(value);
+
+        let crc = crc32fast::hash(&buf[4..]);
+        buf[..4].copy_from_slice(&crc.to_be_bytes());
+
+        buf.freeze()
+    }
+
+    /// Full length of the record, judging by its header.
+    pub(crate) fn encoded_len(header: &[u8]) -> Result<usize, DecodeError> {
+        if header.len() < HEADER_LEN {
+            return Err(DecodeError::Incomplete);
+        }
+
+        // Lengths are only trusted once the header looks like one, the checksum
+        // can't be verified before the whole record is read
+        if header[4] != FORMAT_VERSION {
+            return Err(DecodeError::Corrupted(format!(
+                "unsupported format version {}",
+                header[4]
+            )));
+        }
+
+        let expires_at_len = if header[5] & EXPIRES_FLAG != 0 {
+            EXPIRES_AT_LEN
+        } else {
+            0
+        };
+
+        let mut lengths = &header[14..HEADER_LEN];
+        let key_len = lengths.get_u32() as usize;
+        let value_len = lengths.get_u32() as usize;
+
+        Ok(HEADER_LEN + expires_at_len + key_len + value_len)
+    }
+
+    /// Value of the decoded record is a slice of the buffer rather than a copy.
+    pub fn decode(buf: &Bytes) -> Result<FileRecord, DecodeError> {
+        let len = FileRecord::encoded_len(buf)?;
+
+        if buf.len() < len {
+            return Err(DecodeError::Incomplete);
+        }
+
+        let mut src = &buf[..len];
+
+        let crc = src.get_u32();
+
+        if crc != crc32fast::hash(src) {
+            return Err(DecodeError::Corrupted("checksum mismatch".to_string()));
+        }
+
+        let _version = src.get_u8();
+        let flags = src.get_u8();
+        let timestamp = src.get_u64();
+        let key_len = src.get_u32() as usize;
+        let value_len = src.get_u32() as usize;
+
+        let expires_at = if flags & EXPIRES_FLAG != 0 {
+            Some(src.get_u64())
+        } else {
+            None
+        };
+
+        let key = String::from_utf8(src[..key_len].to_vec())
+            .map_err(|_| DecodeError::Corrupted("key is not valid utf-8".to_string()))?;
+        src.advance(key_len);
+
+        let mut value_start = len - value_len;
+
+        let field = if flags & FIELD_FLAG != 0 {
+            if value_len < 4 || (&src[..4]).get_u32() as usize > value_len - 4 {
+                return Err(DecodeError::Corrupted(
+                    "field exceeds the value".to_string(),
+                ));
+            }
+
+            let field_len = src.get_u32() as usize;
+            let field = String::from_utf8(src[..field_len].to_vec())
+                .map_err(|_| DecodeError::Corrupted("field is not valid utf-8".to_string()))?;
+            value_start += 4 + field_len;
+
+            Some(field)
+        } else {
+            None
+        };
+
+        let is_tombstone = flags & TOMBSTONE_FLAG != 0;
+
+        let value = if is_tombstone {
+            None
+        } else {
+            Some(buf.slice(value_start..len))
+        };
+
+        Ok(FileRecord {
+            key,
+            value,
+            timestamp,
+            is_tombstone,
+            expires_at,
+            batch_continues: flags & BATCH_CONTINUES_FLAG != 0,
+            is_historical: flags & HISTORICAL_FLAG != 0,
+            kind: kind_from_tag((flags & KIND_MASK) >> KIND_SHIFT)?,
+            field,
+        })
+    }
+
+    /// Reads the next record from a sequential source, such as a segment file.
+    /// Every record comes along with its encoded length, and `None` is returned
+    /// once the source is exhausted right at a record boundary.
+    pub(crate) fn read_from(src: &mut impl Read) -> Result<Option<(FileRecord, u64)>, DecodeError> {
+        let mut buf = vec![0; HEADER_LEN];
+
+        match read_full(src, &mut buf)? {
+            0 => return Ok(None),
+            HEADER_LEN => {}
+            _ => return Err(DecodeError::Incomplete),
+        }
+
+        let len = FileRecord::encoded_len(&buf)?;
+
+        // Length can't be trusted before the checksum is verified, so the buffer only
+        // grows as far as the source goes, rather than up front
+        let rest = (&mut *src)
+            .take((len - HEADER_LEN) as u64)
+            .read_to_end(&mut buf)
+            .map_err(DecodeError::Io)?;
+
+        if rest < len - HEADER_LEN {
+            return Err(DecodeError::Corrupted(format!(
+                "record length {} exceeds the {} bytes left",
+                len,
+                HEADER_LEN + rest
+            )));
+        }
+
+        let record = FileRecord::decode(&Bytes::from(buf))?;
+
+        Ok(Some((record, len as u64)))
+    }
+
+    /// Like `read_from`, but hands out all the records of a batch at once, while
+    /// a record which isn't part of a batch comes on its own. Batch cut off by
+    /// the end of the source is as incomplete as a torn record.
+    pub(crate) fn read_batch_from(
+        src: &mut impl Read,
+    ) -> Result<Option<Vec<(FileRecord, u64)>>, DecodeError> {
+        let mut batch = vec![];
+
+        loop {
+            match FileRecord::read_from(src)? {
+                Some((record, len)) => {
+                    let batch_continues = record.batch_continues;
+                    batch.push((record, len));
+
+                    if !batch_continues {
+                        return Ok(Some(batch));
+                    }
+                }
+                None if batch.is_empty() => return Ok(None),
+                None => return Err(DecodeError::Incomplete),
+            }
+        }
+    }
+}
+
+pub(crate) fn now_millis() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let now = SystemTime::now();
+    let since_the_epoch = now.duration_since(UNIX_EPOCH).unwrap();
+
+    since_the_epoch.as_millis() as u64
+}
+
+fn kind_tag(kind: Kind) -> u8 {
+    match kind {
+        Kind::String => 0,
+        Kind::Hash => 1,
+        Kind::List => 2,
+        Kind::Set => 3,
+        Kind::SortedSet => 4,
+    }
+}
+
+fn kind_from_tag(tag: u8) -> Result<Kind, DecodeError> {
+    match tag {
+        0 => Ok(Kind::String),
+        1 => Ok(Kind::Hash),
+        2 => Ok(Kind::List),
+        3 => Ok(Kind::Set),
+        4 => Ok(Kind::SortedSet),
+        _ => Err(DecodeError::Corrupted(format!(
+            "unknown value type {}",
+            tag
+        ))),
+    }
+}
+
+/// Unlike `read_exact`, tells how many bytes were read before the source ended.
+fn read_full(src: &mut impl Read, buf: &mut [u8]) -> Result<usize, DecodeError> {
+    let mut read = 0;
+
+    while read < buf.len() {
+        match src.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(DecodeError::Io(err)),
+        }
+    }
+
+    Ok(read)
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Incomplete => "record ended early".fmt(f),
+            DecodeError::Corrupted(reason) => write!(f, "corrupted record; {}", reason),
+            DecodeError::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<io::Error> for DecodeError {
+    fn from(src: io::Error) -> DecodeError {
+        DecodeError::Io(src)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding_roundtrip() {
+        let record = FileRecord::new("key".to_string(), Some(Bytes::from("value")), false);
+        let encoded = record.encode();
+
+        assert_eq!(encoded.len(), HEADER_LEN + 3 + 5);
+        assert_eq!(FileRecord::decode(&encoded).unwrap(), record);
+
+        let tombstone = FileRecord::new("key".to_string(), None, true);
+        let encoded = tombstone.encode();
+
+        assert_eq!(encoded.len(), HEADER_LEN + 3);
+        assert_eq!(FileRecord::decode(&encoded).unwrap(), tombstone);
+
+        let expiring = FileRecord::new("key".to_string(), Some(Bytes::from("value")), false)
+            .expiring_at(Some(1_700_000_000_000));
+        let encoded = expiring.encode();
+
+        assert_eq!(encoded.len(), HEADER_LEN + 8 + 3 + 5);
+        assert_eq!(FileRecord::decode(&encoded).unwrap(), expiring);
+
+        let mut historical = FileRecord::new("key".to_string(), Some(Bytes::from("value")), false);
+        historical.is_historical = true;
+        let encoded = historical.encode();
+
+        assert_eq!(FileRecord::decode(&encoded).unwrap(), historical);
+
+        let hash = FileRecord::new("key".to_string(), Some(Bytes::from("fields")), false)
+            .of_kind(Kind::Hash);
+        let encoded = hash.encode();
+
+        assert_eq!(FileRecord::decode(&encoded).unwrap(), hash);
+
+        let list = FileRecord::new("key".to_string(), Some(Bytes::from("items")), false)
+            .of_kind(Kind::List);
+        let encoded = list.encode();
+
+        assert_eq!(FileRecord::decode(&encoded).unwrap(), list);
+
+        let field = FileRecord::field_update(
+            "key".to_string(),
+            "field".to_string(),
+            Some(Bytes::from("value")),
+        );
+        let encoded = field.encode();
+
+        assert_eq!(encoded.len(), HEADER_LEN + 3 + 4 + 5 + 5);
+        assert_eq!(FileRecord::decode(&encoded).unwrap(), field);
+
+        let deleted_field = FileRecord::field_update("key".to_string(), "field".to_string(), None);
+        let encoded = deleted_field.encode();
+
+        assert_eq!(encoded.len(), HEADER_LEN + 3 + 4 + 5);
+        assert_eq!(FileRecord::decode(&encoded).unwrap(), deleted_field);
+    }
+
+    #[test]
+    fn test_corruption_detection() {
+        let record = FileRecord::new("key".to_string(), Some(Bytes::from("value")), false);
+        let mut encoded = record.encode().to_vec();
+        let last = encoded.len() - 1;
+        encoded[last] ^= 0xff;
+
+        assert!(matches!(
+            FileRecord::decode(&Bytes::copy_from_slice(&encoded[..encoded.len() - 1])),
+            Err(DecodeError::Incomplete)
+        ));
+
+        assert!(matches!(
+            FileRecord::decode(&Bytes::from(encoded)),
+            Err(DecodeError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn test_sequential_reading() {
+        let first = FileRecord::new("first".to_string(), Some(Bytes::from("1")), false);
+        let second = FileRecord::new("second".to_string(), None, true);
+
+        let mut buf = first.encode().to_vec();
+        buf.extend_from_slice(&second.encode());
+
+        let mut src = &buf[..];
+
+        let (record, len) = FileRecord::read_from(&mut src).unwrap().unwrap();
+        assert_eq!(record, first);
+        assert_eq!(len, first.encode().len() as u64);
+
+        let (record, _) = FileRecord::read_from(&mut src).unwrap().unwrap();
+        assert_eq!(record, second);
+
+        assert!(FileRecord::read_from(&mut src).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_length_past_the_end() {
+        let mut buf = FileRecord::new("key".to_string(), Some(Bytes::from("value")), false)
+            .encode()
+            .to_vec();
+
+        // Key and value lengths are corrupted, while the header still looks valid
+        buf[14..HEADER_LEN].fill(0xff);
+
+        let mut src = &buf[..];
+
+        assert!(matches!(
+            FileRecord::read_from(&mut src),
+            Err(DecodeError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn test_batch_reading() {
+        let mut first = FileRecord::new("first".to_string(), Some(Bytes::from("1")), false);
+        first.batch_continues = true;
+        let second = FileRecord::new("second".to_string(), Some(Bytes::from("2")), false);
+        let single = FileRecord::new("single".to_string(), None, true);
+
+        let mut buf = first.encode().to_vec();
+        buf.extend_from_slice(&second.encode());
+        buf.extend_from_slice(&single.encode());
+
+        let mut src = &buf[..];
+
+        let batch = FileRecord::read_batch_from(&mut src).unwrap().unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[0].0, first);
+        assert_eq!(batch[1].0, second);
+
+        let batch = FileRecord::read_batch_from(&mut src).unwrap().unwrap();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].0, single);
+
+        assert!(FileRecord::read_batch_from(&mut src).unwrap().is_none());
+
+        // Batch missing its last record is never handed out
+        let mut src = &first.encode()[..];
+
+        assert!(matches!(
+            FileRecord::read_batch_from(&mut src),
+            Err(DecodeError::Incomplete)
+        ));
+    }
+}
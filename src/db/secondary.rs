@@ -0,0 +1,184 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde_json::Value as Json;
+
+use crate::cmd::glob;
+use crate::engine::JsonPath;
+
+/// Index over a field of the JSON documents held by the keys matching the pattern,
+/// like `user_id` of `order:*`.
+#[derive(Debug, Clone)]
+pub struct IndexDefinition {
+    pub name: String,
+    /// Glob-style pattern, as in `KEYS`
+    pub pattern: String,
+    pub field: JsonPath,
+}
+
+/// Keys by the value of the indexed field, for every index defined. Only scalar
+/// values are indexed, by their JSON text, so that the string `"7"` and the number
+/// `7` are told apart.
+#[derive(Debug)]
+pub(crate) struct SecondaryIndexes {
+    definitions: Vec<IndexDefinition>,
+    /// Keys by value, for each of the definitions
+    entries: Vec<BTreeMap<String, BTreeSet<String>>>,
+    /// Definitions along with the values each key is indexed under, so that
+    /// the entries could be dropped without reading the previous document
+    indexed: HashMap<String, Vec<(usize, String)>>,
+}
+
+impl SecondaryIndexes {
+    pub(crate) fn new(definitions: Vec<IndexDefinition>) -> Result<SecondaryIndexes, crate::Error> {
+        for (idx, definition) in definitions.iter().enumerate() {
+            if definitions[..idx]
+                .iter()
+                .any(|other| other.name == definition.name)
+            {
+                return Err(format!("index {} is defined more than once", definition.name).into());
+            }
+        }
+
+        Ok(SecondaryIndexes {
+            entries: vec![BTreeMap::new(); definitions.len()],
+            definitions,
+            indexed: HashMap::new(),
+        })
+    }
+
+    /// Whether the value of the key is indexed at all, so that the document
+    /// doesn't have to be read otherwise.
+    pub(crate) fn covers(&self, key: &str) -> bool {
+        self.definitions
+            .iter()
+            .any(|definition| glob::matches(&definition.pattern, key))
+    }
+
+    /// Indexes the current value of the key, which isn't expected to be indexed yet.
+    /// Values which aren't JSON documents are left out.
+    pub(crate) fn add(&mut self, key: &str, value: &[u8]) {
+        if !self.covers(key) {
+            return;
+        }
+
+        let doc: Json = match serde_json::from_slice(value) {
+            Ok(doc) => doc,
+            Err(_) => return,
+        };
+
+        let mut indexed = vec![];
+
+        for (idx, definition) in self.definitions.iter().enumerate() {
+            if !glob::matches(&definition.pattern, key) {
+                continue;
+            }
+
+            if let Some(value) = definition.field.get(&doc).and_then(indexed_value) {
+                self.entries[idx]
+                    .entry(value.clone())
+                    .or_default()
+                    .insert(key.to_string());
+
+                indexed.push((idx, value));
+            }
+        }
+
+        if !indexed.is_empty() {
+            self.indexed.insert(key.to_string(), indexed);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) {
+        for (idx, value) in self.indexed.remove(key).into_iter().flatten() {
+            if let Some(keys) = self.entries[idx].get_mut(&value) {
+                keys.remove(key);
+
+                if keys.is_empty() {
+                    self.entries[idx].remove(&value);
+                }
+            }
+        }
+    }
+
+    /// Keys indexed under the value, in key order. `None` if there is no such index.
+    pub(crate) fn find(&self, name: &str, value: &Json) -> Option<Vec<String>> {
+        let idx = self
+            .definitions
+            .iter()
+            .position(|definition| definition.name == name)?;
+
+        let keys = indexed_value(value)
+            .and_then(|value| self.entries[idx].get(&value))
+            .into_iter()
+            .flatten();
+
+        Some(keys.cloned().collect())
+    }
+}
+
+fn indexed_value(value: &Json) -> Option<String> {
+    match value {
+        Json::String(_) | Json::Number(_) | Json::Bool(_) => Some(value.to_string()),
+        Json::Null | Json::Array(_) | Json::Object(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn test_secondary_indexes() -> Result<(), crate::Error> {
+        let definition = |name: &str, pattern: &str, field: &str| IndexDefinition {
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+            field: JsonPath::parse(field).unwrap(),
+        };
+
+        let mut indexes = SecondaryIndexes::new(vec![
+            definition("by_user", "order:*", "user_id"),
+            definition("by_paid", "order:*", "$.paid"),
+        ])?;
+
+        indexes.add("order:1", br#"{"user_id": "u1", "paid": true}"#);
+        indexes.add("order:2", br#"{"user_id": "u1", "paid": false}"#);
+        indexes.add("order:3", br#"{"user_id": 7, "paid": null}"#);
+        indexes.add("order:4", b"not json");
+        indexes.add("user:1", br#"{"user_id": "u1"}"#);
+
+        let keys = |keys: &[&str]| Some(keys.iter().map(|key| key.to_string()).collect());
+
+        assert_eq!(
+            indexes.find("by_user", &json!("u1")),
+            keys(&["order:1", "order:2"])
+        );
+        assert_eq!(indexes.find("by_user", &json!(7)), keys(&["order:3"]));
+        assert_eq!(indexes.find("by_paid", &json!(true)), keys(&["order:1"]));
+        assert_eq!(indexes.find("by_paid", &json!(null)), keys(&[]));
+        assert_eq!(indexes.find("by_user", &json!(["u1"])), keys(&[]));
+        assert_eq!(indexes.find("by_name", &json!("u1")), None);
+
+        // Strings and numbers of the same text are different values
+        indexes.add("order:5", br#"{"user_id": "7", "paid": "true"}"#);
+
+        assert_eq!(indexes.find("by_user", &json!(7)), keys(&["order:3"]));
+        assert_eq!(indexes.find("by_user", &json!("7")), keys(&["order:5"]));
+        assert_eq!(indexes.find("by_paid", &json!(true)), keys(&["order:1"]));
+
+        indexes.remove("order:1");
+        indexes.remove("order:4");
+
+        assert_eq!(indexes.find("by_user", &json!("u1")), keys(&["order:2"]));
+        assert_eq!(indexes.find("by_paid", &json!(true)), keys(&[]));
+
+        assert!(SecondaryIndexes::new(vec![
+            definition("by_user", "order:*", "user_id"),
+            definition("by_user", "cart:*", "user_id"),
+        ])
+        .is_err());
+
+        Ok(())
+    }
+}
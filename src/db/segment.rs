@@ -0,0 +1,364 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use memmap2::Mmap;
+
+use super::record::now_millis;
+
+/// Segments are numbered in the order they were created, so records from a segment
+/// with a greater id always take precedence over records from the older ones.
+pub type SegmentId = u64;
+
+pub(crate) const FIRST_SEGMENT_ID: SegmentId = 1;
+
+const SEGMENT_SUFFIX: &str = "dat";
+const MERGE_SUFFIX: &str = "merge";
+const MERGE_TMP_SUFFIX: &str = "merge.tmp";
+const HINT_SUFFIX: &str = "hint";
+const HINT_TMP_SUFFIX: &str = "hint.tmp";
+const QUARANTINE_SUFFIX: &str = "quarantine";
+
+/*
+    Directory layout:
+
+    000001.dat        - closed segment
+    000002.dat        - closed segment
+    000003.dat        - active segment (the only one being appended to)
+    000002.merge      - complete merge output which replaces segments 1..=2
+    000002.merge.tmp  - merge output which is still being written
+    000002.hint       - key locations within merged segment 2, for faster startup
+    000002.hint.tmp   - hint file which is still being written
+
+    000003.quarantine.1699084615000 - corrupted tail cut off segment 3 on startup
+*/
+
+/// Segment files kept open across requests. Every segment has at most a single
+/// handle, which is shared by all the readers through positional reads, while
+/// the handle of the active segment is also used for appending.
+///
+/// Closed segments can optionally be memory-mapped instead, in which case records
+/// are handed out as slices of the mapping without copying.
+#[derive(Debug)]
+pub(crate) struct Segments {
+    dir: PathBuf,
+    pub(crate) active: SegmentId,
+    pub(crate) active_size: u64,
+    pub(crate) closed: BTreeSet<SegmentId>,
+    files: HashMap<SegmentId, Arc<File>>,
+    mmap: bool,
+    maps: HashMap<SegmentId, Bytes>,
+}
+
+/// Way of reading records of a particular segment.
+#[derive(Debug, Clone)]
+pub(crate) enum SegmentReader {
+    Mapped(Bytes),
+    File(Arc<File>),
+}
+
+impl Segments {
+    pub(crate) fn new(
+        dir: &Path,
+        active: SegmentId,
+        active_size: u64,
+        closed: BTreeSet<SegmentId>,
+        mmap: bool,
+    ) -> Segments {
+        Segments {
+            dir: dir.to_path_buf(),
+            active,
+            active_size,
+            closed,
+            files: HashMap::new(),
+            mmap,
+            maps: HashMap::new(),
+        }
+    }
+
+    /// Reader of the segment. Active segment keeps growing, so it's always read
+    /// through the file handle, even if the closed ones are mapped.
+    pub(crate) fn reader(&mut self, id: SegmentId) -> Result<SegmentReader, crate::Error> {
+        if !self.mmap || id == self.active {
+            return Ok(SegmentReader::File(self.file(id)?));
+        }
+
+        if let Some(map) = self.maps.get(&id) {
+            return Ok(SegmentReader::Mapped(map.clone()));
+        }
+
+        let file = self.file(id)?;
+
+        // SAFETY: closed segments are never modified in place, they are only ever
+        // replaced by merge through unlinking, which leaves the mapping intact.
+        let map = Bytes::from_owner(unsafe { Mmap::map(&*file)? });
+        self.maps.insert(id, map.clone());
+
+        Ok(SegmentReader::Mapped(map))
+    }
+
+    /// Handle of the segment, opened on the first use. Handle stays valid even after
+    /// the segment is replaced by a merge, so it can be used without holding the lock.
+    pub(crate) fn file(&mut self, id: SegmentId) -> Result<Arc<File>, crate::Error> {
+        if let Some(file) = self.files.get(&id) {
+            return Ok(file.clone());
+        }
+
+        let mut options = OpenOptions::new();
+        options.read(true);
+
+        if id == self.active {
+            options.create(true).append(true);
+        }
+
+        let file = Arc::new(options.open(segment_path(&self.dir, id))?);
+        self.files.insert(id, file.clone());
+
+        Ok(file)
+    }
+
+    /// Appends encoded record to the active segment, returning its offset.
+    pub(crate) fn append(&mut self, buf: &[u8]) -> Result<u64, crate::Error> {
+        let file = self.file(self.active)?;
+        let offset = self.active_size;
+
+        if let Err(err) = (&*file).write_all(buf) {
+            // Partially written record would make all the following ones unreadable
+            file.set_len(offset)?;
+            return Err(err.into());
+        }
+
+        self.active_size += buf.len() as u64;
+
+        Ok(offset)
+    }
+
+    /// Closes the active segment and starts a new one.
+    pub(crate) fn roll(&mut self, sync: bool) -> Result<(), crate::Error> {
+        if sync {
+            self.file(self.active)?.sync_data()?;
+        }
+
+        self.closed.insert(self.active);
+
+        // Handle opened for appending is kept for reading
+        self.active += 1;
+        self.active_size = 0;
+
+        Ok(())
+    }
+
+    /// Forgets handles of the segments replaced by the merge output. Segments closed
+    /// while the merge was being written come after it and stay closed.
+    pub(crate) fn replace_merged(&mut self, target: SegmentId) {
+        self.files.retain(|&id, _| id > target);
+        self.maps.retain(|&id, _| id > target);
+        self.closed.retain(|&id| id > target);
+        self.closed.insert(target);
+    }
+}
+
+impl SegmentReader {
+    /// Reads exactly `len` bytes at `offset`. File is read without affecting
+    /// the handle's cursor, so the same handle could be shared by concurrent readers.
+    pub(crate) fn read(&self, offset: u64, len: u64) -> Result<Bytes, crate::Error> {
+        match self {
+            SegmentReader::Mapped(map) => {
+                let (start, end) = (offset as usize, (offset + len) as usize);
+
+                if end > map.len() {
+                    return Err("record is out of the mapped segment bounds".into());
+                }
+
+                Ok(map.slice(start..end))
+            }
+            SegmentReader::File(file) => {
+                let mut buffer = vec![0; len as usize];
+
+                file.read_exact_at(&mut buffer, offset)?;
+
+                Ok(Bytes::from(buffer))
+            }
+        }
+    }
+}
+
+pub(crate) fn segment_path(dir: &Path, id: SegmentId) -> PathBuf {
+    dir.join(format!("{:06}.{}", id, SEGMENT_SUFFIX))
+}
+
+pub(crate) fn merge_path(dir: &Path, id: SegmentId) -> PathBuf {
+    dir.join(format!("{:06}.{}", id, MERGE_SUFFIX))
+}
+
+pub(crate) fn merge_tmp_path(dir: &Path, id: SegmentId) -> PathBuf {
+    dir.join(format!("{:06}.{}", id, MERGE_TMP_SUFFIX))
+}
+
+pub(crate) fn hint_path(dir: &Path, id: SegmentId) -> PathBuf {
+    dir.join(format!("{:06}.{}", id, HINT_SUFFIX))
+}
+
+pub(crate) fn hint_tmp_path(dir: &Path, id: SegmentId) -> PathBuf {
+    dir.join(format!("{:06}.{}", id, HINT_TMP_SUFFIX))
+}
+
+/// Ids of all segment files within the directory in ascending order.
+pub(crate) fn list_segments(dir: &Path) -> Result<Vec<SegmentId>, crate::Error> {
+    list_files_with_suffix(dir, SEGMENT_SUFFIX)
+}
+
+/// Brings the directory into a consistent state after a possible crash during merge.
+///
+/// Merge output is renamed from `.merge.tmp` to `.merge` only once it's fully written
+/// and synced, which is the commit point of the merge. Leftover `.merge.tmp` files are
+/// therefore discarded, while `.merge` files are rolled forward.
+pub(crate) fn recover(dir: &Path) -> Result<(), crate::Error> {
+    for id in list_files_with_suffix(dir, MERGE_TMP_SUFFIX)? {
+        let path = merge_tmp_path(dir, id);
+
+        fs::remove_file(&path)?;
+
+        eprintln!("removed unfinished merge file {}", path.display());
+    }
+
+    for id in list_files_with_suffix(dir, HINT_TMP_SUFFIX)? {
+        fs::remove_file(hint_tmp_path(dir, id))?;
+    }
+
+    for id in list_files_with_suffix(dir, MERGE_SUFFIX)? {
+        finish_merge(dir, id)?;
+
+        eprintln!("completed interrupted merge into segment {}", id);
+    }
+
+    Ok(())
+}
+
+/// Replaces all segments up to `target` inclusively with the committed merge output.
+///
+/// Older segments are removed before the rename, so at any moment the directory
+/// either still has the `.merge` file (and the merge can be rolled forward), or
+/// already has only the merged segment. Hints of the replaced segments go first,
+/// hint of the merged segment is only written once it's in place.
+pub(crate) fn finish_merge(dir: &Path, target: SegmentId) -> Result<(), crate::Error> {
+    for id in list_files_with_suffix(dir, HINT_SUFFIX)? {
+        if id > target {
+            break;
+        }
+
+        fs::remove_file(hint_path(dir, id))?;
+    }
+
+    for id in list_segments(dir)? {
+        if id > target {
+            break;
+        }
+
+        fs::remove_file(segment_path(dir, id))?;
+    }
+
+    fs::rename(merge_path(dir, target), segment_path(dir, target))?;
+    sync_dir(dir)?;
+
+    Ok(())
+}
+
+/// Cuts the segment off at `offset`, keeping everything past it in a quarantine
+/// file for manual inspection. Path of the quarantine file is returned.
+pub(crate) fn truncate_tail(
+    dir: &Path,
+    id: SegmentId,
+    offset: u64,
+) -> Result<PathBuf, crate::Error> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(segment_path(dir, id))?;
+
+    let mut tail = vec![];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_to_end(&mut tail)?;
+
+    let quarantine_path = quarantine(dir, id, &tail)?;
+
+    file.set_len(offset)?;
+    file.sync_all()?;
+
+    Ok(quarantine_path)
+}
+
+/// Puts unreadable content of the segment aside for manual inspection.
+pub(crate) fn quarantine(
+    dir: &Path,
+    id: SegmentId,
+    content: &[u8],
+) -> Result<PathBuf, crate::Error> {
+    let quarantine_path = dir.join(format!("{:06}.{}.{}", id, QUARANTINE_SUFFIX, now_millis()));
+
+    let mut quarantine_file = File::create(&quarantine_path)?;
+    quarantine_file.write_all(content)?;
+    quarantine_file.sync_all()?;
+
+    sync_dir(dir)?;
+
+    Ok(quarantine_path)
+}
+
+/// Makes renames and removals within the directory durable.
+pub(crate) fn sync_dir(dir: &Path) -> Result<(), crate::Error> {
+    File::open(dir)?.sync_all()?;
+
+    Ok(())
+}
+
+/// Moves a single-file store from the times before segmentation into the directory
+/// as its first segment. Nothing is done if the directory already has segments.
+pub(crate) fn adopt_legacy_file(legacy_path: &Path, dir: &Path) -> Result<(), crate::Error> {
+    if !legacy_path.exists() || !list_segments(dir)?.is_empty() {
+        return Ok(());
+    }
+
+    fs::rename(legacy_path, segment_path(dir, FIRST_SEGMENT_ID))?;
+    sync_dir(dir)?;
+
+    eprintln!(
+        "moved {} into {} as the first segment",
+        legacy_path.display(),
+        dir.display()
+    );
+
+    Ok(())
+}
+
+fn list_files_with_suffix(dir: &Path, suffix: &str) -> Result<Vec<SegmentId>, crate::Error> {
+    let mut ids = vec![];
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(ids),
+        Err(err) => return Err(err.into()),
+    };
+
+    for entry in entries {
+        let file_name = entry?.file_name();
+
+        let parsed = file_name
+            .to_str()
+            .and_then(|name| name.split_once('.'))
+            .filter(|(_, file_suffix)| *file_suffix == suffix)
+            .and_then(|(stem, _)| stem.parse::<SegmentId>().ok());
+
+        if let Some(id) = parsed {
+            ids.push(id);
+        }
+    }
+
+    ids.sort_unstable();
+
+    Ok(ids)
+}
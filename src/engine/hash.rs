@@ -0,0 +1,92 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use super::Fields;
+
+/*
+    Hash value layout (all integers are big-endian):
+
+    +-------+-----------+-------+-----------+-------+
+    | count | field_len | field | value_len | value |  ... `count` times, in field order
+    |  u32  |    u32    |       |    u32    |       |
+    +-------+-----------+-------+-----------+-------+
+*/
+
+pub(crate) fn encode(fields: &Fields) -> Bytes {
+    let capacity: usize = fields
+        .iter()
+        .map(|(field, value)| 8 + field.len() + value.len())
+        .sum();
+    let mut buf = BytesMut::with_capacity(4 + capacity);
+
+    buf.put_u32(fields.len() as u32);
+
+    for (field, value) in fields {
+        buf.put_u32(field.len() as u32);
+        buf.put_slice(field.as_bytes());
+        buf.put_u32(value.len() as u32);
+        buf.put_slice(value);
+    }
+
+    buf.freeze()
+}
+
+/// Values are slices of the buffer rather than copies.
+pub(crate) fn decode(buf: &Bytes) -> Result<Fields, crate::Error> {
+    let mut src = &buf[..];
+    let mut fields = Fields::new();
+
+    let count = read_len(&mut src)?;
+
+    for _ in 0..count {
+        let field_len = read_len(&mut src)?;
+        let field = String::from_utf8(src[..field_len].to_vec())?;
+        src.advance(field_len);
+
+        let value_len = read_len(&mut src)?;
+        let value_start = buf.len() - src.len();
+        src.advance(value_len);
+
+        fields.insert(field, buf.slice(value_start..value_start + value_len));
+    }
+
+    if src.has_remaining() {
+        return Err("hash has trailing bytes".into());
+    }
+
+    Ok(fields)
+}
+
+/// Length, or count, which is checked not to exceed the rest of the buffer.
+fn read_len(src: &mut &[u8]) -> Result<usize, crate::Error> {
+    if src.remaining() < 4 {
+        return Err("hash ended early".into());
+    }
+
+    let len = src.get_u32() as usize;
+
+    if src.remaining() < len {
+        return Err("hash ended early".into());
+    }
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_roundtrip() -> Result<(), crate::Error> {
+        let mut fields = Fields::new();
+        fields.insert("name".to_string(), Bytes::from("Ada"));
+        fields.insert("empty".to_string(), Bytes::new());
+
+        let encoded = encode(&fields);
+
+        assert_eq!(decode(&encoded)?, fields);
+        assert_eq!(decode(&encode(&Fields::new()))?, Fields::new());
+        assert!(decode(&encoded.slice(..encoded.len() - 1)).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+use std::fmt;
+
+use serde_json::{Number, Value as Json};
+
+/// Location within a JSON document, like `$.items[0].price`. Root is spelled `$`,
+/// object members follow a dot or are quoted within brackets, as in `$["a b"]`,
+/// and array elements are picked by an index within brackets, negative indexes
+/// counting from the end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Member(String),
+    Index(i64),
+}
+
+/// Stored value isn't the JSON document an operation expects.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JsonError {
+    /// Value of the key isn't valid JSON
+    NotJson,
+    /// Value at the path isn't a number, or the result isn't a valid one
+    NotANumber,
+}
+
+impl JsonPath {
+    pub fn root() -> JsonPath {
+        JsonPath { segments: vec![] }
+    }
+
+    /// Leading `$` is optional, so `.a.b` and `a.b` are the same as `$.a.b`.
+    pub fn parse(src: &str) -> Result<JsonPath, crate::Error> {
+        let invalid = || -> crate::Error { format!("invalid JSON path {}", src).into() };
+
+        let (mut rest, mut segments) = match src.strip_prefix('$') {
+            Some(rest) => (rest, vec![]),
+            None if src.is_empty() || src.starts_with(['.', '[']) => (src, vec![]),
+            None => {
+                // Path without the root, the first member isn't preceded by a dot
+                let end = src.find(['.', '[']).unwrap_or(src.len());
+
+                (&src[end..], vec![Segment::Member(src[..end].to_string())])
+            }
+        };
+
+        // Lone dot stands for the root too
+        if rest == "." {
+            rest = "";
+        }
+
+        while !rest.is_empty() {
+            if let Some(after_dot) = rest.strip_prefix('.') {
+                let end = after_dot.find(['.', '[']).unwrap_or(after_dot.len());
+
+                if end == 0 {
+                    return Err(invalid());
+                }
+
+                segments.push(Segment::Member(after_dot[..end].to_string()));
+                rest = &after_dot[end..];
+            } else if let Some(after_bracket) = rest.strip_prefix('[') {
+                let end = after_bracket.find(']').ok_or_else(invalid)?;
+                let inner = &after_bracket[..end];
+
+                let segment = if inner.starts_with('"') {
+                    Segment::Member(serde_json::from_str(inner).map_err(|_| invalid())?)
+                } else {
+                    Segment::Index(inner.parse().map_err(|_| invalid())?)
+                };
+
+                segments.push(segment);
+                rest = &after_bracket[end + 1..];
+            } else {
+                return Err(invalid());
+            }
+        }
+
+        Ok(JsonPath { segments })
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub(crate) fn get<'a>(&self, doc: &'a Json) -> Option<&'a Json> {
+        self.segments
+            .iter()
+            .try_fold(doc, |node, segment| segment.get(node))
+    }
+
+    pub(crate) fn get_mut<'a>(&self, doc: &'a mut Json) -> Option<&'a mut Json> {
+        self.segments
+            .iter()
+            .try_fold(doc, |node, segment| segment.get_mut(node))
+    }
+
+    /// Replaces the value at the path. Missing object member is added, but the rest
+    /// of the path has to be there already. Returns `false` if nothing was set.
+    pub(crate) fn set(&self, doc: &mut Json, value: Json) -> bool {
+        let (last, parent) = match self.split_last() {
+            Some(split) => split,
+            None => {
+                *doc = value;
+                return true;
+            }
+        };
+
+        match (parent.get_mut(doc), last) {
+            (Some(Json::Object(members)), Segment::Member(member)) => {
+                members.insert(member.clone(), value);
+                true
+            }
+            (Some(node), segment) => match segment.get_mut(node) {
+                Some(element) => {
+                    *element = value;
+                    true
+                }
+                None => false,
+            },
+            (None, _) => false,
+        }
+    }
+
+    /// Removes the value at the path from its parent. Returns `false` if there
+    /// was nothing to remove. Root can't be removed this way.
+    pub(crate) fn remove(&self, doc: &mut Json) -> bool {
+        let (last, parent) = match self.split_last() {
+            Some(split) => split,
+            None => return false,
+        };
+
+        match (parent.get_mut(doc), last) {
+            (Some(Json::Object(members)), Segment::Member(member)) => {
+                members.remove(member).is_some()
+            }
+            (Some(Json::Array(elements)), Segment::Index(index)) => {
+                match resolve_index(*index, elements.len()) {
+                    Some(index) => {
+                        elements.remove(index);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    fn split_last(&self) -> Option<(&Segment, JsonPath)> {
+        let (last, parent) = self.segments.split_last()?;
+
+        Some((
+            last,
+            JsonPath {
+                segments: parent.to_vec(),
+            },
+        ))
+    }
+}
+
+impl Segment {
+    fn get<'a>(&self, node: &'a Json) -> Option<&'a Json> {
+        match (self, node) {
+            (Segment::Member(member), Json::Object(members)) => members.get(member),
+            (Segment::Index(index), Json::Array(elements)) => {
+                elements.get(resolve_index(*index, elements.len())?)
+            }
+            _ => None,
+        }
+    }
+
+    fn get_mut<'a>(&self, node: &'a mut Json) -> Option<&'a mut Json> {
+        match (self, node) {
+            (Segment::Member(member), Json::Object(members)) => members.get_mut(member),
+            (Segment::Index(index), Json::Array(elements)) => {
+                let index = resolve_index(*index, elements.len())?;
+                elements.get_mut(index)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Position of the element, negative indexes counting from the end.
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+
+    usize::try_from(index).ok().filter(|index| *index < len)
+}
+
+/// Sum of the numbers, which stays an integer if both of them are integers
+/// and it fits. `None` if the sum isn't a finite number.
+pub(crate) fn add_numbers(a: &Number, b: &Number) -> Option<Number> {
+    if let (Some(a), Some(b)) = (a.as_i64(), b.as_i64()) {
+        if let Some(sum) = a.checked_add(b) {
+            return Some(Number::from(sum));
+        }
+    }
+
+    Number::from_f64(a.as_f64()? + b.as_f64()?)
+}
+
+pub(crate) fn parse_document(bytes: &[u8]) -> Result<Json, JsonError> {
+    serde_json::from_slice(bytes).map_err(|_| JsonError::NotJson)
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "$")?;
+
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Member(member) if is_plain_member(member) => write!(f, ".{}", member)?,
+                Segment::Member(member) => {
+                    let quoted = serde_json::to_string(member).map_err(|_| fmt::Error)?;
+                    write!(f, "[{}]", quoted)?
+                }
+                Segment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the member can follow a dot, rather than being quoted within brackets.
+fn is_plain_member(member: &str) -> bool {
+    !member.is_empty()
+        && member
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == ':')
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JsonError::NotJson => write!(f, "value is not valid JSON"),
+            JsonError::NotANumber => write!(f, "value at the path is not a number"),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn test_json_paths() -> Result<(), crate::Error> {
+        let mut doc = json!({"user": {"name": "Ada", "tags": ["a", "b"]}, "a b": 1});
+
+        for (src, expected) in [
+            ("$", "$"),
+            (".", "$"),
+            ("", "$"),
+            ("$.user.name", "$.user.name"),
+            ("user.tags[-1]", "$.user.tags[-1]"),
+            ("$[\"a b\"]", "$[\"a b\"]"),
+        ] {
+            assert_eq!(JsonPath::parse(src)?.to_string(), expected);
+        }
+
+        for invalid in ["$..a", "$.a.", "$[", "$[x]", "$a"] {
+            assert!(JsonPath::parse(invalid).is_err(), "{}", invalid);
+        }
+
+        let path = |src: &str| JsonPath::parse(src).unwrap();
+
+        assert_eq!(path("$.user.tags[-1]").get(&doc), Some(&json!("b")));
+        assert_eq!(path("$[\"a b\"]").get(&doc), Some(&json!(1)));
+        assert_eq!(path("$.user.tags[2]").get(&doc), None);
+        assert_eq!(path("$.user.name.first").get(&doc), None);
+
+        assert!(path("$.user.age").set(&mut doc, json!(36)));
+        assert!(path("$.user.tags[0]").set(&mut doc, json!("c")));
+        assert!(!path("$.user.tags[5]").set(&mut doc, json!("d")));
+        assert!(!path("$.missing.age").set(&mut doc, json!(1)));
+
+        assert!(path("$.user.tags[-1]").remove(&mut doc));
+        assert!(!path("$.user.missing").remove(&mut doc));
+        assert!(!path("$").remove(&mut doc));
+
+        assert_eq!(
+            doc,
+            json!({"user": {"name": "Ada", "tags": ["c"], "age": 36}, "a b": 1})
+        );
+
+        assert!(path("$").set(&mut doc, json!([])));
+        assert_eq!(doc, json!([]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_numbers() {
+        let number = |value: Json| match value {
+            Json::Number(number) => number,
+            _ => unreachable!(),
+        };
+
+        assert_eq!(
+            add_numbers(&number(json!(2)), &number(json!(3))),
+            Some(number(json!(5)))
+        );
+        assert_eq!(
+            add_numbers(&number(json!(2)), &number(json!(0.5))),
+            Some(number(json!(2.5)))
+        );
+        assert_eq!(
+            add_numbers(&number(json!(i64::MAX)), &number(json!(1))),
+            Some(number(json!(i64::MAX as f64 + 1.0)))
+        );
+        assert_eq!(
+            add_numbers(&number(json!(f64::MAX)), &number(json!(f64::MAX))),
+            None
+        );
+    }
+}
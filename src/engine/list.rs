@@ -0,0 +1,88 @@
+use std::collections::VecDeque;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+/// Items of a list, from its left end to its right one.
+pub type Items = VecDeque<Bytes>;
+
+/*
+    List value layout (all integers are big-endian):
+
+    +-------+----------+------+
+    | count | item_len | item |  ... `count` times, from left to right
+    |  u32  |   u32    |      |
+    +-------+----------+------+
+*/
+
+pub(crate) fn encode(items: &Items) -> Bytes {
+    let capacity: usize = items.iter().map(|item| 4 + item.len()).sum();
+    let mut buf = BytesMut::with_capacity(4 + capacity);
+
+    buf.put_u32(items.len() as u32);
+
+    for item in items {
+        buf.put_u32(item.len() as u32);
+        buf.put_slice(item);
+    }
+
+    buf.freeze()
+}
+
+/// Items are slices of the buffer rather than copies.
+pub(crate) fn decode(buf: &Bytes) -> Result<Items, crate::Error> {
+    let mut src = &buf[..];
+    let mut items = Items::new();
+
+    let count = read_len(&mut src)?;
+
+    for _ in 0..count {
+        let item_len = read_len(&mut src)?;
+        let item_start = buf.len() - src.len();
+        src.advance(item_len);
+
+        items.push_back(buf.slice(item_start..item_start + item_len));
+    }
+
+    if src.has_remaining() {
+        return Err("list has trailing bytes".into());
+    }
+
+    Ok(items)
+}
+
+/// Length, or count, which is checked not to exceed the rest of the buffer.
+fn read_len(src: &mut &[u8]) -> Result<usize, crate::Error> {
+    if src.remaining() < 4 {
+        return Err("list ended early".into());
+    }
+
+    let len = src.get_u32() as usize;
+
+    if src.remaining() < len {
+        return Err("list ended early".into());
+    }
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_roundtrip() -> Result<(), crate::Error> {
+        let items = Items::from(vec![
+            Bytes::from("job-1"),
+            Bytes::new(),
+            Bytes::from("job-1"),
+        ]);
+
+        let encoded = encode(&items);
+
+        assert_eq!(decode(&encoded)?, items);
+        assert_eq!(decode(&encode(&Items::new()))?, Items::new());
+        assert!(decode(&encoded.slice(..encoded.len() - 1)).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,447 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use bytes::Bytes;
+
+use super::{KeyRange, StorageEngine, Update, Value};
+use crate::db::now_millis;
+
+/// Engine keeping everything in memory, nothing survives a restart.
+#[derive(Debug, Default)]
+pub struct MemoryEngine {
+    entries: Mutex<BTreeMap<String, Value>>,
+    /// Only changed while holding the entries lock
+    last_version: AtomicU64,
+}
+
+impl MemoryEngine {
+    pub fn new() -> MemoryEngine {
+        MemoryEngine::default()
+    }
+}
+
+impl StorageEngine for MemoryEngine {
+    fn get_value(&self, key: &str) -> Result<Option<Value>, crate::Error> {
+        let entries = self.entries.lock().unwrap();
+
+        Ok(entries
+            .get(key)
+            .filter(|value| !value.is_expired(now_millis()))
+            .cloned())
+    }
+
+    fn update(
+        &self,
+        key: &str,
+        f: &mut dyn FnMut(Option<&Value>) -> Result<Update, crate::Error>,
+    ) -> Result<(), crate::Error> {
+        let mut entries = self.entries.lock().unwrap();
+
+        let current = entries
+            .get(key)
+            .filter(|value| !value.is_expired(now_millis()));
+
+        match f(current)? {
+            Update::Keep => {}
+            Update::Set(mut value) => {
+                value.version = super::next_version(self.last_version.load(Ordering::Relaxed));
+                self.last_version.store(value.version, Ordering::Relaxed);
+
+                entries.insert(key.to_string(), value);
+            }
+            Update::Delete => {
+                entries.remove(key);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_batch_if(
+        &self,
+        versions: Vec<(String, Option<u64>)>,
+        batch: Vec<(String, Option<Value>)>,
+    ) -> Result<bool, crate::Error> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = now_millis();
+
+        for (key, version) in versions {
+            let current = entries
+                .get(&key)
+                .filter(|value| !value.is_expired(now))
+                .map(|value| value.version);
+
+            if current != version {
+                return Ok(false);
+            }
+        }
+
+        for (key, value) in batch {
+            match value {
+                Some(mut value) => {
+                    value.version = super::next_version(self.last_version.load(Ordering::Relaxed));
+                    self.last_version.store(value.version, Ordering::Relaxed);
+
+                    entries.insert(key, value);
+                }
+                None => {
+                    entries.remove(&key);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn scan(
+        &self,
+        range: KeyRange,
+        reverse: bool,
+        limit: usize,
+    ) -> Result<Vec<(String, Bytes)>, crate::Error> {
+        if super::is_empty_range(&range) {
+            return Ok(vec![]);
+        }
+
+        let entries = self.entries.lock().unwrap();
+        let now = now_millis();
+        let pairs = entries
+            .range(range)
+            .filter(|(_, value)| !value.is_expired(now))
+            .map(|(key, value)| (key.clone(), value.bytes.clone()));
+
+        if reverse {
+            Ok(pairs.rev().take(limit).collect())
+        } else {
+            Ok(pairs.take(limit).collect())
+        }
+    }
+
+    fn key_count(&self) -> Result<usize, crate::Error> {
+        let entries = self.entries.lock().unwrap();
+        let now = now_millis();
+
+        Ok(entries
+            .values()
+            .filter(|value| !value.is_expired(now))
+            .count())
+    }
+
+    fn compact(&self) -> Result<(), crate::Error> {
+        Ok(())
+    }
+
+    fn purge_expired(&self) -> Result<usize, crate::Error> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = now_millis();
+        let before = entries.len();
+
+        entries.retain(|_, value| !value.is_expired(now));
+
+        Ok(before - entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ops::Bound;
+
+    use serde_json::{json, Number};
+
+    use crate::engine::{JsonError, JsonPath, ListEnd, SetCondition};
+
+    #[test]
+    fn test_memory_engine() -> Result<(), crate::Error> {
+        let engine = MemoryEngine::new();
+
+        engine.set("b".to_string(), Bytes::from("2"))?;
+        engine.set("a".to_string(), Bytes::from("1"))?;
+        engine.set("b".to_string(), Bytes::from("3"))?;
+
+        assert_eq!(engine.get("b")?, Some(Bytes::from("3")));
+        assert_eq!(engine.get("c")?, None);
+
+        let all = (Bound::Unbounded, Bound::Unbounded);
+
+        assert_eq!(
+            engine.scan(all.clone(), false, usize::MAX)?,
+            vec![
+                ("a".to_string(), Bytes::from("1")),
+                ("b".to_string(), Bytes::from("3")),
+            ]
+        );
+        assert_eq!(
+            engine.scan(all, true, 1)?,
+            vec![("b".to_string(), Bytes::from("3"))]
+        );
+
+        assert!(engine.delete("a")?);
+        assert!(!engine.delete("a")?);
+        assert_eq!(engine.get("a")?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expiration() -> Result<(), crate::Error> {
+        let engine = MemoryEngine::new();
+        let now = now_millis();
+
+        engine.set_with(
+            "gone".to_string(),
+            Bytes::from("1"),
+            Some(now - 1),
+            SetCondition::Always,
+        )?;
+        engine.set_with(
+            "later".to_string(),
+            Bytes::from("2"),
+            Some(now + 60_000),
+            SetCondition::Always,
+        )?;
+        engine.set("kept".to_string(), Bytes::from("3"))?;
+
+        assert_eq!(engine.get("gone")?, None);
+        assert_eq!(engine.key_count()?, 2);
+        assert_eq!(engine.keys()?, vec!["kept", "later"]);
+
+        // Expired key no longer stands in the way of a conditional write
+        assert!(engine.set_with(
+            "gone".to_string(),
+            Bytes::from("4"),
+            None,
+            SetCondition::IfAbsent
+        )?);
+        assert!(!engine.set_with(
+            "later".to_string(),
+            Bytes::from("5"),
+            None,
+            SetCondition::IfAbsent
+        )?);
+
+        assert_eq!(engine.set_expiry("later", None)?, Some(Some(now + 60_000)));
+        assert_eq!(engine.get_value("later")?.unwrap().expires_at, None);
+        assert_eq!(engine.set_expiry("missing", Some(now))?, None);
+
+        engine.set_expiry("kept", Some(now - 1))?;
+        assert_eq!(engine.purge_expired()?, 1);
+        assert_eq!(engine.keys()?, vec!["gone", "later"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hashes() -> Result<(), crate::Error> {
+        let engine = MemoryEngine::new();
+
+        let fields = vec![
+            ("a".to_string(), Bytes::from("1")),
+            ("b".to_string(), Bytes::from("2")),
+            ("a".to_string(), Bytes::from("3")),
+        ];
+
+        assert_eq!(engine.hash_set("hash", fields)?, 2);
+        assert_eq!(engine.hash_get("hash", "a")?, Some(Bytes::from("3")));
+        assert_eq!(engine.hash_get("hash", "c")?, None);
+        assert_eq!(engine.hash_get("missing", "a")?, None);
+
+        // Hash and string values don't mix
+        engine.set("string".to_string(), Bytes::from("1"))?;
+
+        assert!(engine.get("hash").is_err());
+        assert!(engine.hash_get("string", "a").is_err());
+        assert!(engine.increment("hash", 1).is_err());
+
+        assert_eq!(
+            engine.hash_delete("hash", &["a".to_string(), "c".to_string()])?,
+            1
+        );
+        assert_eq!(
+            engine.hash_get_all("hash")?.into_iter().collect::<Vec<_>>(),
+            vec![("b".to_string(), Bytes::from("2"))]
+        );
+
+        assert_eq!(engine.hash_delete("hash", &["b".to_string()])?, 1);
+        assert!(!engine.contains("hash")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lists() -> Result<(), crate::Error> {
+        let engine = MemoryEngine::new();
+
+        let values = |values: &[&'static str]| -> Vec<Bytes> {
+            values.iter().map(|value| Bytes::from(*value)).collect()
+        };
+
+        assert_eq!(
+            engine.list_push("list", values(&["b", "a"]), ListEnd::Left)?,
+            2
+        );
+        assert_eq!(
+            engine.list_push("list", values(&["c", "d"]), ListEnd::Right)?,
+            4
+        );
+        assert_eq!(engine.list_len("list")?, 4);
+        assert_eq!(engine.list_len("missing")?, 0);
+
+        assert_eq!(
+            engine.list_range("list", 0, -1)?,
+            values(&["a", "b", "c", "d"])
+        );
+        assert_eq!(engine.list_range("list", -3, 1)?, values(&["b"]));
+        assert_eq!(engine.list_range("list", 2, 100)?, values(&["c", "d"]));
+        assert_eq!(engine.list_range("list", 3, 1)?, values(&[]));
+        assert_eq!(engine.list_range("missing", 0, -1)?, values(&[]));
+
+        // List and string values don't mix
+        engine.set("string".to_string(), Bytes::from("1"))?;
+
+        assert!(engine.get("list").is_err());
+        assert!(engine
+            .list_push("string", values(&["a"]), ListEnd::Left)
+            .is_err());
+        assert!(engine.hash_get("list", "a").is_err());
+
+        assert_eq!(
+            engine.list_pop("list", ListEnd::Left)?,
+            Some(Bytes::from("a"))
+        );
+        assert_eq!(
+            engine.list_pop("list", ListEnd::Right)?,
+            Some(Bytes::from("d"))
+        );
+        assert_eq!(
+            engine.list_pop("list", ListEnd::Right)?,
+            Some(Bytes::from("c"))
+        );
+        assert_eq!(
+            engine.list_pop("list", ListEnd::Right)?,
+            Some(Bytes::from("b"))
+        );
+        assert_eq!(engine.list_pop("list", ListEnd::Right)?, None);
+        assert!(!engine.contains("list")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sorted_sets() -> Result<(), crate::Error> {
+        let engine = MemoryEngine::new();
+
+        let scores = vec![
+            (2.0, Bytes::from("b")),
+            (1.0, Bytes::from("c")),
+            (2.0, Bytes::from("a")),
+        ];
+
+        assert_eq!(engine.sorted_set_add("board", scores)?, 3);
+        assert_eq!(
+            engine.sorted_set_add("board", vec![(0.5, Bytes::from("a"))])?,
+            0
+        );
+
+        let members = |ranked: Vec<(Bytes, f64)>| -> Vec<Bytes> {
+            ranked.into_iter().map(|(member, _)| member).collect()
+        };
+
+        // Same scores are ordered by member
+        assert_eq!(
+            members(engine.sorted_set_range("board", 0, -1)?),
+            vec!["a", "c", "b"]
+        );
+        assert_eq!(
+            members(engine.sorted_set_range("board", -2, -1)?),
+            vec!["c", "b"]
+        );
+        assert_eq!(
+            engine.sorted_set_range_by_score(
+                "board",
+                Bound::Excluded(0.5),
+                Bound::Included(2.0)
+            )?,
+            vec![(Bytes::from("c"), 1.0), (Bytes::from("b"), 2.0)]
+        );
+        assert_eq!(engine.sorted_set_rank("board", b"b")?, Some(2));
+        assert_eq!(engine.sorted_set_rank("board", b"missing")?, None);
+
+        assert_eq!(
+            engine.sorted_set_increment("board", Bytes::from("a"), 10.0)?,
+            Some(10.5)
+        );
+        assert_eq!(
+            engine.sorted_set_increment("board", Bytes::from("d"), -1.0)?,
+            Some(-1.0)
+        );
+        assert_eq!(engine.sorted_set_rank("board", b"a")?, Some(3));
+
+        // Score that isn't a number is never written
+        engine.sorted_set_increment("board", Bytes::from("e"), f64::INFINITY)?;
+        assert_eq!(
+            engine.sorted_set_increment("board", Bytes::from("e"), f64::NEG_INFINITY)?,
+            None
+        );
+
+        assert!(engine.set_add("board", vec![Bytes::from("a")]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_documents() -> Result<(), crate::Error> {
+        let engine = MemoryEngine::new();
+
+        let path = |src: &str| JsonPath::parse(src).unwrap();
+
+        // Only the root of a missing document can be set
+        assert!(!engine.json_set("order", &path("$.id"), json!(1))?);
+        assert!(engine.json_set("order", &path("$"), json!({"id": 1, "items": []}))?);
+        assert!(engine.json_set("order", &path("$.items"), json!([{"qty": 2}]))?);
+        assert!(!engine.json_set("order", &path("$.user.id"), json!(7))?);
+
+        assert_eq!(
+            engine.json_increment("order", &path("$.items[0].qty"), &Number::from(3))?,
+            Some(Number::from(5))
+        );
+        assert_eq!(
+            engine.json_increment("order", &path("$.total"), &Number::from(3))?,
+            None
+        );
+
+        // Documents are strings like any other
+        assert_eq!(
+            engine.get("order")?,
+            Some(Bytes::from(r#"{"id":1,"items":[{"qty":5}]}"#))
+        );
+        assert_eq!(
+            engine.json_get("order", &path("$.items[-1]"))?,
+            Some(json!({"qty": 5}))
+        );
+        assert_eq!(engine.json_get("order", &path("$.missing"))?, None);
+
+        let err = engine
+            .json_increment("order", &path("$.items"), &Number::from(1))
+            .unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&JsonError::NotANumber));
+
+        engine.set("text".to_string(), Bytes::from("not json"))?;
+        let err = engine.json_get("text", &path("$")).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&JsonError::NotJson));
+
+        assert!(engine.json_delete("order", &path("$.items[0]"))?);
+        assert!(!engine.json_delete("order", &path("$.items[0]"))?);
+        assert_eq!(
+            engine.json_get("order", &path("$"))?,
+            Some(json!({"id": 1, "items": []}))
+        );
+
+        assert!(engine.json_delete("order", &path("$"))?);
+        assert!(!engine.contains("order")?);
+
+        Ok(())
+    }
+}
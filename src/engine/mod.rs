@@ -0,0 +1,996 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::{Bound, Range, RangeBounds};
+use std::time::Duration;
+
+use bytes::Bytes;
+use serde_json::{Number, Value as Json};
+
+pub(crate) mod hash;
+pub(crate) mod json;
+pub(crate) mod list;
+mod memory;
+pub(crate) mod set;
+mod transaction;
+pub(crate) mod zset;
+
+pub use json::{JsonError, JsonPath};
+pub use list::Items;
+pub use memory::MemoryEngine;
+pub use set::Members;
+pub use transaction::Transaction;
+pub use zset::Scores;
+
+/// Keys between two bounds, either of which may be left open.
+pub type KeyRange = (Bound<String>, Bound<String>);
+
+/// Fields of a hash along with their values, in field order.
+pub type Fields = BTreeMap<String, Bytes>;
+
+/// Value of a live key along with its expiration time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Value {
+    /// Encoded collection for any type other than a string
+    pub bytes: Bytes,
+    /// Milliseconds since the unix epoch after which the key is gone
+    pub expires_at: Option<u64>,
+    /// Assigned by the engine on every write and ignored when writing. Versions
+    /// only ever grow and are never reused, so a changed version means the value
+    /// has been written since, even if it's still the same.
+    pub version: u64,
+    pub kind: Kind,
+}
+
+/// Type of the value held by a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Kind {
+    #[default]
+    String,
+    Hash,
+    List,
+    Set,
+    SortedSet,
+}
+
+/// Value of the key is of another type than the operation is for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WrongType {
+    pub expected: Kind,
+    pub found: Kind,
+}
+
+/// End of a list items are pushed to or popped from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+/// What `StorageEngine::compare_and_swap` expects the current value of the key to be.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expected {
+    Value(Bytes),
+    Version(u64),
+}
+
+impl Value {
+    pub fn new(bytes: Bytes, expires_at: Option<u64>) -> Value {
+        Value {
+            bytes,
+            expires_at,
+            version: 0,
+            kind: Kind::String,
+        }
+    }
+
+    pub fn hash(fields: &Fields, expires_at: Option<u64>) -> Value {
+        Value {
+            kind: Kind::Hash,
+            ..Value::new(hash::encode(fields), expires_at)
+        }
+    }
+
+    pub fn list(items: &Items, expires_at: Option<u64>) -> Value {
+        Value {
+            kind: Kind::List,
+            ..Value::new(list::encode(items), expires_at)
+        }
+    }
+
+    pub fn set(members: &Members, expires_at: Option<u64>) -> Value {
+        Value {
+            kind: Kind::Set,
+            ..Value::new(set::encode(members), expires_at)
+        }
+    }
+
+    pub fn sorted_set(scores: &Scores, expires_at: Option<u64>) -> Value {
+        Value {
+            kind: Kind::SortedSet,
+            ..Value::new(zset::encode(scores), expires_at)
+        }
+    }
+
+    /// Bytes of a string value, fails with `WrongType` for any other type,
+    /// as do the accessors of the other types.
+    pub fn into_bytes(self) -> Result<Bytes, crate::Error> {
+        self.expect(Kind::String)?;
+
+        Ok(self.bytes)
+    }
+
+    pub fn fields(&self) -> Result<Fields, crate::Error> {
+        self.expect(Kind::Hash)?;
+
+        hash::decode(&self.bytes)
+    }
+
+    pub fn items(&self) -> Result<Items, crate::Error> {
+        self.expect(Kind::List)?;
+
+        list::decode(&self.bytes)
+    }
+
+    pub fn members(&self) -> Result<Members, crate::Error> {
+        self.expect(Kind::Set)?;
+
+        set::decode(&self.bytes)
+    }
+
+    pub fn scores(&self) -> Result<Scores, crate::Error> {
+        self.expect(Kind::SortedSet)?;
+
+        zset::decode(&self.bytes)
+    }
+
+    fn expect(&self, kind: Kind) -> Result<(), WrongType> {
+        if self.kind != kind {
+            return Err(WrongType {
+                expected: kind,
+                found: self.kind,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Whether the value has already expired by `now` milliseconds since the unix epoch.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// What `StorageEngine::update` is to do with the key.
+#[derive(Debug)]
+pub enum Update {
+    Keep,
+    Set(Value),
+    Delete,
+}
+
+/// Condition the key has to satisfy for `StorageEngine::set_with` to write it.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SetCondition {
+    #[default]
+    Always,
+    /// Only if the key doesn't exist yet
+    IfAbsent,
+    /// Only if the key already exists
+    IfPresent,
+}
+
+/// Storage the commands are applied to. Server holds a single engine shared by all
+/// the connections, so implementations take care of their own synchronization.
+///
+/// Methods are blocking, which is fine for quick lookups and appends, while
+/// longer maintenance work like compaction is run on the blocking pool.
+///
+/// Expired keys are never visible, whether or not they have been purged yet.
+pub trait StorageEngine: Send + Sync {
+    fn get_value(&self, key: &str) -> Result<Option<Value>, crate::Error>;
+
+    /// Replaces the current value of the key with the outcome of `f`, with no other
+    /// write to the key in between. Nothing is written if `f` fails.
+    fn update(
+        &self,
+        key: &str,
+        f: &mut dyn FnMut(Option<&Value>) -> Result<Update, crate::Error>,
+    ) -> Result<(), crate::Error>;
+
+    /// Sets the keys paired with a value and deletes the ones paired with `None`,
+    /// all at once: other readers either see none of the writes or all of them.
+    fn write_batch(&self, batch: Vec<(String, Option<Value>)>) -> Result<(), crate::Error> {
+        self.write_batch_if(vec![], batch)?;
+
+        Ok(())
+    }
+
+    /// Writes the batch only if every key of `versions` is still at the given version,
+    /// `None` standing for a missing key. Returns `false` if nothing was written.
+    fn write_batch_if(
+        &self,
+        versions: Vec<(String, Option<u64>)>,
+        batch: Vec<(String, Option<Value>)>,
+    ) -> Result<bool, crate::Error>;
+
+    fn get(&self, key: &str) -> Result<Option<Bytes>, crate::Error> {
+        self.get_value(key)?.map(Value::into_bytes).transpose()
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, crate::Error> {
+        Ok(self.get_value(key)?.is_some())
+    }
+
+    fn set(&self, key: String, value: Bytes) -> Result<(), crate::Error> {
+        self.set_with(key, value, None, SetCondition::Always)?;
+
+        Ok(())
+    }
+
+    /// Returns `false` if the value wasn't written because of the condition.
+    fn set_with(
+        &self,
+        key: String,
+        value: Bytes,
+        expires_at: Option<u64>,
+        condition: SetCondition,
+    ) -> Result<bool, crate::Error> {
+        let mut written = false;
+
+        self.update(&key, &mut |current| {
+            written = match condition {
+                SetCondition::Always => true,
+                SetCondition::IfAbsent => current.is_none(),
+                SetCondition::IfPresent => current.is_some(),
+            };
+
+            if !written {
+                return Ok(Update::Keep);
+            }
+
+            Ok(Update::Set(Value::new(value.clone(), expires_at)))
+        })?;
+
+        Ok(written)
+    }
+
+    /// Sets the value, returning the previous one. Expiration time of the key is cleared.
+    fn get_set(&self, key: String, value: Bytes) -> Result<Option<Bytes>, crate::Error> {
+        let mut previous = None;
+
+        self.update(&key, &mut |current| {
+            previous = current.cloned().map(Value::into_bytes).transpose()?;
+
+            Ok(Update::Set(Value::new(value.clone(), None)))
+        })?;
+
+        Ok(previous)
+    }
+
+    /// Sets the value only if the key exists and its current value or version is
+    /// the expected one. Returns `false` if the value wasn't written.
+    fn compare_and_swap(
+        &self,
+        key: String,
+        expected: Expected,
+        value: Bytes,
+    ) -> Result<bool, crate::Error> {
+        let mut swapped = false;
+
+        self.update(&key, &mut |current| {
+            let current_bytes = current.cloned().map(Value::into_bytes).transpose()?;
+
+            swapped = match (current, &expected) {
+                (Some(_), Expected::Value(bytes)) => current_bytes.as_ref() == Some(bytes),
+                (Some(current), Expected::Version(version)) => current.version == *version,
+                (None, _) => false,
+            };
+
+            if !swapped {
+                return Ok(Update::Keep);
+            }
+
+            let expires_at = current.and_then(|current| current.expires_at);
+
+            Ok(Update::Set(Value::new(value.clone(), expires_at)))
+        })?;
+
+        Ok(swapped)
+    }
+
+    /// Returns `false` if there was nothing to delete.
+    fn delete(&self, key: &str) -> Result<bool, crate::Error> {
+        let mut existed = false;
+
+        self.update(key, &mut |current| {
+            existed = current.is_some();
+
+            Ok(if existed {
+                Update::Delete
+            } else {
+                Update::Keep
+            })
+        })?;
+
+        Ok(existed)
+    }
+
+    /// Adds `delta` to the integer stored as decimal string, a missing key counting
+    /// as 0, and returns the result. `None` is returned and nothing is written if the
+    /// value isn't an integer or the result would overflow. Expiration time is kept.
+    fn increment(&self, key: &str, delta: i64) -> Result<Option<i64>, crate::Error> {
+        let mut result = None;
+
+        self.update(key, &mut |current| {
+            let count = match current.cloned().map(Value::into_bytes).transpose()? {
+                Some(bytes) => std::str::from_utf8(&bytes)
+                    .ok()
+                    .and_then(|count| count.parse::<i64>().ok()),
+                None => Some(0),
+            };
+
+            result = count.and_then(|count| count.checked_add(delta));
+
+            let count = match result {
+                Some(count) => count,
+                None => return Ok(Update::Keep),
+            };
+
+            let expires_at = current.and_then(|current| current.expires_at);
+
+            Ok(Update::Set(Value::new(
+                Bytes::from(count.to_string()),
+                expires_at,
+            )))
+        })?;
+
+        Ok(result)
+    }
+
+    /// Sets or clears the expiration time of an existing key, returning the previous
+    /// one. `None` is returned if there is no such key.
+    fn set_expiry(
+        &self,
+        key: &str,
+        expires_at: Option<u64>,
+    ) -> Result<Option<Option<u64>>, crate::Error> {
+        let mut previous = None;
+
+        self.update(key, &mut |current| {
+            let current = match current {
+                Some(current) => current,
+                None => return Ok(Update::Keep),
+            };
+
+            previous = Some(current.expires_at);
+
+            if current.expires_at == expires_at {
+                return Ok(Update::Keep);
+            }
+
+            Ok(Update::Set(Value {
+                expires_at,
+                ..current.clone()
+            }))
+        })?;
+
+        Ok(previous)
+    }
+
+    /// Sets the fields of the hash, creating it if the key is missing. Returns how many
+    /// of the fields weren't in the hash before.
+    fn hash_set(&self, key: &str, fields: Vec<(String, Bytes)>) -> Result<usize, crate::Error> {
+        let mut added = 0;
+
+        if fields.is_empty() {
+            return Ok(added);
+        }
+
+        self.update(key, &mut |current| {
+            let (mut hash, expires_at) = match current {
+                Some(current) => (current.fields()?, current.expires_at),
+                None => (Fields::new(), None),
+            };
+
+            added = 0;
+
+            for (field, value) in fields.iter() {
+                if hash.insert(field.clone(), value.clone()).is_none() {
+                    added += 1;
+                }
+            }
+
+            Ok(Update::Set(Value::hash(&hash, expires_at)))
+        })?;
+
+        Ok(added)
+    }
+
+    fn hash_get(&self, key: &str, field: &str) -> Result<Option<Bytes>, crate::Error> {
+        match self.get_value(key)? {
+            Some(value) => Ok(value.fields()?.remove(field)),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes the fields from the hash, returning how many of them were there.
+    /// Hash left without any fields is deleted.
+    fn hash_delete(&self, key: &str, fields: &[String]) -> Result<usize, crate::Error> {
+        let mut removed = 0;
+
+        self.update(key, &mut |current| {
+            let current = match current {
+                Some(current) => current,
+                None => return Ok(Update::Keep),
+            };
+
+            let mut hash = current.fields()?;
+
+            removed = fields
+                .iter()
+                .filter(|field| hash.remove(*field).is_some())
+                .count();
+
+            Ok(if removed == 0 {
+                Update::Keep
+            } else if hash.is_empty() {
+                Update::Delete
+            } else {
+                Update::Set(Value::hash(&hash, current.expires_at))
+            })
+        })?;
+
+        Ok(removed)
+    }
+
+    /// All the fields of the hash, none if the key is missing.
+    fn hash_get_all(&self, key: &str) -> Result<Fields, crate::Error> {
+        match self.get_value(key)? {
+            Some(value) => value.fields(),
+            None => Ok(Fields::new()),
+        }
+    }
+
+    /// Pushes the values one by one to the end of the list, creating it if the key is
+    /// missing, so values pushed to the left end come out in reverse. Returns the
+    /// length of the list after the push.
+    fn list_push(
+        &self,
+        key: &str,
+        values: Vec<Bytes>,
+        end: ListEnd,
+    ) -> Result<usize, crate::Error> {
+        let mut len = 0;
+
+        self.update(key, &mut |current| {
+            let (mut items, expires_at) = match current {
+                Some(current) => (current.items()?, current.expires_at),
+                None => (Items::new(), None),
+            };
+
+            for value in values.iter().cloned() {
+                match end {
+                    ListEnd::Left => items.push_front(value),
+                    ListEnd::Right => items.push_back(value),
+                }
+            }
+
+            len = items.len();
+
+            Ok(if values.is_empty() {
+                Update::Keep
+            } else {
+                Update::Set(Value::list(&items, expires_at))
+            })
+        })?;
+
+        Ok(len)
+    }
+
+    /// Removes the item at the end of the list and returns it.
+    /// List left without any items is deleted.
+    fn list_pop(&self, key: &str, end: ListEnd) -> Result<Option<Bytes>, crate::Error> {
+        let mut popped = None;
+
+        self.update(key, &mut |current| {
+            let current = match current {
+                Some(current) => current,
+                None => return Ok(Update::Keep),
+            };
+
+            let mut items = current.items()?;
+
+            popped = match end {
+                ListEnd::Left => items.pop_front(),
+                ListEnd::Right => items.pop_back(),
+            };
+
+            Ok(if items.is_empty() {
+                Update::Delete
+            } else {
+                Update::Set(Value::list(&items, current.expires_at))
+            })
+        })?;
+
+        Ok(popped)
+    }
+
+    /// Items between the two positions, both included. Negative positions count
+    /// from the right end, -1 being the last item. Positions past either end
+    /// are clamped, none if the key is missing.
+    fn list_range(&self, key: &str, start: i64, stop: i64) -> Result<Vec<Bytes>, crate::Error> {
+        let items = match self.get_value(key)? {
+            Some(value) => value.items()?,
+            None => return Ok(vec![]),
+        };
+
+        let range = rank_range(items.len(), start, stop);
+
+        Ok(items
+            .into_iter()
+            .skip(range.start)
+            .take(range.len())
+            .collect())
+    }
+
+    /// Length of the list, 0 if the key is missing.
+    fn list_len(&self, key: &str) -> Result<usize, crate::Error> {
+        match self.get_value(key)? {
+            Some(value) => Ok(value.items()?.len()),
+            None => Ok(0),
+        }
+    }
+
+    /// Adds the members to the set, creating it if the key is missing. Returns how
+    /// many of them weren't in the set before.
+    fn set_add(&self, key: &str, members: Vec<Bytes>) -> Result<usize, crate::Error> {
+        let mut added = 0;
+
+        self.update(key, &mut |current| {
+            let (mut set, expires_at) = match current {
+                Some(current) => (current.members()?, current.expires_at),
+                None => (Members::new(), None),
+            };
+
+            added = members
+                .iter()
+                .filter(|member| set.insert((*member).clone()))
+                .count();
+
+            Ok(if added == 0 {
+                Update::Keep
+            } else {
+                Update::Set(Value::set(&set, expires_at))
+            })
+        })?;
+
+        Ok(added)
+    }
+
+    /// Removes the members from the set, returning how many of them were there.
+    /// Set left without any members is deleted.
+    fn set_remove(&self, key: &str, members: &[Bytes]) -> Result<usize, crate::Error> {
+        let mut removed = 0;
+
+        self.update(key, &mut |current| {
+            let current = match current {
+                Some(current) => current,
+                None => return Ok(Update::Keep),
+            };
+
+            let mut set = current.members()?;
+
+            removed = members.iter().filter(|member| set.remove(*member)).count();
+
+            Ok(if removed == 0 {
+                Update::Keep
+            } else if set.is_empty() {
+                Update::Delete
+            } else {
+                Update::Set(Value::set(&set, current.expires_at))
+            })
+        })?;
+
+        Ok(removed)
+    }
+
+    /// All the members of the set, none if the key is missing.
+    fn set_members(&self, key: &str) -> Result<Members, crate::Error> {
+        match self.get_value(key)? {
+            Some(value) => value.members(),
+            None => Ok(Members::new()),
+        }
+    }
+
+    fn set_contains(&self, key: &str, member: &[u8]) -> Result<bool, crate::Error> {
+        Ok(self.set_members(key)?.contains(member))
+    }
+
+    /// Members found in every one of the sets, a missing key being an empty set.
+    /// Sets are read one after another rather than at once.
+    fn set_intersect(&self, keys: &[String]) -> Result<Members, crate::Error> {
+        let mut keys = keys.iter();
+
+        let mut common = match keys.next() {
+            Some(key) => self.set_members(key)?,
+            None => return Ok(Members::new()),
+        };
+
+        for key in keys {
+            let members = self.set_members(key)?;
+            common.retain(|member| members.contains(member));
+        }
+
+        Ok(common)
+    }
+
+    /// Adds the members to the sorted set with their scores, creating it if the key
+    /// is missing. Scores of the members already there are updated. Returns how many
+    /// of the members weren't in the sorted set before.
+    fn sorted_set_add(&self, key: &str, scores: Vec<(f64, Bytes)>) -> Result<usize, crate::Error> {
+        let mut added = 0;
+
+        if scores.is_empty() {
+            return Ok(added);
+        }
+
+        self.update(key, &mut |current| {
+            let (mut sorted_set, expires_at) = match current {
+                Some(current) => (current.scores()?, current.expires_at),
+                None => (Scores::new(), None),
+            };
+
+            added = 0;
+
+            for (score, member) in scores.iter() {
+                if sorted_set.insert(member.clone(), *score).is_none() {
+                    added += 1;
+                }
+            }
+
+            Ok(Update::Set(Value::sorted_set(&sorted_set, expires_at)))
+        })?;
+
+        Ok(added)
+    }
+
+    /// Members between the two ranks, both included, along with their scores.
+    /// Ranks go by score and are otherwise treated like `list_range` positions.
+    fn sorted_set_range(
+        &self,
+        key: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<(Bytes, f64)>, crate::Error> {
+        let ranked = match self.get_value(key)? {
+            Some(value) => zset::ranked(value.scores()?),
+            None => return Ok(vec![]),
+        };
+
+        let range = rank_range(ranked.len(), start, stop);
+
+        Ok(ranked
+            .into_iter()
+            .skip(range.start)
+            .take(range.len())
+            .collect())
+    }
+
+    /// Members with scores between the bounds, in score order, along with their scores.
+    fn sorted_set_range_by_score(
+        &self,
+        key: &str,
+        min: Bound<f64>,
+        max: Bound<f64>,
+    ) -> Result<Vec<(Bytes, f64)>, crate::Error> {
+        let ranked = match self.get_value(key)? {
+            Some(value) => zset::ranked(value.scores()?),
+            None => return Ok(vec![]),
+        };
+
+        Ok(ranked
+            .into_iter()
+            .filter(|(_, score)| (min, max).contains(score))
+            .collect())
+    }
+
+    /// Position of the member in score order, starting from 0.
+    fn sorted_set_rank(&self, key: &str, member: &[u8]) -> Result<Option<usize>, crate::Error> {
+        let ranked = match self.get_value(key)? {
+            Some(value) => zset::ranked(value.scores()?),
+            None => return Ok(None),
+        };
+
+        Ok(ranked.iter().position(|(ranked, _)| ranked == member))
+    }
+
+    /// Adds `delta` to the score of the member, a missing member being added with
+    /// a score of 0, and returns the new score. `None` is returned and nothing is
+    /// written if the score would be NaN, e.g. when adding -inf to inf.
+    fn sorted_set_increment(
+        &self,
+        key: &str,
+        member: Bytes,
+        delta: f64,
+    ) -> Result<Option<f64>, crate::Error> {
+        let mut result = None;
+
+        self.update(key, &mut |current| {
+            let (mut sorted_set, expires_at) = match current {
+                Some(current) => (current.scores()?, current.expires_at),
+                None => (Scores::new(), None),
+            };
+
+            let score = sorted_set.get(&member).copied().unwrap_or(0.0) + delta;
+
+            result = Some(score).filter(|score| !score.is_nan());
+
+            if result.is_none() {
+                return Ok(Update::Keep);
+            }
+
+            sorted_set.insert(member.clone(), score);
+
+            Ok(Update::Set(Value::sorted_set(&sorted_set, expires_at)))
+        })?;
+
+        Ok(result)
+    }
+
+    /// Sets the value at the path of the JSON document held by the key as a string.
+    /// Missing key is created, as long as the path is the root. Returns `false` if
+    /// nothing was set because the parent of the path isn't there.
+    fn json_set(&self, key: &str, path: &JsonPath, value: Json) -> Result<bool, crate::Error> {
+        let mut written = false;
+
+        self.update(key, &mut |current| {
+            let (mut doc, expires_at) = match current {
+                Some(current) => (
+                    json::parse_document(&current.clone().into_bytes()?)?,
+                    current.expires_at,
+                ),
+                None if path.is_root() => (Json::Null, None),
+                None => return Ok(Update::Keep),
+            };
+
+            written = path.set(&mut doc, value.clone());
+
+            if !written {
+                return Ok(Update::Keep);
+            }
+
+            Ok(Update::Set(Value::new(
+                Bytes::from(serde_json::to_vec(&doc)?),
+                expires_at,
+            )))
+        })?;
+
+        Ok(written)
+    }
+
+    /// Value at the path of the JSON document, `None` if either is missing.
+    fn json_get(&self, key: &str, path: &JsonPath) -> Result<Option<Json>, crate::Error> {
+        let doc = match self.get(key)? {
+            Some(bytes) => json::parse_document(&bytes)?,
+            None => return Ok(None),
+        };
+
+        Ok(path.get(&doc).cloned())
+    }
+
+    /// Removes the value at the path of the JSON document, the root path deleting
+    /// the whole key. Returns `false` if there was nothing to remove.
+    fn json_delete(&self, key: &str, path: &JsonPath) -> Result<bool, crate::Error> {
+        let mut removed = false;
+
+        self.update(key, &mut |current| {
+            let current = match current {
+                Some(current) => current,
+                None => return Ok(Update::Keep),
+            };
+
+            let mut doc = json::parse_document(&current.clone().into_bytes()?)?;
+
+            if path.is_root() {
+                removed = true;
+                return Ok(Update::Delete);
+            }
+
+            removed = path.remove(&mut doc);
+
+            if !removed {
+                return Ok(Update::Keep);
+            }
+
+            Ok(Update::Set(Value::new(
+                Bytes::from(serde_json::to_vec(&doc)?),
+                current.expires_at,
+            )))
+        })?;
+
+        Ok(removed)
+    }
+
+    /// Adds `delta` to the number at the path of the JSON document and returns the
+    /// result, `None` if either is missing. Fails with `JsonError::NotANumber` if
+    /// the value isn't a number or the result wouldn't be a finite one.
+    fn json_increment(
+        &self,
+        key: &str,
+        path: &JsonPath,
+        delta: &Number,
+    ) -> Result<Option<Number>, crate::Error> {
+        let mut result = None;
+
+        self.update(key, &mut |current| {
+            result = None;
+
+            let current = match current {
+                Some(current) => current,
+                None => return Ok(Update::Keep),
+            };
+
+            let mut doc = json::parse_document(&current.clone().into_bytes()?)?;
+
+            let number = match path.get_mut(&mut doc) {
+                Some(Json::Number(number)) => number,
+                Some(_) => return Err(JsonError::NotANumber.into()),
+                None => return Ok(Update::Keep),
+            };
+
+            *number = json::add_numbers(number, delta).ok_or(JsonError::NotANumber)?;
+            result = Some(number.clone());
+
+            Ok(Update::Set(Value::new(
+                Bytes::from(serde_json::to_vec(&doc)?),
+                current.expires_at,
+            )))
+        })?;
+
+        Ok(result)
+    }
+
+    /// Up to `limit` live key-value pairs with keys within the range, in key order
+    /// or, if `reverse` is set, starting from the last key of the range backwards.
+    fn scan(
+        &self,
+        range: KeyRange,
+        reverse: bool,
+        limit: usize,
+    ) -> Result<Vec<(String, Bytes)>, crate::Error>;
+
+    /// Earliest point in time, in milliseconds since the unix epoch, the keys can be
+    /// read as of. `None` if the engine keeps no history.
+    fn history_start(&self) -> Option<u64> {
+        None
+    }
+
+    /// Value the key had at the point in time, which is compared against versions.
+    /// Point in time has to be no earlier than `history_start`.
+    fn get_value_at(&self, key: &str, at: u64) -> Result<Option<Value>, crate::Error> {
+        let _ = (key, at);
+
+        Err("engine keeps no history".into())
+    }
+
+    /// Same as `scan`, but as of the point in time, so that pages scanned one after
+    /// another see the same keys and values while writes continue.
+    fn scan_at(
+        &self,
+        range: KeyRange,
+        reverse: bool,
+        limit: usize,
+        at: u64,
+    ) -> Result<Vec<(String, Bytes)>, crate::Error> {
+        let _ = (range, reverse, limit, at);
+
+        Err("engine keeps no history".into())
+    }
+
+    /// All live keys in key order.
+    fn keys(&self) -> Result<Vec<String>, crate::Error> {
+        let entries = self.scan((Bound::Unbounded, Bound::Unbounded), false, usize::MAX)?;
+
+        Ok(entries.into_iter().map(|(key, _)| key).collect())
+    }
+
+    /// Live keys whose field covered by the secondary index holds the value, in key
+    /// order. `None` if there is no such index, which is always the case for engines
+    /// keeping none.
+    fn find(&self, index: &str, value: &Json) -> Result<Option<Vec<String>>, crate::Error> {
+        let _ = (index, value);
+
+        Ok(None)
+    }
+
+    /// Amount of live keys.
+    fn key_count(&self) -> Result<usize, crate::Error> {
+        Ok(self.keys()?.len())
+    }
+
+    /// Reclaims space taken by overwritten, deleted and expired values.
+    fn compact(&self) -> Result<(), crate::Error>;
+
+    /// Drops expired keys still held in memory, returning how many of them there were.
+    fn purge_expired(&self) -> Result<usize, crate::Error> {
+        Ok(0)
+    }
+
+    /// Flushes acknowledged writes to durable storage.
+    fn sync(&self) -> Result<(), crate::Error> {
+        Ok(())
+    }
+
+    /// How often the server should call `sync`, if at all.
+    fn sync_interval(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::String => write!(f, "string"),
+            Kind::Hash => write!(f, "hash"),
+            Kind::List => write!(f, "list"),
+            Kind::Set => write!(f, "set"),
+            Kind::SortedSet => write!(f, "sorted set"),
+        }
+    }
+}
+
+impl fmt::Display for WrongType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "value is a {} rather than a {}",
+            self.found, self.expected
+        )
+    }
+}
+
+impl std::error::Error for WrongType {}
+
+/// Indexes of the items between the two positions of a sequence, both included.
+/// Negative positions count from the end, -1 being the last item. Positions past
+/// either end are clamped.
+pub(crate) fn rank_range(len: usize, start: i64, stop: i64) -> Range<usize> {
+    let len = len as i64;
+    let position = |index: i64| if index < 0 { len + index } else { index };
+
+    let start = position(start).clamp(0, len);
+    let stop = position(stop).clamp(-1, len - 1);
+
+    if start > stop {
+        return 0..0;
+    }
+
+    start as usize..stop as usize + 1
+}
+
+/// Whether the range can't hold any key. Ranges like that make `BTreeMap::range` panic.
+pub(crate) fn is_empty_range<K: Ord + ?Sized>(range: &impl RangeBounds<K>) -> bool {
+    match (range.start_bound(), range.end_bound()) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end))
+        | (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+        _ => false,
+    }
+}
+
+/// Whether the key goes after all the keys of the range.
+pub(crate) fn is_past_range<K: Ord + ?Sized>(range: &impl RangeBounds<K>, key: &K) -> bool {
+    match range.end_bound() {
+        Bound::Included(end) => key > end,
+        Bound::Excluded(end) => key >= end,
+        Bound::Unbounded => false,
+    }
+}
+
+/// Version for the next write: the current time, unless that doesn't come after
+/// the last version, which happens on several writes within a millisecond
+/// or when the clock goes backwards.
+pub(crate) fn next_version(last_version: u64) -> u64 {
+    crate::db::now_millis().max(last_version + 1)
+}
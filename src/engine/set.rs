@@ -0,0 +1,84 @@
+use std::collections::BTreeSet;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+/// Members of a set, in member order.
+pub type Members = BTreeSet<Bytes>;
+
+/*
+    Set value layout (all integers are big-endian):
+
+    +-------+------------+--------+
+    | count | member_len | member |  ... `count` times, in member order
+    |  u32  |    u32     |        |
+    +-------+------------+--------+
+*/
+
+pub(crate) fn encode(members: &Members) -> Bytes {
+    let capacity: usize = members.iter().map(|member| 4 + member.len()).sum();
+    let mut buf = BytesMut::with_capacity(4 + capacity);
+
+    buf.put_u32(members.len() as u32);
+
+    for member in members {
+        buf.put_u32(member.len() as u32);
+        buf.put_slice(member);
+    }
+
+    buf.freeze()
+}
+
+/// Members are slices of the buffer rather than copies.
+pub(crate) fn decode(buf: &Bytes) -> Result<Members, crate::Error> {
+    let mut src = &buf[..];
+    let mut members = Members::new();
+
+    let count = read_len(&mut src)?;
+
+    for _ in 0..count {
+        let member_len = read_len(&mut src)?;
+        let member_start = buf.len() - src.len();
+        src.advance(member_len);
+
+        members.insert(buf.slice(member_start..member_start + member_len));
+    }
+
+    if src.has_remaining() {
+        return Err("set has trailing bytes".into());
+    }
+
+    Ok(members)
+}
+
+/// Length, or count, which is checked not to exceed the rest of the buffer.
+fn read_len(src: &mut &[u8]) -> Result<usize, crate::Error> {
+    if src.remaining() < 4 {
+        return Err("set ended early".into());
+    }
+
+    let len = src.get_u32() as usize;
+
+    if src.remaining() < len {
+        return Err("set ended early".into());
+    }
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_roundtrip() -> Result<(), crate::Error> {
+        let members = Members::from([Bytes::from("rust"), Bytes::new(), Bytes::from("go")]);
+
+        let encoded = encode(&members);
+
+        assert_eq!(decode(&encoded)?, members);
+        assert_eq!(decode(&encode(&Members::new()))?, Members::new());
+        assert!(decode(&encoded.slice(..encoded.len() - 1)).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use serde_json::Value as Json;
+
+use super::{KeyRange, StorageEngine, Update, Value};
+use crate::db::now_millis;
+
+/// Engine buffering the writes on top of another one, until they are committed
+/// as a single batch. Commit only succeeds if none of the keys read through the
+/// transaction has been written to the underlying engine since.
+///
+/// Keys seen by scans aren't checked on commit, only the ones read individually.
+/// Values written within the transaction have no version until they are committed.
+pub struct Transaction<'a> {
+    engine: &'a dyn StorageEngine,
+    state: Mutex<TransactionState>,
+}
+
+struct TransactionState {
+    /// Versions the keys had when they were first read, `None` for missing keys
+    reads: BTreeMap<String, Option<u64>>,
+    /// Values to be committed, `None` standing for a deletion
+    writes: BTreeMap<String, Option<Value>>,
+}
+
+impl<'a> Transaction<'a> {
+    /// Keys in `watched` are checked on commit along with the ones read later on,
+    /// as if they were read at the given versions.
+    pub fn new(
+        engine: &'a dyn StorageEngine,
+        watched: Vec<(String, Option<u64>)>,
+    ) -> Transaction<'a> {
+        let state = TransactionState {
+            reads: watched.into_iter().collect(),
+            writes: BTreeMap::new(),
+        };
+
+        Transaction {
+            engine,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Writes all the buffered values at once. Returns `false` if nothing was
+    /// written because some of the keys read have changed in the meantime.
+    pub fn commit(self) -> Result<bool, crate::Error> {
+        let state = self.state.into_inner().unwrap();
+
+        self.engine.write_batch_if(
+            state.reads.into_iter().collect(),
+            state.writes.into_iter().collect(),
+        )
+    }
+}
+
+impl TransactionState {
+    fn read(
+        &mut self,
+        engine: &dyn StorageEngine,
+        key: &str,
+    ) -> Result<Option<Value>, crate::Error> {
+        if let Some(value) = self.writes.get(key) {
+            return Ok(value
+                .clone()
+                .filter(|value| !value.is_expired(now_millis())));
+        }
+
+        let value = engine.get_value(key)?;
+
+        self.reads
+            .entry(key.to_string())
+            .or_insert(value.as_ref().map(|value| value.version));
+
+        Ok(value)
+    }
+}
+
+impl StorageEngine for Transaction<'_> {
+    fn get_value(&self, key: &str) -> Result<Option<Value>, crate::Error> {
+        self.state.lock().unwrap().read(self.engine, key)
+    }
+
+    fn update(
+        &self,
+        key: &str,
+        f: &mut dyn FnMut(Option<&Value>) -> Result<Update, crate::Error>,
+    ) -> Result<(), crate::Error> {
+        let mut state = self.state.lock().unwrap();
+
+        let current = state.read(self.engine, key)?;
+
+        match f(current.as_ref())? {
+            Update::Keep => {}
+            Update::Set(value) => {
+                state.writes.insert(key.to_string(), Some(value));
+            }
+            Update::Delete if current.is_none() => {}
+            Update::Delete => {
+                state.writes.insert(key.to_string(), None);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_batch_if(
+        &self,
+        versions: Vec<(String, Option<u64>)>,
+        batch: Vec<(String, Option<Value>)>,
+    ) -> Result<bool, crate::Error> {
+        let mut state = self.state.lock().unwrap();
+
+        for (key, version) in versions {
+            if state.read(self.engine, &key)?.map(|value| value.version) != version {
+                return Ok(false);
+            }
+        }
+
+        state.writes.extend(batch);
+
+        Ok(true)
+    }
+
+    /// Every buffered write within the range may hide one of the entries
+    /// of the underlying engine, so that many more are fetched from it.
+    fn scan(
+        &self,
+        range: KeyRange,
+        reverse: bool,
+        limit: usize,
+    ) -> Result<Vec<(String, Bytes)>, crate::Error> {
+        if super::is_empty_range(&range) {
+            return Ok(vec![]);
+        }
+
+        let state = self.state.lock().unwrap();
+        let pending: Vec<_> = state.writes.range(range.clone()).collect();
+
+        let mut entries: BTreeMap<String, Bytes> = self
+            .engine
+            .scan(range, reverse, limit.saturating_add(pending.len()))?
+            .into_iter()
+            .collect();
+
+        let now = now_millis();
+
+        for (key, value) in pending {
+            match value {
+                Some(value) if !value.is_expired(now) => {
+                    entries.insert(key.clone(), value.bytes.clone());
+                }
+                _ => {
+                    entries.remove(key);
+                }
+            }
+        }
+
+        if reverse {
+            Ok(entries.into_iter().rev().take(limit).collect())
+        } else {
+            Ok(entries.into_iter().take(limit).collect())
+        }
+    }
+
+    /// Past doesn't depend on the writes of the transaction.
+    fn history_start(&self) -> Option<u64> {
+        self.engine.history_start()
+    }
+
+    fn get_value_at(&self, key: &str, at: u64) -> Result<Option<Value>, crate::Error> {
+        self.engine.get_value_at(key, at)
+    }
+
+    fn scan_at(
+        &self,
+        range: KeyRange,
+        reverse: bool,
+        limit: usize,
+        at: u64,
+    ) -> Result<Vec<(String, Bytes)>, crate::Error> {
+        self.engine.scan_at(range, reverse, limit, at)
+    }
+
+    /// Index doesn't cover the writes of the transaction.
+    fn find(&self, index: &str, value: &Json) -> Result<Option<Vec<String>>, crate::Error> {
+        self.engine.find(index, value)
+    }
+
+    /// Nothing is stored until the commit.
+    fn compact(&self) -> Result<(), crate::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ops::Bound;
+
+    use crate::engine::MemoryEngine;
+
+    #[test]
+    fn test_transaction() -> Result<(), crate::Error> {
+        let engine = MemoryEngine::new();
+
+        engine.set("a".to_string(), Bytes::from("1"))?;
+        engine.set("b".to_string(), Bytes::from("2"))?;
+
+        let transaction = Transaction::new(&engine, vec![]);
+
+        assert_eq!(transaction.increment("a", 10)?, Some(11));
+        assert!(transaction.delete("b")?);
+        transaction.set("c".to_string(), Bytes::from("3"))?;
+
+        // Writes are only visible within the transaction until the commit
+        assert_eq!(transaction.get("a")?, Some(Bytes::from("11")));
+        assert_eq!(transaction.get("b")?, None);
+        assert_eq!(engine.get("a")?, Some(Bytes::from("1")));
+        assert_eq!(transaction.keys()?, vec!["a".to_string(), "c".to_string()]);
+        assert_eq!(
+            transaction.scan((Bound::Unbounded, Bound::Unbounded), true, 1)?,
+            vec![("c".to_string(), Bytes::from("3"))]
+        );
+
+        assert!(transaction.commit()?);
+
+        assert_eq!(engine.get("a")?, Some(Bytes::from("11")));
+        assert_eq!(engine.get("b")?, None);
+        assert_eq!(engine.get("c")?, Some(Bytes::from("3")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflicting_commit() -> Result<(), crate::Error> {
+        let engine = MemoryEngine::new();
+
+        engine.set("a".to_string(), Bytes::from("1"))?;
+
+        let transaction = Transaction::new(&engine, vec![]);
+        transaction.increment("a", 1)?;
+
+        // Key read by the transaction is written before it commits
+        engine.set("a".to_string(), Bytes::from("5"))?;
+
+        assert!(!transaction.commit()?);
+        assert_eq!(engine.get("a")?, Some(Bytes::from("5")));
+
+        // Watched key counts as read, even if the transaction never reads it
+        let version = engine.get_value("a")?.map(|value| value.version);
+        let transaction = Transaction::new(&engine, vec![("a".to_string(), version)]);
+        transaction.set("b".to_string(), Bytes::from("2"))?;
+
+        engine.delete("a")?;
+
+        assert!(!transaction.commit()?);
+        assert_eq!(engine.get("b")?, None);
+
+        Ok(())
+    }
+}
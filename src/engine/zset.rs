@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+/// Members of a sorted set along with their scores, in member order.
+pub type Scores = BTreeMap<Bytes, f64>;
+
+/*
+    Sorted set value layout (all integers are big-endian):
+
+    +-------+------------+--------+-------+
+    | count | member_len | member | score |  ... `count` times, in member order
+    |  u32  |    u32     |        |  f64  |
+    +-------+------------+--------+-------+
+*/
+
+pub(crate) fn encode(scores: &Scores) -> Bytes {
+    let capacity: usize = scores.keys().map(|member| 12 + member.len()).sum();
+    let mut buf = BytesMut::with_capacity(4 + capacity);
+
+    buf.put_u32(scores.len() as u32);
+
+    for (member, score) in scores {
+        buf.put_u32(member.len() as u32);
+        buf.put_slice(member);
+        buf.put_f64(*score);
+    }
+
+    buf.freeze()
+}
+
+/// Members are slices of the buffer rather than copies.
+pub(crate) fn decode(buf: &Bytes) -> Result<Scores, crate::Error> {
+    let mut src = &buf[..];
+    let mut scores = Scores::new();
+
+    let count = read_len(&mut src)?;
+
+    for _ in 0..count {
+        let member_len = read_len(&mut src)?;
+        let member_start = buf.len() - src.len();
+        src.advance(member_len);
+
+        if src.remaining() < 8 {
+            return Err("sorted set ended early".into());
+        }
+
+        let score = src.get_f64();
+
+        scores.insert(buf.slice(member_start..member_start + member_len), score);
+    }
+
+    if src.has_remaining() {
+        return Err("sorted set has trailing bytes".into());
+    }
+
+    Ok(scores)
+}
+
+/// Members ordered by score, ties broken by member order.
+pub(crate) fn ranked(scores: Scores) -> Vec<(Bytes, f64)> {
+    let mut ranked: Vec<(Bytes, f64)> = scores.into_iter().collect();
+
+    // Sort is stable, so members with the same score stay in member order
+    ranked.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+    ranked
+}
+
+/// Length, or count, which is checked not to exceed the rest of the buffer.
+fn read_len(src: &mut &[u8]) -> Result<usize, crate::Error> {
+    if src.remaining() < 4 {
+        return Err("sorted set ended early".into());
+    }
+
+    let len = src.get_u32() as usize;
+
+    if src.remaining() < len {
+        return Err("sorted set ended early".into());
+    }
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sorted_set_roundtrip() -> Result<(), crate::Error> {
+        let scores = Scores::from([
+            (Bytes::from("ada"), 3.5),
+            (Bytes::from("bob"), -1.0),
+            (Bytes::from("cy"), f64::INFINITY),
+            (Bytes::from("dan"), 3.5),
+        ]);
+
+        let encoded = encode(&scores);
+
+        assert_eq!(decode(&encoded)?, scores);
+        assert_eq!(decode(&encode(&Scores::new()))?, Scores::new());
+        assert!(decode(&encoded.slice(..encoded.len() - 1)).is_err());
+
+        let members: Vec<Bytes> = ranked(scores).into_iter().map(|(m, _)| m).collect();
+        assert_eq!(members, vec!["bob", "ada", "dan", "cy"]);
+
+        Ok(())
+    }
+}
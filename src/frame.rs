@@ -10,15 +10,34 @@ use bytes::{Buf, Bytes};
 pub enum Frame {
     Error(FrameErrorKind), // -
     Simple(String),        // +
-    Integer(u64),          // :
+    Integer(i64),          // :
     Bulk(Bytes),           // $
     Array(Vec<Frame>),     // *
+    Null,                  // _
 }
 
 #[derive(Debug, Clone)]
 pub enum FrameErrorKind {
     NotFound,
     InternalError,
+    NotAnInteger,
+    /// `EXEC` or `DISCARD` without `MULTI`
+    NoTransaction,
+    /// `MULTI` or `WATCH` within a transaction
+    InTransaction,
+    /// Point in time is past the history kept by the engine
+    HistoryUnavailable,
+    /// Key holds a value of another type than the command is for
+    WrongType,
+    NotAFloat,
+    /// Value of the key isn't valid JSON
+    NotJson,
+    /// Value at the JSON path isn't a number
+    NotANumber,
+    /// `FIND` against an index which isn't defined
+    NoSuchIndex,
+    /// Time to live runs out too far in the future
+    InvalidExpireTime,
 }
 
 #[derive(Debug)]
@@ -62,6 +81,16 @@ impl Frame {
                 get_line(src)?;
                 Ok(())
             }
+            // integer
+            b':' => {
+                get_integer(src)?;
+                Ok(())
+            }
+            // null
+            b'_' => {
+                get_line(src)?;
+                Ok(())
+            }
             // bulk
             b'$' => {
                 let len = get_decimal(src)? as usize;
@@ -105,6 +134,16 @@ impl Frame {
 
                 Ok(Frame::Simple(string))
             }
+            b':' => {
+                let value = get_integer(src)?;
+
+                Ok(Frame::Integer(value))
+            }
+            b'_' => {
+                get_line(src)?;
+
+                Ok(Frame::Null)
+            }
             b'$' => {
                 let len = get_decimal(src)? as usize;
                 let n = len + 2;
@@ -143,6 +182,14 @@ fn get_decimal(src: &mut Cursor<&[u8]>) -> Result<u64, Error> {
     atoi::<u64>(line).ok_or_else(|| "protocol error; invalid frame format".into())
 }
 
+/// Unlike lengths, integer values may be negative.
+fn get_integer(src: &mut Cursor<&[u8]>) -> Result<i64, Error> {
+    let line = str::from_utf8(get_line(src)?)?;
+
+    line.parse()
+        .map_err(|_| "protocol error; invalid frame format".into())
+}
+
 /// A "line" refers to a sequence of bytes that is terminated by a carriage return
 fn get_line<'a>(src: &mut Cursor<&'a [u8]>) -> Result<&'a [u8], Error> {
     let start = src.position() as usize;
@@ -160,7 +207,7 @@ fn get_line<'a>(src: &mut Cursor<&'a [u8]>) -> Result<&'a [u8], Error> {
         }
     }
 
-    return Err(Error::Incomplete);
+    Err(Error::Incomplete)
 }
 
 fn get_descriptor(src: &mut Cursor<&[u8]>) -> Result<u8, Error> {
@@ -185,6 +232,19 @@ impl fmt::Display for FrameErrorKind {
         match self {
             FrameErrorKind::NotFound => write!(f, "not found"),
             FrameErrorKind::InternalError => write!(f, "internal error"),
+            FrameErrorKind::NotAnInteger => write!(f, "value is not an integer or out of range"),
+            FrameErrorKind::NoTransaction => write!(f, "no transaction is in progress"),
+            FrameErrorKind::InTransaction => write!(f, "not allowed within a transaction"),
+            FrameErrorKind::HistoryUnavailable => write!(f, "history is not kept that far back"),
+            FrameErrorKind::WrongType => write!(
+                f,
+                "WRONGTYPE operation against a key holding the wrong kind of value"
+            ),
+            FrameErrorKind::NotAFloat => write!(f, "resulting score is not a number"),
+            FrameErrorKind::NotJson => write!(f, "value is not valid JSON"),
+            FrameErrorKind::NotANumber => write!(f, "value at the path is not a number"),
+            FrameErrorKind::NoSuchIndex => write!(f, "no such index"),
+            FrameErrorKind::InvalidExpireTime => write!(f, "invalid expire time"),
         }
     }
 }
@@ -196,6 +256,18 @@ impl str::FromStr for FrameErrorKind {
         match s {
             "not found" => Ok(FrameErrorKind::NotFound),
             "internal error" => Ok(FrameErrorKind::InternalError),
+            "value is not an integer or out of range" => Ok(FrameErrorKind::NotAnInteger),
+            "no transaction is in progress" => Ok(FrameErrorKind::NoTransaction),
+            "not allowed within a transaction" => Ok(FrameErrorKind::InTransaction),
+            "history is not kept that far back" => Ok(FrameErrorKind::HistoryUnavailable),
+            "WRONGTYPE operation against a key holding the wrong kind of value" => {
+                Ok(FrameErrorKind::WrongType)
+            }
+            "resulting score is not a number" => Ok(FrameErrorKind::NotAFloat),
+            "value is not valid JSON" => Ok(FrameErrorKind::NotJson),
+            "value at the path is not a number" => Ok(FrameErrorKind::NotANumber),
+            "no such index" => Ok(FrameErrorKind::NoSuchIndex),
+            "invalid expire time" => Ok(FrameErrorKind::InvalidExpireTime),
             _ => Err(()),
         }
     }
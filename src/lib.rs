@@ -2,7 +2,9 @@ pub mod client;
 pub mod cmd;
 pub mod connection;
 pub mod db;
+pub mod engine;
 pub mod frame;
+pub mod lsm;
 pub mod server;
 
 pub type Error = Box<dyn std::error::Error>;
@@ -35,7 +37,8 @@ pub const DEFAULT_PORT: u16 = 6379;
     - CLI commands integration with clap crate ✅
     - Delete command ✅
     - Implement simple compaction (without segments) including deleted records cleanup ✅
-    - Tests for Db module
+    - Crash-safe compaction via atomic file replacement ✅
+    - Tests for Db module ✅
     - README
 
 
@@ -47,8 +50,9 @@ pub const DEFAULT_PORT: u16 = 6379;
 
     Version 1.2.0
     - Concurrent read-write? -> research needed
-    - Segment file compaction
-    - Scan?
+    - Segment file compaction ✅
+    - Scan ✅
+    - Key expiration ✅
     - More advanced tests
     - Error handling with anyhow?
 
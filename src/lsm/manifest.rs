@@ -0,0 +1,49 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::sstable::TableId;
+
+const MANIFEST_FILENAME: &str = "MANIFEST";
+const MANIFEST_TMP_FILENAME: &str = "MANIFEST.tmp";
+
+/// Tables making up every level. Table files are only considered part of the store
+/// once they are listed here, so that replacing the manifest is the commit point
+/// of both flushes and compactions.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub(crate) struct Manifest {
+    /// Level 0 tables go from the newest to the oldest, while the tables
+    /// of the deeper levels are ordered by their keys.
+    pub(crate) levels: Vec<Vec<TableId>>,
+    /// Greatest version among the records in the tables, so that versions assigned
+    /// after a restart keep growing even if they have run ahead of the clock
+    #[serde(default)]
+    pub(crate) last_version: u64,
+}
+
+impl Manifest {
+    /// Empty manifest is returned for a fresh directory.
+    pub(crate) fn read(dir: &Path) -> Result<Manifest, crate::Error> {
+        match fs::read(dir.join(MANIFEST_FILENAME)) {
+            Ok(buf) => Ok(serde_json::from_slice(&buf)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Manifest::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Replaces the manifest atomically.
+    pub(crate) fn write(&self, dir: &Path) -> Result<(), crate::Error> {
+        let tmp_path = dir.join(MANIFEST_TMP_FILENAME);
+
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&serde_json::to_vec(self)?)?;
+        file.sync_all()?;
+
+        fs::rename(&tmp_path, dir.join(MANIFEST_FILENAME))?;
+        File::open(dir)?.sync_all()?;
+
+        Ok(())
+    }
+}
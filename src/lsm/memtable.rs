@@ -0,0 +1,59 @@
+use std::collections::BTreeMap;
+
+use crate::engine::{KeyRange, Value};
+
+use super::Entry;
+
+/// Sorted buffer of the most recent writes, flushed into a table once it grows
+/// large enough. Deletions are kept as `None` to shadow values in the tables.
+#[derive(Debug, Default)]
+pub(crate) struct Memtable {
+    entries: BTreeMap<String, Option<Value>>,
+    /// Approximate amount of memory taken by keys and values
+    size: usize,
+}
+
+impl Memtable {
+    /// `Some(None)` means the key is deleted, `None` that the memtable knows nothing of it.
+    pub(crate) fn get(&self, key: &str) -> Option<Option<Value>> {
+        self.entries.get(key).cloned()
+    }
+
+    pub(crate) fn insert(&mut self, key: String, value: Option<Value>) {
+        let key_len = key.len();
+        let value_len = value.as_ref().map_or(0, |value| value.bytes.len());
+
+        match self.entries.insert(key, value) {
+            Some(previous) => self.size -= previous.as_ref().map_or(0, |value| value.bytes.len()),
+            None => self.size += key_len,
+        }
+
+        self.size += value_len;
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.size = 0;
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&String, &Option<Value>)> {
+        self.entries.iter()
+    }
+
+    /// Copy of the entries within the range in key order, so it could be read
+    /// without holding the lock. Range is expected to be non-empty.
+    pub(crate) fn snapshot(&self, range: &KeyRange) -> Vec<Entry> {
+        self.entries
+            .range(range.clone())
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,97 @@
+use std::iter::Peekable;
+
+use super::Entry;
+
+pub(crate) type EntryIter = Box<dyn Iterator<Item = Result<Entry, crate::Error>>>;
+
+/// Merges sorted sources into a single sorted stream. Sources are ordered from the
+/// newest to the oldest, and when several of them have the same key, the entry of
+/// the newest one wins while the rest are skipped.
+pub(crate) struct MergeIter {
+    sources: Vec<Peekable<EntryIter>>,
+}
+
+impl MergeIter {
+    pub(crate) fn new(sources: Vec<EntryIter>) -> MergeIter {
+        MergeIter {
+            sources: sources.into_iter().map(Iterator::peekable).collect(),
+        }
+    }
+}
+
+impl Iterator for MergeIter {
+    type Item = Result<Entry, crate::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut newest: Option<(usize, String)> = None;
+
+        for (idx, source) in self.sources.iter_mut().enumerate() {
+            match source.peek() {
+                // Strict comparison keeps the newest source among the equal keys
+                Some(Ok((key, _)))
+                    if newest
+                        .as_ref()
+                        .is_none_or(|(_, newest_key)| key < newest_key) =>
+                {
+                    newest = Some((idx, key.clone()));
+                }
+                Some(Err(_)) => return source.next(),
+                _ => {}
+            }
+        }
+
+        let idx = newest?.0;
+        let entry = self.sources[idx].next()?;
+
+        if let Ok((key, _)) = &entry {
+            for source in self.sources.iter_mut() {
+                while matches!(source.peek(), Some(Ok((other, _))) if other == key) {
+                    source.next();
+                }
+            }
+        }
+
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::Bytes;
+
+    use crate::engine::Value;
+
+    fn value(bytes: &'static str) -> Value {
+        Value::new(Bytes::from(bytes), None)
+    }
+
+    fn source(entries: &[(&str, Option<&'static str>)]) -> EntryIter {
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|(key, bytes)| Ok((key.to_string(), bytes.map(value))))
+            .collect();
+
+        Box::new(entries.into_iter())
+    }
+
+    #[test]
+    fn test_newest_source_wins() -> Result<(), crate::Error> {
+        let newer = source(&[("a", Some("new")), ("c", None)]);
+        let older = source(&[("a", Some("old")), ("b", Some("old")), ("c", Some("old"))]);
+
+        let merged = MergeIter::new(vec![newer, older]).collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(
+            merged,
+            vec![
+                ("a".to_string(), Some(value("new"))),
+                ("b".to_string(), Some(value("old"))),
+                ("c".to_string(), None),
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,797 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use crate::db::{now_millis, Durability, FileRecord};
+use crate::engine::{self, KeyRange, StorageEngine, Update, Value};
+
+mod manifest;
+mod memtable;
+mod merge;
+mod sstable;
+mod wal;
+
+use manifest::Manifest;
+use memtable::Memtable;
+use merge::{EntryIter, MergeIter};
+use sstable::{Table, TableId, TableWriter};
+use wal::Wal;
+
+/// Key along with its value, `None` standing for a deletion
+pub(crate) type Entry = (String, Option<Value>);
+
+/// Level 0 is merged into level 1 once it has this many tables
+const LEVEL0_COMPACTION_TRIGGER: usize = 4;
+
+/// Every level past the first one may hold this many times more than the previous one
+const LEVEL_SIZE_MULTIPLIER: u64 = 10;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Directory holding the write-ahead log, tables and the manifest
+    pub dir: PathBuf,
+    /// Memtable is flushed into a level 0 table once it takes this many bytes
+    pub memtable_size: usize,
+    /// Compaction output is split into tables of about this size
+    pub table_size: u64,
+    /// Total size of level 1 tables after which they are merged into level 2
+    pub level_base_size: u64,
+    pub durability: Durability,
+}
+
+/// Log-structured merge tree. Writes go into the memtable, backed by the write-ahead
+/// log, which is flushed into an immutable sorted table once it's large enough.
+/// Tables are then gradually merged into deeper levels by compaction.
+#[derive(Clone)]
+pub struct LsmEngine {
+    state: Arc<Mutex<State>>,
+    config: Arc<Config>,
+    /// Ensures only a single compaction is running at a time
+    compaction_lock: Arc<Mutex<()>>,
+}
+
+struct State {
+    memtable: Memtable,
+    wal: Wal,
+    /// Level 0 tables go from the newest to the oldest and may overlap, while
+    /// the tables of the deeper levels don't overlap and are ordered by their keys
+    levels: Vec<Vec<Arc<Table>>>,
+    next_table_id: TableId,
+    /// Timestamp of the last logged record, which is also its version
+    last_version: u64,
+}
+
+/// Tables merged by a single compaction, from the newest to the oldest
+struct CompactionTask {
+    target_level: usize,
+    inputs: Vec<Arc<Table>>,
+    /// Deletions only have to be kept while there are older tables below to shadow
+    drop_tombstones: bool,
+    /// Values which expired before the compaction started are turned into deletions
+    now: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            dir: PathBuf::from("store"),
+            memtable_size: 4 * 1024 * 1024,
+            table_size: 2 * 1024 * 1024,
+            level_base_size: 10 * 1024 * 1024,
+            durability: Durability::Interval(Duration::from_secs(1)),
+        }
+    }
+}
+
+impl LsmEngine {
+    pub fn open(config: Config) -> Result<LsmEngine, crate::Error> {
+        let dir = config.dir.as_path();
+
+        fs::create_dir_all(dir)?;
+
+        let manifest = Manifest::read(dir)?;
+        let manifest_version = manifest.last_version;
+
+        let mut levels = vec![];
+        let mut live_tables = HashSet::new();
+
+        for level_tables in manifest.levels {
+            let mut level = vec![];
+
+            for id in level_tables {
+                level.push(Arc::new(Table::open(dir, id)?));
+                live_tables.insert(id);
+            }
+
+            levels.push(level);
+        }
+
+        // Tables missing from the manifest never made it into the store
+        let existing_tables = sstable::list_tables(dir)?;
+
+        for &id in existing_tables.iter() {
+            if !live_tables.contains(&id) {
+                fs::remove_file(sstable::table_path(dir, id))?;
+
+                eprintln!("removed table {} left behind by an interrupted write", id);
+            }
+        }
+
+        let mut memtable = Memtable::default();
+        let wal = Wal::open(dir, &mut memtable)?;
+
+        let last_version = memtable
+            .iter()
+            .filter_map(|(_, value)| value.as_ref().map(|value| value.version))
+            .fold(manifest_version, u64::max);
+
+        let state = State {
+            memtable,
+            wal,
+            levels,
+            next_table_id: existing_tables.last().map_or(1, |id| id + 1),
+            last_version,
+        };
+
+        Ok(LsmEngine {
+            state: Arc::new(Mutex::new(state)),
+            config: Arc::new(config),
+            compaction_lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Logs the write and applies it to the memtable, flushing the memtable if needed.
+    /// Caller is expected to hold the state lock for the whole operation.
+    fn append(&self, state: &mut State, record: FileRecord) -> Result<(), crate::Error> {
+        self.append_batch(state, vec![record])
+    }
+
+    /// Logs the writes with a single append, so that the log is never replayed
+    /// with only a part of them, and applies them to the memtable.
+    fn append_batch(
+        &self,
+        state: &mut State,
+        mut records: Vec<FileRecord>,
+    ) -> Result<(), crate::Error> {
+        let batch_len = records.len();
+
+        for (idx, record) in records.iter_mut().enumerate() {
+            record.timestamp = engine::next_version(state.last_version);
+            record.batch_continues = idx + 1 < batch_len;
+            state.last_version = record.timestamp;
+        }
+
+        state.wal.append(&records)?;
+
+        if self.config.durability == Durability::Always {
+            state.wal.sync()?;
+        }
+
+        for record in records {
+            state
+                .memtable
+                .insert(record.key.clone(), record.into_value());
+        }
+
+        if state.memtable.size() >= self.config.memtable_size {
+            self.flush(state)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the memtable out as the newest level 0 table. Log is only emptied
+    /// once the table is in the manifest, so a crash in between only leads
+    /// to the same records being replayed into the memtable again.
+    fn flush(&self, state: &mut State) -> Result<(), crate::Error> {
+        if state.memtable.is_empty() {
+            return Ok(());
+        }
+
+        let id = state.next_table_id;
+        state.next_table_id += 1;
+
+        let mut writer = TableWriter::create(&self.config.dir, id)?;
+
+        for (key, value) in state.memtable.iter() {
+            writer.add(key, value.as_ref())?;
+        }
+
+        let table = writer.finish(&self.config.dir)?;
+
+        if state.levels.is_empty() {
+            state.levels.push(vec![]);
+        }
+
+        state.levels[0].insert(0, Arc::new(table));
+        self.write_manifest(state)?;
+
+        state.wal.reset()?;
+        state.memtable.clear();
+
+        Ok(())
+    }
+
+    fn write_manifest(&self, state: &State) -> Result<(), crate::Error> {
+        let manifest = Manifest {
+            levels: state
+                .levels
+                .iter()
+                .map(|level| level.iter().map(|table| table.id).collect())
+                .collect(),
+            last_version: state.last_version,
+        };
+
+        manifest.write(&self.config.dir)
+    }
+
+    /// Looks the key up in the tables, from the newest to the oldest. Only a single
+    /// table has to be checked per level past the level 0.
+    fn lookup(levels: &[Vec<Arc<Table>>], key: &str) -> Result<Option<Value>, crate::Error> {
+        for (level_idx, level) in levels.iter().enumerate() {
+            let candidates = if level_idx == 0 {
+                &level[..]
+            } else {
+                let table_idx = level.partition_point(|table| table.last_key.as_str() < key);
+                &level[table_idx..level.len().min(table_idx + 1)]
+            };
+
+            for table in candidates {
+                if let Some(value) = table.get(key)? {
+                    return Ok(value);
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn max_level_size(&self, level_idx: usize) -> u64 {
+        self.config.level_base_size * LEVEL_SIZE_MULTIPLIER.pow(level_idx as u32 - 1)
+    }
+
+    /// Picks the tables to merge next: the whole level 0 once it has too many tables,
+    /// or a single table of a deeper level which has outgrown its size. Tables of the
+    /// next level overlapping with the picked ones are merged along with them.
+    fn pick_compaction(&self) -> Option<CompactionTask> {
+        let state = self.state.lock().unwrap();
+        let levels = &state.levels;
+
+        let (level_idx, mut inputs) =
+            if levels.first().map_or(0, Vec::len) >= LEVEL0_COMPACTION_TRIGGER {
+                (0, levels[0].clone())
+            } else {
+                levels
+                    .iter()
+                    .enumerate()
+                    .skip(1)
+                    .find(|(level_idx, level)| {
+                        let size: u64 = level.iter().map(|table| table.size).sum();
+                        size > self.max_level_size(*level_idx)
+                    })
+                    .map(|(level_idx, level)| (level_idx, vec![level[0].clone()]))?
+            };
+
+        let first_key = inputs.iter().map(|table| &table.first_key).min()?.clone();
+        let last_key = inputs.iter().map(|table| &table.last_key).max()?.clone();
+
+        let target_level = level_idx + 1;
+
+        if let Some(level) = levels.get(target_level) {
+            let overlapping = level
+                .iter()
+                .filter(|table| table.overlaps(&first_key, &last_key));
+
+            inputs.extend(overlapping.cloned());
+        }
+
+        Some(CompactionTask {
+            target_level,
+            inputs,
+            drop_tombstones: levels.iter().skip(target_level + 1).all(Vec::is_empty),
+            now: now_millis(),
+        })
+    }
+
+    /// Merges the input tables into new tables of the target level. Inputs are immutable,
+    /// so the state lock is only taken to swap the tables once the output is written.
+    fn run_compaction(&self, task: CompactionTask) -> Result<(), crate::Error> {
+        let dir = self.config.dir.as_path();
+
+        let sources = task
+            .inputs
+            .iter()
+            .map(|table| Box::new(table.iter()) as EntryIter)
+            .collect();
+
+        let mut outputs = vec![];
+        let mut writer: Option<TableWriter> = None;
+
+        for entry in MergeIter::new(sources) {
+            let (key, mut value) = entry?;
+
+            if value
+                .as_ref()
+                .is_some_and(|value| value.is_expired(task.now))
+            {
+                value = None;
+            }
+
+            if value.is_none() && task.drop_tombstones {
+                continue;
+            }
+
+            let table_writer = match writer.as_mut() {
+                Some(table_writer) => table_writer,
+                None => writer.insert(TableWriter::create(dir, self.next_table_id())?),
+            };
+
+            table_writer.add(&key, value.as_ref())?;
+
+            if table_writer.size() >= self.config.table_size {
+                if let Some(table_writer) = writer.take() {
+                    outputs.push(Arc::new(table_writer.finish(dir)?));
+                }
+            }
+        }
+
+        if let Some(table_writer) = writer {
+            outputs.push(Arc::new(table_writer.finish(dir)?));
+        }
+
+        let input_ids: HashSet<TableId> = task.inputs.iter().map(|table| table.id).collect();
+
+        {
+            let mut state = self.state.lock().unwrap();
+
+            for level in state.levels.iter_mut() {
+                level.retain(|table| !input_ids.contains(&table.id));
+            }
+
+            while state.levels.len() <= task.target_level {
+                state.levels.push(vec![]);
+            }
+
+            let level = &mut state.levels[task.target_level];
+            level.extend(outputs);
+            level.sort_by(|a, b| a.first_key.cmp(&b.first_key));
+
+            self.write_manifest(&state)?;
+        }
+
+        // Readers still holding the inputs keep their handles valid
+        for id in input_ids {
+            fs::remove_file(sstable::table_path(dir, id))?;
+        }
+
+        Ok(())
+    }
+
+    fn next_table_id(&self) -> TableId {
+        let mut state = self.state.lock().unwrap();
+        state.next_table_id += 1;
+
+        state.next_table_id - 1
+    }
+}
+
+impl StorageEngine for LsmEngine {
+    fn get_value(&self, key: &str) -> Result<Option<Value>, crate::Error> {
+        // Tables are searched outside of the lock
+        let levels = {
+            let state = self.state.lock().unwrap();
+
+            if let Some(value) = state.memtable.get(key) {
+                return Ok(value.filter(|value| !value.is_expired(now_millis())));
+            }
+
+            state.levels.clone()
+        };
+
+        let value = LsmEngine::lookup(&levels, key)?;
+
+        Ok(value.filter(|value| !value.is_expired(now_millis())))
+    }
+
+    fn update(
+        &self,
+        key: &str,
+        f: &mut dyn FnMut(Option<&Value>) -> Result<Update, crate::Error>,
+    ) -> Result<(), crate::Error> {
+        let mut state = self.state.lock().unwrap();
+
+        let current = match state.memtable.get(key) {
+            Some(value) => value,
+            None => LsmEngine::lookup(&state.levels, key)?,
+        };
+        let current = current.filter(|value| !value.is_expired(now_millis()));
+
+        let record = match f(current.as_ref())? {
+            Update::Keep => return Ok(()),
+            Update::Set(value) => FileRecord::from_value(key.to_string(), Some(value)),
+            Update::Delete if current.is_none() => return Ok(()),
+            Update::Delete => FileRecord::new(key.to_string(), None, true),
+        };
+
+        self.append(&mut state, record)
+    }
+
+    fn write_batch_if(
+        &self,
+        versions: Vec<(String, Option<u64>)>,
+        batch: Vec<(String, Option<Value>)>,
+    ) -> Result<bool, crate::Error> {
+        let records: Vec<FileRecord> = batch
+            .into_iter()
+            .map(|(key, value)| FileRecord::from_value(key, value))
+            .collect();
+
+        let mut state = self.state.lock().unwrap();
+        let now = now_millis();
+
+        for (key, version) in versions {
+            let current = match state.memtable.get(&key) {
+                Some(value) => value,
+                None => LsmEngine::lookup(&state.levels, &key)?,
+            };
+            let current = current
+                .filter(|value| !value.is_expired(now))
+                .map(|value| value.version);
+
+            if current != version {
+                return Ok(false);
+            }
+        }
+
+        if !records.is_empty() {
+            self.append_batch(&mut state, records)?;
+        }
+
+        Ok(true)
+    }
+
+    fn set(&self, key: String, value: Bytes) -> Result<(), crate::Error> {
+        let mut state = self.state.lock().unwrap();
+
+        self.append(&mut state, FileRecord::new(key, Some(value), false))
+    }
+
+    /// Tables only have forward iterators, so a reverse scan goes through
+    /// the whole range before picking the entries from its end.
+    fn scan(
+        &self,
+        range: KeyRange,
+        reverse: bool,
+        limit: usize,
+    ) -> Result<Vec<(String, Bytes)>, crate::Error> {
+        if engine::is_empty_range(&range) {
+            return Ok(vec![]);
+        }
+
+        let (memtable, levels) = {
+            let state = self.state.lock().unwrap();
+
+            (state.memtable.snapshot(&range), state.levels.clone())
+        };
+
+        let mut sources: Vec<EntryIter> = vec![Box::new(memtable.into_iter().map(Ok))];
+
+        for table in levels.iter().flatten() {
+            if table.overlaps_range(&range) {
+                sources.push(table.iter_range(&range));
+            }
+        }
+
+        let now = now_millis();
+
+        let live_entries = MergeIter::new(sources).filter_map(move |entry| match entry {
+            Ok((key, Some(value))) if !value.is_expired(now) => Some(Ok((key, value.bytes))),
+            Ok(_) => None,
+            Err(err) => Some(Err(err)),
+        });
+
+        if reverse {
+            let mut entries = live_entries.collect::<Result<Vec<_>, _>>()?;
+            entries.reverse();
+            entries.truncate(limit);
+
+            Ok(entries)
+        } else {
+            live_entries.take(limit).collect()
+        }
+    }
+
+    fn compact(&self) -> Result<(), crate::Error> {
+        let _compaction_guard = self.compaction_lock.lock().unwrap();
+
+        while let Some(task) = self.pick_compaction() {
+            self.run_compaction(task)?;
+        }
+
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<(), crate::Error> {
+        self.state.lock().unwrap().wal.sync()
+    }
+
+    fn sync_interval(&self) -> Option<Duration> {
+        match self.config.durability {
+            Durability::Interval(period) => Some(period),
+            Durability::Always | Durability::Never => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::File;
+    use std::io::Write;
+    use std::ops::Bound;
+
+    use crate::engine::{Expected, SetCondition};
+
+    fn setup_config(name: &str) -> Config {
+        let dir = std::env::temp_dir().join(format!("kv_db_lsm_{}", name));
+
+        let _ = fs::remove_dir_all(&dir);
+
+        Config {
+            dir,
+            memtable_size: 256,
+            table_size: 512,
+            level_base_size: 1024,
+            durability: Durability::Never,
+        }
+    }
+
+    fn all_keys() -> KeyRange {
+        (Bound::Unbounded, Bound::Unbounded)
+    }
+
+    fn table_count(engine: &LsmEngine) -> Vec<usize> {
+        let state = engine.state.lock().unwrap();
+
+        state.levels.iter().map(Vec::len).collect()
+    }
+
+    #[test]
+    fn test_flush_and_wal_replay() -> Result<(), crate::Error> {
+        let config = setup_config("wal_replay");
+        let engine = LsmEngine::open(config.clone())?;
+        let field = |value: &str| vec![("field".to_string(), Bytes::from(value.to_string()))];
+
+        engine.hash_set("flushed_hash", field("1"))?;
+
+        for i in 0..40 {
+            engine.set(format!("key_{:02}", i), Bytes::from(i.to_string()))?;
+        }
+
+        assert!(table_count(&engine)[0] > 0);
+        assert!(!engine.state.lock().unwrap().memtable.is_empty());
+
+        assert!(engine.delete("key_00")?);
+        assert!(!engine.delete("key_00")?);
+        engine.hash_set("logged_hash", field("2"))?;
+
+        drop(engine);
+        let engine = LsmEngine::open(config)?;
+
+        assert_eq!(engine.get("key_00")?, None);
+        assert_eq!(engine.get("key_39")?, Some(Bytes::from("39")));
+        assert_eq!(engine.scan(all_keys(), false, usize::MAX)?.len(), 41);
+
+        // Type of the value survives both the tables and the log
+        assert_eq!(
+            engine.hash_get("flushed_hash", "field")?,
+            Some(Bytes::from("1"))
+        );
+        assert_eq!(
+            engine.hash_get("logged_hash", "field")?,
+            Some(Bytes::from("2"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_replay() -> Result<(), crate::Error> {
+        let config = setup_config("batch_replay");
+        let engine = LsmEngine::open(config.clone())?;
+
+        engine.set("first".to_string(), Bytes::from("0"))?;
+        engine.write_batch(vec![
+            (
+                "first".to_string(),
+                Some(Value::new(Bytes::from("1"), None)),
+            ),
+            (
+                "second".to_string(),
+                Some(Value::new(Bytes::from("2"), None)),
+            ),
+            ("first".to_string(), None),
+        ])?;
+
+        assert!(engine.get("first")?.is_none());
+        assert_eq!(engine.get("second")?, Some(Bytes::from("2")));
+
+        drop(engine);
+
+        // Batch cut off by a crash is left out of the replay as a whole
+        let mut record = FileRecord::new("third".to_string(), Some(Bytes::from("3")), false);
+        record.batch_continues = true;
+        let mut wal_file = File::options()
+            .append(true)
+            .open(config.dir.join("wal.log"))?;
+        wal_file.write_all(&record.encode())?;
+        drop(wal_file);
+
+        let engine = LsmEngine::open(config)?;
+
+        assert!(engine.get("first")?.is_none());
+        assert_eq!(engine.get("second")?, Some(Bytes::from("2")));
+        assert!(engine.get("third")?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_leveled_compaction() -> Result<(), crate::Error> {
+        let config = setup_config("leveled_compaction");
+        let engine = LsmEngine::open(config.clone())?;
+
+        for round in 0..5 {
+            for i in 0..100 {
+                let value = Bytes::from(format!("value_{}_{}", i, round));
+                engine.set(format!("key_{:03}", i), value)?;
+            }
+        }
+
+        for i in (0..100).step_by(2) {
+            engine.delete(&format!("key_{:03}", i))?;
+        }
+
+        engine.compact()?;
+
+        let tables = table_count(&engine);
+        assert!(tables[0] < LEVEL0_COMPACTION_TRIGGER);
+        assert!(tables.len() > 2);
+
+        // Deeper levels don't have overlapping tables
+        for level in engine.state.lock().unwrap().levels.iter().skip(1) {
+            assert!(level
+                .windows(2)
+                .all(|pair| pair[0].last_key < pair[1].first_key));
+        }
+
+        drop(engine);
+        let engine = LsmEngine::open(config)?;
+
+        assert_eq!(engine.get("key_000")?, None);
+        assert_eq!(engine.get("key_001")?, Some(Bytes::from("value_1_4")));
+
+        let entries = engine.scan(all_keys(), false, usize::MAX)?;
+        assert_eq!(entries.len(), 50);
+        assert!(entries.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        let range = (
+            Bound::Included("key_010".to_string()),
+            Bound::Excluded("key_020".to_string()),
+        );
+
+        let forward = engine.scan(range.clone(), false, 3)?;
+        let keys: Vec<&str> = forward.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, vec!["key_011", "key_013", "key_015"]);
+
+        let reverse = engine.scan(range, true, 3)?;
+        let keys: Vec<&str> = reverse.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, vec!["key_019", "key_017", "key_015"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expiration() -> Result<(), crate::Error> {
+        let config = setup_config("expiration");
+        let engine = LsmEngine::open(config.clone())?;
+        let now = now_millis();
+
+        for i in 0..100 {
+            let expires_at = (i % 2 == 0).then_some(now + 100);
+            engine.set_with(
+                format!("key_{:03}", i),
+                Bytes::from("value"),
+                expires_at,
+                SetCondition::Always,
+            )?;
+        }
+
+        assert_eq!(engine.key_count()?, 100);
+        assert!(table_count(&engine)[0] > 0);
+
+        std::thread::sleep(Duration::from_millis(150));
+
+        assert_eq!(engine.get("key_000")?, None);
+        assert_eq!(engine.get("key_001")?, Some(Bytes::from("value")));
+        assert_eq!(engine.key_count()?, 50);
+        assert!(engine.set_with(
+            "key_000".to_string(),
+            Bytes::from("new"),
+            None,
+            SetCondition::IfAbsent
+        )?);
+
+        engine.flush(&mut engine.state.lock().unwrap())?;
+        engine.compact()?;
+
+        drop(engine);
+        let engine = LsmEngine::open(config)?;
+
+        assert_eq!(engine.get("key_000")?, Some(Bytes::from("new")));
+        assert_eq!(engine.get("key_002")?, None);
+        assert_eq!(engine.key_count()?, 51);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_versions_are_kept() -> Result<(), crate::Error> {
+        let config = setup_config("versions");
+        let engine = LsmEngine::open(config.clone())?;
+
+        for i in 0..100 {
+            engine.set(format!("key_{:03}", i), Bytes::from("value"))?;
+        }
+
+        let versions: Vec<u64> = (0..100)
+            .map(|i| Ok(engine.get_value(&format!("key_{:03}", i))?.unwrap().version))
+            .collect::<Result<_, crate::Error>>()?;
+
+        assert!(versions.windows(2).all(|pair| pair[0] < pair[1]));
+
+        engine.flush(&mut engine.state.lock().unwrap())?;
+        engine.compact()?;
+
+        drop(engine);
+        let engine = LsmEngine::open(config)?;
+
+        let expected = Expected::Version(versions[0]);
+        assert!(engine.compare_and_swap("key_000".to_string(), expected, Bytes::from("new"))?);
+        assert!(engine.get_value("key_000")?.unwrap().version > versions[99]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_drops_tombstones() -> Result<(), crate::Error> {
+        // Everything fits into level 1, so there is nothing below it to shadow
+        let config = Config {
+            level_base_size: 1024 * 1024,
+            ..setup_config("drop_tombstones")
+        };
+
+        let engine = LsmEngine::open(config)?;
+
+        for i in 0..200 {
+            engine.set(format!("key_{:03}", i), Bytes::from("value"))?;
+        }
+
+        for i in 0..200 {
+            engine.delete(&format!("key_{:03}", i))?;
+        }
+
+        engine.flush(&mut engine.state.lock().unwrap())?;
+        engine.compact()?;
+
+        assert!(engine.scan(all_keys(), false, usize::MAX)?.is_empty());
+        assert!(table_count(&engine).iter().all(|&count| count == 0));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,487 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::ops::{Bound, RangeBounds};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::db::FileRecord;
+use crate::engine::{self, KeyRange, Value};
+
+use super::merge::EntryIter;
+use super::Entry;
+
+/*
+    Table file layout (all integers are big-endian):
+
+    +------------+-----+------------+-------+--------+
+    | data block | ... | data block | index | footer |
+    +------------+-----+------------+-------+--------+
+
+    - data blocks hold records in key order, in the log engine record format
+    - index starts with the first key of the table, followed by an entry per block:
+
+      +--------------+----------+--------+-----+
+      | last_key_len | last_key | offset | len |
+      |     u32      |          |  u64   | u64 |
+      +--------------+----------+--------+-----+
+
+    - footer locates the index and guards it with a checksum:
+
+      +--------------+-----------+-----------+
+      | index_offset | index_len | index_crc |
+      |     u64      |    u64    |    u32    |
+      +--------------+-----------+-----------+
+*/
+
+pub(crate) type TableId = u64;
+
+const TABLE_SUFFIX: &str = "sst";
+
+const FOOTER_LEN: u64 = 20;
+
+/// Blocks are cut once they exceed this size, it's the unit of reading a table.
+const BLOCK_SIZE: usize = 4 * 1024;
+
+/// Immutable sorted table. Only the block index is kept in memory, while the
+/// records are read block by block from the file.
+#[derive(Debug)]
+pub(crate) struct Table {
+    pub(crate) id: TableId,
+    pub(crate) size: u64,
+    pub(crate) first_key: String,
+    pub(crate) last_key: String,
+    file: File,
+    blocks: Vec<BlockHandle>,
+}
+
+#[derive(Debug)]
+struct BlockHandle {
+    last_key: String,
+    offset: u64,
+    len: u64,
+}
+
+/// Writes records into a new table. Records have to be added in key order.
+pub(crate) struct TableWriter {
+    id: TableId,
+    file: BufWriter<File>,
+    offset: u64,
+    block: BytesMut,
+    block_last_key: String,
+    first_key: Option<String>,
+    blocks: Vec<BlockHandle>,
+}
+
+impl Table {
+    pub(crate) fn open(dir: &Path, id: TableId) -> Result<Table, crate::Error> {
+        let file = File::open(table_path(dir, id))?;
+        let size = file.metadata()?.len();
+
+        if size < FOOTER_LEN {
+            return Err(format!("table {} is too short", id).into());
+        }
+
+        let mut footer = vec![0; FOOTER_LEN as usize];
+        file.read_exact_at(&mut footer, size - FOOTER_LEN)?;
+
+        let mut footer = &footer[..];
+        let index_offset = footer.get_u64();
+        let index_len = footer.get_u64();
+        let index_crc = footer.get_u32();
+
+        if index_offset + index_len + FOOTER_LEN != size {
+            return Err(format!("table {} has a malformed footer", id).into());
+        }
+
+        let mut index = vec![0; index_len as usize];
+        file.read_exact_at(&mut index, index_offset)?;
+
+        if crc32fast::hash(&index) != index_crc {
+            return Err(format!("table {} has a corrupted index", id).into());
+        }
+
+        let mut src = &index[..];
+        let first_key = read_key(&mut src)?;
+        let mut blocks = vec![];
+
+        while src.has_remaining() {
+            let last_key = read_key(&mut src)?;
+
+            if src.remaining() < 16 {
+                return Err(format!("table {} has a corrupted index", id).into());
+            }
+
+            blocks.push(BlockHandle {
+                last_key,
+                offset: src.get_u64(),
+                len: src.get_u64(),
+            });
+        }
+
+        let last_key = match blocks.last() {
+            Some(block) => block.last_key.clone(),
+            None => return Err(format!("table {} is empty", id).into()),
+        };
+
+        Ok(Table {
+            id,
+            size,
+            first_key,
+            last_key,
+            file,
+            blocks,
+        })
+    }
+
+    pub(crate) fn covers(&self, key: &str) -> bool {
+        self.first_key.as_str() <= key && key <= self.last_key.as_str()
+    }
+
+    pub(crate) fn overlaps(&self, first_key: &str, last_key: &str) -> bool {
+        self.first_key.as_str() <= last_key && first_key <= self.last_key.as_str()
+    }
+
+    pub(crate) fn overlaps_range(&self, range: &KeyRange) -> bool {
+        !engine::is_past_range(range, &self.first_key)
+            && !matches!(range.start_bound(), Bound::Included(start) if &self.last_key < start)
+            && !matches!(range.start_bound(), Bound::Excluded(start) if &self.last_key <= start)
+    }
+
+    /// `Some(None)` means the key is deleted, `None` that the table knows nothing of it.
+    /// Only the single block which could hold the key is read.
+    pub(crate) fn get(&self, key: &str) -> Result<Option<Option<Value>>, crate::Error> {
+        if !self.covers(key) {
+            return Ok(None);
+        }
+
+        let block_idx = self
+            .blocks
+            .partition_point(|block| block.last_key.as_str() < key);
+
+        for entry in BlockIter::new(self.read_block(block_idx)?) {
+            let (entry_key, value) = entry?;
+
+            if entry_key == key {
+                return Ok(Some(value));
+            }
+
+            if entry_key.as_str() > key {
+                break;
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Iterates over all the records of the table in key order.
+    pub(crate) fn iter(self: &Arc<Table>) -> TableIter {
+        TableIter {
+            table: self.clone(),
+            next_block: 0,
+            block: BlockIter::new(Bytes::new()),
+        }
+    }
+
+    /// Iterates over the records within the range, starting right at the block
+    /// which could hold the first of them.
+    pub(crate) fn iter_range(self: &Arc<Table>, range: &KeyRange) -> EntryIter {
+        let next_block = match range.start_bound() {
+            Bound::Included(start) | Bound::Excluded(start) => {
+                self.blocks.partition_point(|block| &block.last_key < start)
+            }
+            Bound::Unbounded => 0,
+        };
+
+        let entries = TableIter {
+            table: self.clone(),
+            next_block,
+            block: BlockIter::new(Bytes::new()),
+        };
+
+        let range = range.clone();
+
+        let within_range = entries
+            .take_while({
+                let range = range.clone();
+                move |entry| !matches!(entry, Ok((key, _)) if engine::is_past_range(&range, key))
+            })
+            .filter(move |entry| !matches!(entry, Ok((key, _)) if !range.contains(key)));
+
+        Box::new(within_range)
+    }
+
+    fn read_block(&self, idx: usize) -> Result<Bytes, crate::Error> {
+        let handle = &self.blocks[idx];
+        let mut buffer = vec![0; handle.len as usize];
+
+        self.file.read_exact_at(&mut buffer, handle.offset)?;
+
+        Ok(Bytes::from(buffer))
+    }
+}
+
+pub(crate) struct TableIter {
+    table: Arc<Table>,
+    next_block: usize,
+    block: BlockIter,
+}
+
+impl Iterator for TableIter {
+    type Item = Result<Entry, crate::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.block.next() {
+                return Some(entry);
+            }
+
+            if self.next_block == self.table.blocks.len() {
+                return None;
+            }
+
+            match self.table.read_block(self.next_block) {
+                Ok(block) => self.block = BlockIter::new(block),
+                Err(err) => return Some(Err(err)),
+            }
+
+            self.next_block += 1;
+        }
+    }
+}
+
+/// Decodes records of a single block, values are slices of the block.
+struct BlockIter {
+    block: Bytes,
+    pos: usize,
+}
+
+impl BlockIter {
+    fn new(block: Bytes) -> BlockIter {
+        BlockIter { block, pos: 0 }
+    }
+}
+
+impl Iterator for BlockIter {
+    type Item = Result<Entry, crate::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.block.len() {
+            return None;
+        }
+
+        let rest = self.block.slice(self.pos..);
+
+        let decoded =
+            FileRecord::encoded_len(&rest).and_then(|len| Ok((FileRecord::decode(&rest)?, len)));
+
+        match decoded {
+            Ok((record, len)) => {
+                self.pos += len;
+                Some(Ok((record.key.clone(), record.into_value())))
+            }
+            Err(err) => {
+                // Nothing past a corrupted record can be trusted
+                self.pos = self.block.len();
+                Some(Err(err.into()))
+            }
+        }
+    }
+}
+
+impl TableWriter {
+    pub(crate) fn create(dir: &Path, id: TableId) -> Result<TableWriter, crate::Error> {
+        let file = BufWriter::new(File::create(table_path(dir, id))?);
+
+        Ok(TableWriter {
+            id,
+            file,
+            offset: 0,
+            block: BytesMut::new(),
+            block_last_key: String::new(),
+            first_key: None,
+            blocks: vec![],
+        })
+    }
+
+    pub(crate) fn add(&mut self, key: &str, value: Option<&Value>) -> Result<(), crate::Error> {
+        let record = match value {
+            Some(value) => FileRecord::with_timestamp(
+                key.to_string(),
+                Some(value.bytes.clone()),
+                false,
+                value.version,
+            )
+            .expiring_at(value.expires_at)
+            .of_kind(value.kind),
+            None => FileRecord::new(key.to_string(), None, true),
+        };
+
+        self.block.put_slice(&record.encode());
+        self.block_last_key = key.to_string();
+
+        if self.first_key.is_none() {
+            self.first_key = Some(key.to_string());
+        }
+
+        if self.block.len() >= BLOCK_SIZE {
+            self.finish_block()?;
+        }
+
+        Ok(())
+    }
+
+    /// Amount of bytes written so far, used to split the output of compaction.
+    pub(crate) fn size(&self) -> u64 {
+        self.offset + self.block.len() as u64
+    }
+
+    /// Writes out the index and syncs the file, after which the table is ready for reading.
+    pub(crate) fn finish(mut self, dir: &Path) -> Result<Table, crate::Error> {
+        self.finish_block()?;
+
+        let mut index = BytesMut::new();
+        put_key(&mut index, self.first_key.as_deref().unwrap_or_default());
+
+        for block in self.blocks.iter() {
+            put_key(&mut index, &block.last_key);
+            index.put_u64(block.offset);
+            index.put_u64(block.len);
+        }
+
+        let mut footer = BytesMut::with_capacity(FOOTER_LEN as usize);
+        footer.put_u64(self.offset);
+        footer.put_u64(index.len() as u64);
+        footer.put_u32(crc32fast::hash(&index));
+
+        self.file.write_all(&index)?;
+        self.file.write_all(&footer)?;
+
+        let file = self.file.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()?;
+
+        Table::open(dir, self.id)
+    }
+
+    fn finish_block(&mut self) -> Result<(), crate::Error> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+
+        self.file.write_all(&self.block)?;
+
+        self.blocks.push(BlockHandle {
+            last_key: self.block_last_key.clone(),
+            offset: self.offset,
+            len: self.block.len() as u64,
+        });
+
+        self.offset += self.block.len() as u64;
+        self.block.clear();
+
+        Ok(())
+    }
+}
+
+pub(crate) fn table_path(dir: &Path, id: TableId) -> PathBuf {
+    dir.join(format!("{:06}.{}", id, TABLE_SUFFIX))
+}
+
+/// Ids of all table files within the directory, including the ones left behind
+/// by an interrupted flush or compaction.
+pub(crate) fn list_tables(dir: &Path) -> Result<Vec<TableId>, crate::Error> {
+    let mut ids = vec![];
+
+    for entry in fs::read_dir(dir)? {
+        let file_name = entry?.file_name();
+
+        let parsed = file_name
+            .to_str()
+            .and_then(|name| name.split_once('.'))
+            .filter(|(_, suffix)| *suffix == TABLE_SUFFIX)
+            .and_then(|(stem, _)| stem.parse::<TableId>().ok());
+
+        if let Some(id) = parsed {
+            ids.push(id);
+        }
+    }
+
+    ids.sort_unstable();
+
+    Ok(ids)
+}
+
+fn put_key(buf: &mut BytesMut, key: &str) {
+    buf.put_u32(key.len() as u32);
+    buf.put_slice(key.as_bytes());
+}
+
+fn read_key(src: &mut &[u8]) -> Result<String, crate::Error> {
+    if src.remaining() < 4 {
+        return Err("table index ended early".into());
+    }
+
+    let len = src.get_u32() as usize;
+
+    if src.remaining() < len {
+        return Err("table index ended early".into());
+    }
+
+    let key = String::from_utf8(src[..len].to_vec())?;
+    src.advance(len);
+
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_roundtrip() -> Result<(), crate::Error> {
+        let dir = std::env::temp_dir().join("kv_db_table_roundtrip");
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+
+        let mut writer = TableWriter::create(&dir, 1)?;
+
+        // Enough records to span multiple blocks
+        for i in 0..1000 {
+            let key = format!("key_{:04}", i);
+            let expires_at = (i % 7 == 0).then_some(1_700_000_000_000);
+            let value = Value::new(Bytes::from(format!("value_{}", i)), expires_at);
+
+            writer.add(&key, (i % 10 != 0).then_some(&value))?;
+        }
+
+        let table = Arc::new(writer.finish(&dir)?);
+        assert!(table.blocks.len() > 1);
+
+        let value = |key| -> Result<Option<Bytes>, crate::Error> {
+            Ok(table.get(key)?.flatten().map(|value| value.bytes))
+        };
+
+        assert_eq!(value("key_0001")?, Some(Bytes::from("value_1")));
+        assert_eq!(value("key_0999")?, Some(Bytes::from("value_999")));
+        assert_eq!(
+            table.get("key_0007")?.flatten().unwrap().expires_at,
+            Some(1_700_000_000_000)
+        );
+        assert_eq!(table.get("key_0010")?, Some(None));
+        assert_eq!(table.get("key_00015")?, None);
+        assert_eq!(table.get("zzz")?, None);
+
+        let reopened = Table::open(&dir, 1)?;
+        assert_eq!(reopened.first_key, "key_0000");
+        assert_eq!(reopened.last_key, "key_0999");
+
+        let entries = table.iter().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(entries.len(), 1000);
+        assert!(entries.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        Ok(())
+    }
+}
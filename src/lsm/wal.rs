@@ -0,0 +1,100 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Write};
+use std::path::Path;
+
+use bytes::BytesMut;
+
+use crate::db::{DecodeError, FileRecord};
+
+use super::memtable::Memtable;
+
+const WAL_FILENAME: &str = "wal.log";
+
+/// Write-ahead log of the memtable. Records use the same format as the log engine
+/// segments, and the log is emptied once the memtable is flushed into a table.
+#[derive(Debug)]
+pub(crate) struct Wal {
+    file: File,
+    size: u64,
+}
+
+impl Wal {
+    /// Opens the log, replaying its records into the memtable. Unreadable tail left
+    /// by a torn write is cut off, everything before it has been acknowledged intact.
+    /// Batch cut short by the torn write is dropped as a whole.
+    pub(crate) fn open(dir: &Path, memtable: &mut Memtable) -> Result<Wal, crate::Error> {
+        let path = dir.join(WAL_FILENAME);
+
+        let file = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .append(true)
+            .open(&path)?;
+
+        let mut reader = BufReader::new(&file);
+        let mut size = 0;
+
+        loop {
+            let batch = match FileRecord::read_batch_from(&mut reader) {
+                Ok(Some(read)) => read,
+                Ok(None) => break,
+                Err(DecodeError::Io(err)) => return Err(err.into()),
+                Err(err) => {
+                    eprintln!(
+                        "write-ahead log {} has an unreadable record at offset {} ({}); \
+                         truncated",
+                        path.display(),
+                        size,
+                        err
+                    );
+
+                    file.set_len(size)?;
+                    file.sync_all()?;
+                    break;
+                }
+            };
+
+            for (record, len) in batch {
+                memtable.insert(record.key.clone(), record.into_value());
+                size += len;
+            }
+        }
+
+        Ok(Wal { file, size })
+    }
+
+    /// Records are written at once, as they are expected to be already marked
+    /// as a batch if there are several of them.
+    pub(crate) fn append(&mut self, records: &[FileRecord]) -> Result<(), crate::Error> {
+        let mut encoded_batch = BytesMut::new();
+
+        for record in records {
+            encoded_batch.extend_from_slice(&record.encode());
+        }
+
+        if let Err(err) = self.file.write_all(&encoded_batch) {
+            // Partially written record would make all the following ones unreadable
+            self.file.set_len(self.size)?;
+            return Err(err.into());
+        }
+
+        self.size += encoded_batch.len() as u64;
+
+        Ok(())
+    }
+
+    pub(crate) fn sync(&self) -> Result<(), crate::Error> {
+        self.file.sync_data()?;
+
+        Ok(())
+    }
+
+    /// Drops all the records, once they are safely stored in a table.
+    pub(crate) fn reset(&mut self) -> Result<(), crate::Error> {
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
@@ -1,37 +1,60 @@
+use std::collections::HashMap;
 use std::future::Future;
+use std::sync::{Arc, Mutex};
 
 use tokio::net::{TcpListener, TcpStream};
-use tokio::time::{self, Duration};
+use tokio::sync::Notify;
+use tokio::time::{self, Duration, Instant};
 use tokio_util::sync::CancellationToken;
 
-use crate::cmd::Command;
+use crate::cmd::{BLPop, Command};
 use crate::connection::Connection;
-use crate::db::{Db, DbHolder};
+use crate::engine::{StorageEngine, Transaction};
+use crate::frame::{Frame, FrameErrorKind};
+
+/// Times `EXEC` runs the queued commands before giving up on committing them,
+/// as long as they keep conflicting with other writes.
+const MAX_EXEC_ATTEMPTS: usize = 16;
 
 struct Listener {
     listener: TcpListener,
-    db_holder: DbHolder,
+    engine: Arc<dyn StorageEngine>,
+    waiters: Arc<Waiters>,
 }
 
 struct Handler {
     connection: Connection,
-    db: Db,
+    engine: Arc<dyn StorageEngine>,
+    waiters: Arc<Waiters>,
+    /// Commands queued since `MULTI`, `None` outside of a transaction
+    queued: Option<Vec<Command>>,
+    /// Keys watched since `WATCH` along with their versions at that time,
+    /// `None` standing for a missing key
+    watched: Vec<(String, Option<u64>)>,
+}
+
+/// Connections blocked on empty lists, by the keys of the lists. Each of them is
+/// woken through its own `Notify`, which keeps the wakeup if it comes while
+/// the connection is still checking the lists rather than waiting.
+#[derive(Default)]
+struct Waiters {
+    by_key: Mutex<HashMap<String, Vec<Arc<Notify>>>>,
 }
 
-pub async fn run(listener: TcpListener, shutdown: impl Future) {
+pub async fn run(listener: TcpListener, engine: Arc<dyn StorageEngine>, shutdown: impl Future) {
     let mut server = Listener {
         listener,
-        db_holder: DbHolder::new(),
+        engine,
+        waiters: Arc::new(Waiters::default()),
     };
 
-    let compaction_shutdown_token = CancellationToken::new();
+    let background_shutdown_token = CancellationToken::new();
 
-    // NOTE: not sure how read/write is gonna work if compaction will take a while
-    // normally there should be log segmenting, with communication on separate files
-    // for read/write and compaction repsectively
-    let _db_compaction_task = {
-        let db = server.db_holder.db.clone();
-        let shutdown_token = compaction_shutdown_token.clone();
+    // Compaction is running on the blocking pool, so that engines could do it
+    // without holding up the connections, e.g. by merging only closed segments
+    {
+        let engine = server.engine.clone();
+        let shutdown_token = background_shutdown_token.clone();
 
         tokio::spawn(async move {
             let mut interval = time::interval(Duration::from_secs(20));
@@ -39,7 +62,12 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) {
             loop {
                 tokio::select! {
                     _ = interval.tick() => {
-                        if let Err(err) = db.run_compaction() {
+                        let engine = engine.clone();
+                        let compaction = tokio::task::spawn_blocking(move || {
+                            engine.compact().map_err(|err| err.to_string())
+                        });
+
+                        if let Ok(Err(err)) = compaction.await {
                             eprintln!("compaction failed");
                             dbg!(err);
                         }
@@ -51,7 +79,67 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) {
                 }
             }
         });
-    };
+    }
+
+    // Expired keys are invisible right away, sweeping only frees up the memory
+    // held by the ones nobody asks for anymore
+    {
+        let engine = server.engine.clone();
+        let shutdown_token = background_shutdown_token.clone();
+
+        tokio::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(1));
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        let engine = engine.clone();
+                        let sweep = tokio::task::spawn_blocking(move || {
+                            engine.purge_expired().map_err(|err| err.to_string())
+                        });
+
+                        if let Ok(Err(err)) = sweep.await {
+                            eprintln!("expiry sweep failed");
+                            dbg!(err);
+                        }
+                    }
+                    _ = shutdown_token.cancelled() => {
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    // Writes acknowledged before the policy is satisfied get synced periodically,
+    // with a single fsync covering everything appended since the previous one
+    if let Some(period) = server.engine.sync_interval() {
+        let engine = server.engine.clone();
+        let shutdown_token = background_shutdown_token.clone();
+
+        tokio::spawn(async move {
+            let mut interval = time::interval(period);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        let engine = engine.clone();
+                        let sync = tokio::task::spawn_blocking(move || {
+                            engine.sync().map_err(|err| err.to_string())
+                        });
+
+                        if let Ok(Err(err)) = sync.await {
+                            eprintln!("sync failed");
+                            dbg!(err);
+                        }
+                    }
+                    _ = shutdown_token.cancelled() => {
+                        break;
+                    }
+                }
+            }
+        });
+    }
 
     tokio::select! {
         res = server.run() => {
@@ -62,7 +150,12 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) {
         }
         _ = shutdown => {
             println!("shutting down");
-            compaction_shutdown_token.cancel();
+            background_shutdown_token.cancel();
+
+            if let Err(err) = server.engine.sync() {
+                eprintln!("final sync failed");
+                dbg!(err);
+            }
         }
     }
 }
@@ -85,7 +178,10 @@ impl Listener {
 
             let mut handler = Handler {
                 connection: Connection::new(socket),
-                db: self.db_holder.db(),
+                engine: self.engine.clone(),
+                waiters: self.waiters.clone(),
+                queued: None,
+                watched: vec![],
             };
 
             tokio::spawn(async move {
@@ -100,7 +196,7 @@ impl Listener {
     async fn accept(&mut self) -> Result<TcpStream, crate::Error> {
         match self.listener.accept().await {
             Ok((tcp_stream, _)) => Ok(tcp_stream),
-            Err(err) => return Err(err.into()),
+            Err(err) => Err(err.into()),
         }
     }
 }
@@ -118,7 +214,189 @@ impl Handler {
 
             let cmd = Command::from_frame(frame)?;
 
-            cmd.apply(&mut self.connection, &self.db).await?;
+            let response = match cmd {
+                Command::BLPop(cmd) if self.queued.is_none() => self.blpop(cmd).await?,
+                cmd => self.handle(cmd).await?,
+            };
+
+            self.connection.write_frame(&response).await?;
+        }
+    }
+
+    /// Commands are queued instead of being executed while a transaction is in progress.
+    async fn handle(&mut self, cmd: Command) -> Result<Frame, crate::Error> {
+        let in_transaction = self.queued.is_some();
+
+        match cmd {
+            Command::Multi(_) | Command::Watch(_) if in_transaction => {
+                Ok(Frame::Error(FrameErrorKind::InTransaction))
+            }
+            Command::Exec(_) | Command::Discard(_) if !in_transaction => {
+                Ok(Frame::Error(FrameErrorKind::NoTransaction))
+            }
+            Command::Multi(_) => {
+                self.queued = Some(vec![]);
+
+                Ok(Frame::Simple("OK".to_string()))
+            }
+            Command::Watch(cmd) => {
+                for key in cmd.keys {
+                    let version = self.engine.get_value(&key)?.map(|value| value.version);
+                    self.watched.push((key, version));
+                }
+
+                Ok(Frame::Simple("OK".to_string()))
+            }
+            Command::Discard(_) => {
+                self.queued = None;
+                self.watched.clear();
+
+                Ok(Frame::Simple("OK".to_string()))
+            }
+            Command::Exec(_) => {
+                let commands = self.queued.take().unwrap_or_default();
+                let watched = std::mem::take(&mut self.watched);
+
+                self.exec(commands, watched).await
+            }
+            cmd => match self.queued.as_mut() {
+                Some(queued) => {
+                    queued.push(cmd);
+
+                    Ok(Frame::Simple("QUEUED".to_string()))
+                }
+                None => {
+                    let pushed_key = cmd.pushed_key().map(str::to_string);
+                    let response = cmd.execute(&*self.engine)?;
+
+                    if let Some(key) = pushed_key {
+                        self.waiters.wake(&key);
+                    }
+
+                    Ok(response)
+                }
+            },
+        }
+    }
+
+    /// Pops right away if any of the lists has items, otherwise waits for a push
+    /// to one of them until the timeout, replying with null if there was none.
+    /// Nothing is locked while waiting, other connections go on as usual.
+    async fn blpop(&self, cmd: BLPop) -> Result<Frame, crate::Error> {
+        let deadline = cmd.timeout.map(|timeout| Instant::now() + timeout);
+        let waiter = Arc::new(Notify::new());
+
+        // Registered before the first check, so that no push goes unnoticed
+        self.waiters.register(&cmd.keys, &waiter);
+
+        let response = loop {
+            match Command::BLPop(cmd.clone()).execute(&*self.engine) {
+                Ok(Frame::Null) => {}
+                response => break response,
+            }
+
+            // Another connection may have popped the pushed item first, in which
+            // case the lists are checked again and the wait goes on
+            match deadline {
+                Some(deadline) => {
+                    if time::timeout_at(deadline, waiter.notified()).await.is_err() {
+                        break Ok(Frame::Null);
+                    }
+                }
+                None => waiter.notified().await,
+            }
+        };
+
+        self.waiters.deregister(&cmd.keys, &waiter);
+
+        response
+    }
+
+    /// Runs the queued commands on top of a transaction until it commits, replying
+    /// with all of their responses. Commit fails either because a watched key has
+    /// changed, which aborts the transaction with a null reply, or because a key
+    /// read by the commands was written meanwhile, in which case they are run again,
+    /// up to `MAX_EXEC_ATTEMPTS` times before giving up with a null reply as well.
+    async fn exec(
+        &self,
+        commands: Vec<Command>,
+        watched: Vec<(String, Option<u64>)>,
+    ) -> Result<Frame, crate::Error> {
+        for attempt in 0..MAX_EXEC_ATTEMPTS {
+            // Lets the writers the transaction has conflicted with go on
+            if attempt > 0 {
+                tokio::task::yield_now().await;
+            }
+
+            if let Some(response) = self.try_exec(&commands, &watched)? {
+                return Ok(response);
+            }
+        }
+
+        Ok(Frame::Null)
+    }
+
+    /// Single attempt of `exec`, `None` if it has to be retried.
+    fn try_exec(
+        &self,
+        commands: &[Command],
+        watched: &[(String, Option<u64>)],
+    ) -> Result<Option<Frame>, crate::Error> {
+        let transaction = Transaction::new(&*self.engine, watched.to_vec());
+        let mut responses = Vec::with_capacity(commands.len());
+
+        for cmd in commands.iter().cloned() {
+            responses.push(cmd.execute(&transaction)?);
+        }
+
+        if transaction.commit()? {
+            for key in commands.iter().filter_map(Command::pushed_key) {
+                self.waiters.wake(key);
+            }
+
+            return Ok(Some(Frame::Array(responses)));
+        }
+
+        for (key, version) in watched.iter() {
+            if self.engine.get_value(key)?.map(|value| value.version) != *version {
+                return Ok(Some(Frame::Null));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl Waiters {
+    fn register(&self, keys: &[String], waiter: &Arc<Notify>) {
+        let mut by_key = self.by_key.lock().unwrap();
+
+        for key in keys {
+            by_key.entry(key.clone()).or_default().push(waiter.clone());
+        }
+    }
+
+    fn deregister(&self, keys: &[String], waiter: &Arc<Notify>) {
+        let mut by_key = self.by_key.lock().unwrap();
+
+        for key in keys {
+            if let Some(waiters) = by_key.get_mut(key) {
+                waiters.retain(|registered| !Arc::ptr_eq(registered, waiter));
+
+                if waiters.is_empty() {
+                    by_key.remove(key);
+                }
+            }
+        }
+    }
+
+    /// Wakes every connection waiting on the key, as a push of several items
+    /// may be enough for more than one of them.
+    fn wake(&self, key: &str) {
+        let by_key = self.by_key.lock().unwrap();
+
+        for waiter in by_key.get(key).into_iter().flatten() {
+            waiter.notify_one();
         }
     }
 }
@@ -0,0 +1,853 @@
+use std::future;
+use std::net::SocketAddr;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use serde_json::{json, Number};
+use tokio::net::TcpListener;
+
+use kv_db::client::Client;
+use kv_db::cmd::{Get, IncrBy, LRange, MGet, Push, Scan, Set, Ttl};
+use kv_db::db::{Config, Db, Durability, IndexDefinition};
+use kv_db::engine::{Expected, JsonPath, ListEnd, MemoryEngine, SetCondition, StorageEngine};
+use kv_db::frame::Frame;
+use kv_db::server;
+
+/// Starts a server backed by the in-memory engine on a random port.
+async fn start_server() -> SocketAddr {
+    start_server_with(Arc::new(MemoryEngine::new())).await
+}
+
+async fn start_server_with(engine: Arc<dyn StorageEngine>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(server::run(listener, engine, future::pending::<()>()));
+
+    addr
+}
+
+#[tokio::test]
+async fn ping() {
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    assert_eq!(client.ping().await.unwrap(), "PONG");
+}
+
+#[tokio::test]
+async fn set_get_delete() {
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    assert_eq!(client.get("key").await.unwrap(), None);
+
+    client.set("key", Bytes::from("value")).await.unwrap();
+    assert_eq!(client.get("key").await.unwrap(), Some(Bytes::from("value")));
+
+    assert_eq!(client.delete("key").await.unwrap(), "OK");
+    assert_eq!(client.get("key").await.unwrap(), None);
+    assert_eq!(client.delete("key").await.unwrap(), "Error: not found");
+}
+
+#[tokio::test]
+async fn binary_values() {
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    let value = Bytes::from_static(b"\x00\r\n\xff");
+
+    client.set("key", value.clone()).await.unwrap();
+    assert_eq!(client.get("key").await.unwrap(), Some(value));
+}
+
+#[tokio::test]
+async fn connections_share_engine() {
+    let addr = start_server().await;
+    let mut writer = Client::connect(addr).await.unwrap();
+    let mut reader = Client::connect(addr).await.unwrap();
+
+    writer.set("key", Bytes::from("value")).await.unwrap();
+    assert_eq!(reader.get("key").await.unwrap(), Some(Bytes::from("value")));
+}
+
+#[tokio::test]
+async fn scan_pages() {
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    for i in 0..25 {
+        let key = format!("{}:{:02}", if i % 2 == 0 { "even" } else { "odd" }, i);
+        client.set(&key, Bytes::from("value")).await.unwrap();
+    }
+
+    let mut cursor = Scan::START_CURSOR.to_string();
+    let mut pages = 0;
+    let mut keys = vec![];
+
+    loop {
+        let (next_cursor, page) = client.scan(&cursor, Some("even:*"), Some(5)).await.unwrap();
+
+        pages += 1;
+        keys.extend(page);
+
+        if next_cursor == Scan::START_CURSOR {
+            break;
+        }
+
+        cursor = next_cursor;
+    }
+
+    // Last full page can't tell the iteration is over, so it takes an empty one
+    assert_eq!(pages, 6);
+    assert_eq!(keys.len(), 13);
+    assert!(keys.iter().all(|key| key.starts_with("even:")));
+    assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[tokio::test]
+async fn keyspace_introspection() {
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    assert_eq!(client.dbsize().await.unwrap(), 0);
+
+    for key in ["user:1", "user:2", "session:1"] {
+        client.set(key, Bytes::from("value")).await.unwrap();
+    }
+
+    assert_eq!(client.dbsize().await.unwrap(), 3);
+    assert_eq!(
+        client.keys("user:*").await.unwrap(),
+        vec!["user:1", "user:2"]
+    );
+    assert_eq!(
+        client.keys("*:1").await.unwrap(),
+        vec!["session:1", "user:1"]
+    );
+    assert!(client.keys("nothing*").await.unwrap().is_empty());
+
+    let exists = client.exists(&["user:1", "user:3", "user:1"]).await;
+    assert_eq!(exists.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn key_expiration() {
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    let value = Bytes::from("value");
+    let expire = Some(Duration::from_millis(100));
+
+    let set = client.set_with("temp", value.clone(), expire, SetCondition::Always);
+    assert_eq!(set.await.unwrap().as_deref(), Some("OK"));
+
+    let set = client.set_with("temp", value.clone(), None, SetCondition::IfAbsent);
+    assert_eq!(set.await.unwrap(), None);
+
+    let set = client.set_with("missing", value.clone(), None, SetCondition::IfPresent);
+    assert_eq!(set.await.unwrap(), None);
+
+    assert_eq!(client.ttl("temp").await.unwrap(), 0);
+
+    tokio::time::sleep(Duration::from_millis(150)).await;
+
+    assert_eq!(client.get("temp").await.unwrap(), None);
+    assert_eq!(client.ttl("temp").await.unwrap(), Ttl::MISSING);
+
+    client.set("kept", value).await.unwrap();
+    assert_eq!(client.ttl("kept").await.unwrap(), Ttl::PERSISTENT);
+
+    assert!(client.expire("kept", 100).await.unwrap());
+    assert_eq!(client.ttl("kept").await.unwrap(), 100);
+    assert!(!client.expire("missing", 100).await.unwrap());
+
+    assert!(client.persist("kept").await.unwrap());
+    assert!(!client.persist("kept").await.unwrap());
+    assert_eq!(client.ttl("kept").await.unwrap(), Ttl::PERSISTENT);
+
+    // Expire time past what fits into a timestamp is rejected, the key is left as it was
+    assert!(client.expire("kept", u64::MAX).await.is_err());
+    assert_eq!(client.ttl("kept").await.unwrap(), Ttl::PERSISTENT);
+
+    let expire = Some(Duration::from_millis(u64::MAX));
+    let set = client.set_with("kept", Bytes::from("other"), expire, SetCondition::Always);
+    assert!(set.await.is_err());
+    assert_eq!(
+        client.get("kept").await.unwrap(),
+        Some(Bytes::from("value"))
+    );
+}
+
+#[tokio::test]
+async fn conditional_writes() {
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    assert!(!client.setxx("key", Bytes::from("1")).await.unwrap());
+    assert!(client.setnx("key", Bytes::from("1")).await.unwrap());
+    assert!(!client.setnx("key", Bytes::from("2")).await.unwrap());
+    assert!(client.setxx("key", Bytes::from("2")).await.unwrap());
+
+    let previous = client.getset("key", Bytes::from("3")).await.unwrap();
+    assert_eq!(previous, Some(Bytes::from("2")));
+    assert_eq!(client.getset("new", Bytes::from("1")).await.unwrap(), None);
+
+    let version = client.version("key").await.unwrap().unwrap();
+    assert_eq!(client.version("missing").await.unwrap(), None);
+
+    let expected = Expected::Version(version);
+    assert!(client
+        .cas("key", Bytes::from("4"), expected.clone())
+        .await
+        .unwrap());
+    assert!(!client.cas("key", Bytes::from("5"), expected).await.unwrap());
+
+    let expected = Expected::Value(Bytes::from("4"));
+    assert!(client.cas("key", Bytes::from("5"), expected).await.unwrap());
+
+    assert_eq!(client.get("key").await.unwrap(), Some(Bytes::from("5")));
+}
+
+#[tokio::test]
+async fn counters() {
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    assert_eq!(client.incr("counter").await.unwrap(), 1);
+    assert_eq!(client.incr_by("counter", 10).await.unwrap(), 11);
+    assert_eq!(client.incr_by("counter", -20).await.unwrap(), -9);
+    assert_eq!(client.decr("counter").await.unwrap(), -10);
+    assert_eq!(
+        client.get("counter").await.unwrap(),
+        Some(Bytes::from("-10"))
+    );
+
+    client.set("text", Bytes::from("abc")).await.unwrap();
+    assert!(client.incr("text").await.is_err());
+
+    client
+        .set("max", Bytes::from(i64::MAX.to_string()))
+        .await
+        .unwrap();
+    assert!(client.incr("max").await.is_err());
+
+    // Connection is still usable after an error reply
+    assert_eq!(client.get("text").await.unwrap(), Some(Bytes::from("abc")));
+
+    // Increments from concurrent connections are never lost
+    let tasks: Vec<_> = (0..4)
+        .map(|_| {
+            tokio::spawn(async move {
+                let mut client = Client::connect(addr).await.unwrap();
+
+                for _ in 0..50 {
+                    client.incr("shared").await.unwrap();
+                }
+            })
+        })
+        .collect();
+
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    assert_eq!(
+        client.get("shared").await.unwrap(),
+        Some(Bytes::from("200"))
+    );
+}
+
+#[tokio::test]
+async fn batch_commands() {
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    client
+        .set_with(
+            "first",
+            Bytes::from("0"),
+            Some(Duration::from_secs(100)),
+            SetCondition::Always,
+        )
+        .await
+        .unwrap();
+
+    let mset_res = client
+        .mset(&[
+            ("first", Bytes::from("1")),
+            ("second", Bytes::from("2")),
+            ("third", Bytes::from("3")),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(mset_res, "OK");
+
+    assert_eq!(
+        client
+            .mget(&["first", "missing", "third", "first"])
+            .await
+            .unwrap(),
+        vec![
+            Some(Bytes::from("1")),
+            None,
+            Some(Bytes::from("3")),
+            Some(Bytes::from("1")),
+        ]
+    );
+
+    // Values set by MSET don't expire, just like with SET
+    assert_eq!(client.ttl("first").await.unwrap(), Ttl::PERSISTENT);
+}
+
+#[tokio::test]
+async fn hashes() {
+    let dir = std::env::temp_dir().join("kv_db_server_hashes");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let db = Db::new(Config {
+        dir,
+        durability: Durability::Never,
+        ..Config::default()
+    })
+    .unwrap();
+
+    let addr = start_server_with(Arc::new(db)).await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    let hset_res = client
+        .hset(
+            "user",
+            &[("name", Bytes::from("Ada")), ("lang", Bytes::from("en"))],
+        )
+        .await
+        .unwrap();
+    assert_eq!(hset_res, 2);
+
+    let hset_res = client
+        .hset(
+            "user",
+            &[("lang", Bytes::from("fr")), ("age", Bytes::from("36"))],
+        )
+        .await
+        .unwrap();
+    assert_eq!(hset_res, 1);
+
+    assert_eq!(
+        client.hget("user", "lang").await.unwrap(),
+        Some(Bytes::from("fr"))
+    );
+    assert_eq!(client.hget("user", "missing").await.unwrap(), None);
+    assert_eq!(client.hget("missing", "lang").await.unwrap(), None);
+
+    assert_eq!(client.hdel("user", &["name", "missing"]).await.unwrap(), 1);
+    assert_eq!(
+        client.hgetall("user").await.unwrap(),
+        vec![
+            ("age".to_string(), Bytes::from("36")),
+            ("lang".to_string(), Bytes::from("fr")),
+        ]
+    );
+    assert!(client.hgetall("missing").await.unwrap().is_empty());
+
+    // Hash is a key like any other, but it has no string value
+    assert_eq!(client.exists(&["user"]).await.unwrap(), 1);
+    assert!(client.expire("user", 100).await.unwrap());
+    assert_eq!(
+        client.hget("user", "age").await.unwrap(),
+        Some(Bytes::from("36"))
+    );
+    assert_eq!(client.mget(&["user"]).await.unwrap(), vec![None]);
+
+    assert_eq!(client.hdel("user", &["age", "lang"]).await.unwrap(), 2);
+    assert_eq!(client.exists(&["user"]).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn lists() {
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    let jobs = |jobs: &[&'static str]| -> Vec<Bytes> {
+        jobs.iter().map(|job| Bytes::from(*job)).collect()
+    };
+
+    assert_eq!(client.lpush("queue", &jobs(&["b", "a"])).await.unwrap(), 2);
+    assert_eq!(client.rpush("queue", &jobs(&["c"])).await.unwrap(), 3);
+    assert_eq!(client.llen("queue").await.unwrap(), 3);
+    assert_eq!(
+        client.lrange("queue", 0, -1).await.unwrap(),
+        jobs(&["a", "b", "c"])
+    );
+    assert_eq!(
+        client.lrange("queue", -2, 5).await.unwrap(),
+        jobs(&["b", "c"])
+    );
+
+    assert_eq!(client.lpop("queue").await.unwrap(), Some(Bytes::from("a")));
+    assert_eq!(client.rpop("queue").await.unwrap(), Some(Bytes::from("c")));
+    assert_eq!(client.rpop("queue").await.unwrap(), Some(Bytes::from("b")));
+    assert_eq!(client.lpop("queue").await.unwrap(), None);
+    assert_eq!(client.exists(&["queue"]).await.unwrap(), 0);
+
+    // Items already there are popped without waiting
+    client.rpush("other", &jobs(&["x"])).await.unwrap();
+    assert_eq!(
+        client
+            .blpop(&["queue", "other"], Some(Duration::from_secs(5)))
+            .await
+            .unwrap(),
+        Some(("other".to_string(), Bytes::from("x")))
+    );
+    assert_eq!(
+        client
+            .blpop(&["queue"], Some(Duration::from_millis(50)))
+            .await
+            .unwrap(),
+        None
+    );
+}
+
+#[tokio::test]
+async fn sets_and_sorted_sets() {
+    let dir = std::env::temp_dir().join("kv_db_server_sets");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let db = Db::new(Config {
+        dir,
+        durability: Durability::Never,
+        ..Config::default()
+    })
+    .unwrap();
+
+    let addr = start_server_with(Arc::new(db)).await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    let tags = |tags: &[&'static str]| -> Vec<Bytes> {
+        tags.iter().map(|tag| Bytes::from(*tag)).collect()
+    };
+
+    assert_eq!(
+        client
+            .sadd("post:1", &tags(&["rust", "db", "rust"]))
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(
+        client.sadd("post:2", &tags(&["db", "go"])).await.unwrap(),
+        2
+    );
+    assert_eq!(client.srem("post:2", &tags(&["go", "c"])).await.unwrap(), 1);
+    assert_eq!(
+        client.smembers("post:1").await.unwrap(),
+        tags(&["db", "rust"])
+    );
+    assert!(client
+        .sismember("post:1", Bytes::from("rust"))
+        .await
+        .unwrap());
+    assert!(!client
+        .sismember("post:2", Bytes::from("rust"))
+        .await
+        .unwrap());
+    assert_eq!(
+        client.sinter(&["post:1", "post:2"]).await.unwrap(),
+        tags(&["db"])
+    );
+    assert!(client
+        .sinter(&["post:1", "missing"])
+        .await
+        .unwrap()
+        .is_empty());
+
+    let scores = [(10.0, Bytes::from("ada")), (7.5, Bytes::from("bob"))];
+    assert_eq!(client.zadd("board", &scores).await.unwrap(), 2);
+    assert_eq!(
+        client
+            .zincrby("board", 5.0, Bytes::from("bob"))
+            .await
+            .unwrap(),
+        12.5
+    );
+    assert_eq!(
+        client.zrange("board", 0, -1).await.unwrap(),
+        vec![(Bytes::from("ada"), 10.0), (Bytes::from("bob"), 12.5)]
+    );
+    assert_eq!(
+        client
+            .zrangebyscore("board", Bound::Excluded(10.0), Bound::Unbounded)
+            .await
+            .unwrap(),
+        vec![(Bytes::from("bob"), 12.5)]
+    );
+    assert_eq!(
+        client.zrank("board", Bytes::from("bob")).await.unwrap(),
+        Some(1)
+    );
+    assert_eq!(
+        client.zrank("board", Bytes::from("missing")).await.unwrap(),
+        None
+    );
+
+    // Commands for another type are refused, and the connection goes on
+    let err = client.get("post:1").await.unwrap_err();
+    assert!(err.to_string().contains("WRONGTYPE"));
+
+    let err = client.zrange("post:1", 0, -1).await.unwrap_err();
+    assert!(err.to_string().contains("WRONGTYPE"));
+
+    assert_eq!(client.srem("post:2", &tags(&["db"])).await.unwrap(), 1);
+    assert_eq!(client.exists(&["post:1", "post:2"]).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn json_documents() {
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    // Documents written as plain strings can be patched in place
+    client
+        .set("order:1", Bytes::from(r#"{"user":"ada","total":10}"#))
+        .await
+        .unwrap();
+
+    assert!(client
+        .json_set("order:1", "$.items", json!([{"sku": "a", "qty": 1}]))
+        .await
+        .unwrap());
+    assert!(!client
+        .json_set("order:1", "$.shipping.city", json!("Kyiv"))
+        .await
+        .unwrap());
+    assert!(!client.json_set("missing", "$.a", json!(1)).await.unwrap());
+
+    assert_eq!(
+        client
+            .json_numincrby("order:1", "$.items[0].qty", Number::from(2))
+            .await
+            .unwrap(),
+        Some(Number::from(3))
+    );
+    assert_eq!(
+        client
+            .json_numincrby("order:1", "$.total", Number::from_f64(0.5).unwrap())
+            .await
+            .unwrap(),
+        Some(Number::from_f64(10.5).unwrap())
+    );
+
+    assert_eq!(
+        client.json_get("order:1", "$.items[0]").await.unwrap(),
+        Some(json!({"sku": "a", "qty": 3}))
+    );
+    assert_eq!(
+        client.json_get("order:1", "user").await.unwrap(),
+        Some(json!("ada"))
+    );
+    assert_eq!(client.json_get("order:1", "$.missing").await.unwrap(), None);
+
+    let err = client
+        .json_numincrby("order:1", "$.user", Number::from(1))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("not a number"));
+
+    client.set("text", Bytes::from("plain")).await.unwrap();
+    let err = client.json_get("text", "$").await.unwrap_err();
+    assert!(err.to_string().contains("not valid JSON"));
+
+    assert_eq!(client.json_del("order:1", "$.items").await.unwrap(), 1);
+    assert_eq!(
+        client.get("order:1").await.unwrap(),
+        Some(Bytes::from(r#"{"total":10.5,"user":"ada"}"#))
+    );
+    assert_eq!(client.json_del("order:1", "$").await.unwrap(), 1);
+    assert_eq!(client.exists(&["order:1"]).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn blpop_waits_for_push() {
+    let addr = start_server().await;
+    let mut pusher = Client::connect(addr).await.unwrap();
+
+    let mut waiting = vec![];
+
+    for _ in 0..2 {
+        let mut worker = Client::connect(addr).await.unwrap();
+
+        waiting.push(tokio::spawn(async move {
+            worker
+                .blpop(&["queue"], Some(Duration::from_secs(5)))
+                .await
+                .unwrap()
+        }));
+    }
+
+    // Waiting connections don't hold up the rest
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(pusher.llen("queue").await.unwrap(), 0);
+
+    pusher
+        .rpush("queue", &[Bytes::from("a"), Bytes::from("b")])
+        .await
+        .unwrap();
+
+    let mut popped = vec![];
+
+    for worker in waiting {
+        let (key, item) = worker.await.unwrap().unwrap();
+        assert_eq!(key, "queue");
+        popped.push(item);
+    }
+
+    popped.sort();
+    assert_eq!(popped, vec![Bytes::from("a"), Bytes::from("b")]);
+    assert_eq!(pusher.llen("queue").await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn transactions() {
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    client.set("balance", Bytes::from("100")).await.unwrap();
+
+    client.multi().await.unwrap();
+    client
+        .queue(IncrBy::new("balance", -30).into_frame())
+        .await
+        .unwrap();
+    client
+        .queue(Set::new("last_payment", Bytes::from("30")).into_frame())
+        .await
+        .unwrap();
+    client
+        .queue(Get::new("balance").into_frame())
+        .await
+        .unwrap();
+
+    // Queued commands aren't visible to others until EXEC
+    let mut other = Client::connect(addr).await.unwrap();
+    assert_eq!(other.get("last_payment").await.unwrap(), None);
+
+    let responses = client.exec().await.unwrap().unwrap();
+    assert_eq!(responses.len(), 3);
+    assert!(matches!(responses[0], Frame::Integer(70)));
+    assert!(matches!(&responses[1], Frame::Simple(ok) if ok == "OK"));
+    assert!(matches!(&responses[2], Frame::Bulk(bytes) if bytes == "70"));
+
+    assert_eq!(
+        other.get("last_payment").await.unwrap(),
+        Some(Bytes::from("30"))
+    );
+
+    // Nothing is applied once a watched key has changed
+    client.watch(&["balance"]).await.unwrap();
+    client.multi().await.unwrap();
+    client
+        .queue(Set::new("balance", Bytes::from("0")).into_frame())
+        .await
+        .unwrap();
+
+    other.incr("balance").await.unwrap();
+
+    assert!(client.exec().await.unwrap().is_none());
+    assert_eq!(
+        client.get("balance").await.unwrap(),
+        Some(Bytes::from("71"))
+    );
+
+    // Watched keys are only checked by the transaction right after WATCH
+    client.watch(&["balance"]).await.unwrap();
+    client.multi().await.unwrap();
+    client
+        .queue(Set::new("balance", Bytes::from("0")).into_frame())
+        .await
+        .unwrap();
+    assert_eq!(client.discard().await.unwrap(), "OK");
+
+    other.incr("balance").await.unwrap();
+
+    client.multi().await.unwrap();
+    client
+        .queue(Get::new("balance").into_frame())
+        .await
+        .unwrap();
+    assert!(client.exec().await.unwrap().is_some());
+
+    // Array replies of the queued commands are nested within the reply of EXEC
+    client.multi().await.unwrap();
+    client
+        .queue(
+            Push::new(
+                "jobs",
+                vec![Bytes::from("a"), Bytes::from("b")],
+                ListEnd::Right,
+            )
+            .into_frame(),
+        )
+        .await
+        .unwrap();
+    client
+        .queue(LRange::new("jobs", 0, -1).into_frame())
+        .await
+        .unwrap();
+    client
+        .queue(MGet::new(vec!["balance".to_string(), "missing".to_string()]).into_frame())
+        .await
+        .unwrap();
+
+    let responses = client.exec().await.unwrap().unwrap();
+    assert_eq!(responses.len(), 3);
+    assert!(matches!(responses[0], Frame::Integer(2)));
+    assert!(matches!(&responses[1], Frame::Array(items) if items.len() == 2));
+    assert!(matches!(
+        &responses[2],
+        Frame::Array(values) if matches!(values[..], [Frame::Bulk(_), Frame::Null])
+    ));
+    assert_eq!(
+        client.get("balance").await.unwrap(),
+        Some(Bytes::from("72"))
+    );
+
+    assert!(client.exec().await.is_err());
+    assert!(client.discard().await.is_err());
+}
+
+#[tokio::test]
+async fn point_in_time_reads() {
+    let dir = std::env::temp_dir().join("kv_db_server_point_in_time");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let db = Db::new(Config {
+        dir,
+        durability: Durability::Never,
+        ..Config::default()
+    })
+    .unwrap();
+
+    let addr = start_server_with(Arc::new(db)).await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    client.set("a", Bytes::from("1")).await.unwrap();
+    client.set("b", Bytes::from("1")).await.unwrap();
+    let snapshot = client.version("b").await.unwrap().unwrap();
+
+    client.set("a", Bytes::from("2")).await.unwrap();
+    client.delete("b").await.unwrap();
+    client.set("c", Bytes::from("2")).await.unwrap();
+
+    assert_eq!(
+        client.get_at("a", snapshot).await.unwrap(),
+        Some(Bytes::from("1"))
+    );
+    assert_eq!(client.get("a").await.unwrap(), Some(Bytes::from("2")));
+    assert_eq!(client.get_at("c", snapshot).await.unwrap(), None);
+
+    // Pages scanned as of the same point in time add up to the snapshot
+    let (cursor, first_page) = client
+        .scan_at(Scan::START_CURSOR, None, Some(1), Some(snapshot))
+        .await
+        .unwrap();
+    client.delete("a").await.unwrap();
+    let (_, second_page) = client
+        .scan_at(&cursor, None, Some(1), Some(snapshot))
+        .await
+        .unwrap();
+
+    assert_eq!(first_page, vec!["a".to_string()]);
+    assert_eq!(second_page, vec!["b".to_string()]);
+
+    // There is no history from before the store was opened
+    assert!(client.get_at("a", 1).await.is_err());
+
+    // In-memory engine keeps no history at all
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    assert!(client.get_at("a", snapshot).await.is_err());
+}
+
+#[tokio::test]
+async fn find_by_secondary_index() {
+    let dir = std::env::temp_dir().join("kv_db_server_find");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let db = Db::new(Config {
+        dir,
+        durability: Durability::Never,
+        indexes: vec![IndexDefinition {
+            name: "by_user".to_string(),
+            pattern: "order:*".to_string(),
+            field: JsonPath::parse("$.user_id").unwrap(),
+        }],
+        ..Config::default()
+    })
+    .unwrap();
+
+    let addr = start_server_with(Arc::new(db)).await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    client
+        .json_set("order:1", "$", json!({"user_id": "ada", "total": 10}))
+        .await
+        .unwrap();
+    client
+        .json_set("order:2", "$", json!({"user_id": "bob", "total": 5}))
+        .await
+        .unwrap();
+    client
+        .set("order:3", Bytes::from(r#"{"user_id":"ada"}"#))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        client.find("by_user", json!("ada")).await.unwrap(),
+        vec!["order:1".to_string(), "order:3".to_string()]
+    );
+
+    client
+        .json_set("order:2", "$.user_id", json!("ada"))
+        .await
+        .unwrap();
+    client.delete("order:1").await.unwrap();
+
+    assert_eq!(
+        client.find("by_user", json!("ada")).await.unwrap(),
+        vec!["order:2".to_string(), "order:3".to_string()]
+    );
+    assert!(client
+        .find("by_user", json!("bob"))
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(client.find("by_total", json!(5)).await.is_err());
+
+    // Number and string of the same text are looked up separately
+    client
+        .json_set("order:4", "$", json!({"user_id": 7}))
+        .await
+        .unwrap();
+    client
+        .json_set("order:5", "$", json!({"user_id": "7"}))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        client.find("by_user", json!(7)).await.unwrap(),
+        vec!["order:4".to_string()]
+    );
+    assert_eq!(
+        client.find("by_user", json!("7")).await.unwrap(),
+        vec!["order:5".to_string()]
+    );
+
+    // In-memory engine keeps no secondary indexes
+    let addr = start_server().await;
+    let mut client = Client::connect(addr).await.unwrap();
+
+    assert!(client.find("by_user", json!("ada")).await.is_err());
+}